@@ -0,0 +1,239 @@
+//! Writing audio to files.
+
+use crate::sound::SAMPLE_RATE;
+use std::convert::TryFrom;
+use std::io::{self, Seek, SeekFrom, Write};
+
+mod resampler;
+pub use resampler::Quality;
+use resampler::Resampler;
+
+/// The container audio is recorded in.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AudioFormat {
+    /// A 16-bit stereo WAV file
+    Wav,
+
+    /// Headerless 16-bit little endian stereo samples
+    Raw,
+}
+
+/// Records the audio from the emulator to a file as it runs, such as on a machine without a
+/// sound device. Pass it the output of `Snes::audio_samples` after every frame, then finish it to
+/// complete the file.
+pub struct AudioRecorder<W: Write + Seek> {
+    writer: W,
+    format: AudioFormat,
+    sample_rate: u32,
+    resampler: Option<Resampler>,
+
+    /// Bytes of samples written
+    data_size: u64,
+
+    buffer: Vec<i16>,
+}
+
+impl<W: Write + Seek> AudioRecorder<W> {
+    /// Record at the DSP's own rate, `SAMPLE_RATE`.
+    pub fn new(writer: W, format: AudioFormat) -> io::Result<AudioRecorder<W>> {
+        AudioRecorder::create(writer, format, SAMPLE_RATE, None)
+    }
+
+    /// Record at another rate, such as 44.1 or 48 kHz, resampling with the given quality. The
+    /// rate must be above zero.
+    pub fn with_resampler(
+        writer: W,
+        format: AudioFormat,
+        sample_rate: u32,
+        quality: Quality,
+    ) -> io::Result<AudioRecorder<W>> {
+        let resampler = Resampler::new(SAMPLE_RATE, sample_rate, quality)?;
+        AudioRecorder::create(writer, format, sample_rate, Some(resampler))
+    }
+
+    fn create(
+        mut writer: W,
+        format: AudioFormat,
+        sample_rate: u32,
+        resampler: Option<Resampler>,
+    ) -> io::Result<AudioRecorder<W>> {
+        // The sizes in the header are filled in when the recording is finished
+        if format == AudioFormat::Wav {
+            writer.write_all(&wav_header(sample_rate, 0))?;
+        }
+
+        Ok(AudioRecorder {
+            writer,
+            format,
+            sample_rate,
+            resampler,
+            data_size: 0,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// The rate of the recorded samples.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Record interleaved left and right samples at `SAMPLE_RATE`.
+    pub fn write(&mut self, samples: &[i16]) -> io::Result<()> {
+        match &mut self.resampler {
+            Some(resampler) => {
+                let mut buffer = std::mem::take(&mut self.buffer);
+                buffer.clear();
+                resampler.process(samples, &mut buffer);
+                self.write_samples(&buffer)?;
+                self.buffer = buffer;
+                Ok(())
+            }
+            None => self.write_samples(samples),
+        }
+    }
+
+    fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        let bytes: Vec<u8> = samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        let data_size = self.data_size + bytes.len() as u64;
+        if self.format == AudioFormat::Wav && data_size > u64::from(MAX_WAV_DATA) {
+            return Err(too_long());
+        }
+
+        self.writer.write_all(&bytes)?;
+        self.data_size = data_size;
+        Ok(())
+    }
+
+    /// Write out what the resampler holds back and complete the header, returning the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(resampler) = &mut self.resampler {
+            let mut tail = Vec::new();
+            resampler.flush(&mut tail);
+            self.write_samples(&tail)?;
+        }
+
+        if self.format == AudioFormat::Wav {
+            let end = self.writer.stream_position()?;
+            self.writer.seek(SeekFrom::Start(0))?;
+            self.writer
+                .write_all(&wav_header(self.sample_rate, self.data_size as u32))?;
+            self.writer.seek(SeekFrom::Start(end))?;
+        }
+
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Write interleaved 16-bit stereo samples as a WAV file.
+pub fn write_wav<W: Write>(mut writer: W, sample_rate: u32, samples: &[i16]) -> io::Result<()> {
+    let data_size = u32::try_from(2 * samples.len())
+        .ok()
+        .filter(|&size| size <= MAX_WAV_DATA)
+        .ok_or_else(too_long)?;
+    writer.write_all(&wav_header(sample_rate, data_size))?;
+
+    for sample in samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+    writer.flush()
+}
+
+/// The most bytes of samples a WAV file can hold, as the sizes in its header are 32-bit.
+const MAX_WAV_DATA: u32 = u32::MAX - 36;
+
+fn too_long() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "too much audio for a WAV file")
+}
+
+/// The 44 byte header of a 16-bit stereo PCM WAV file with `data_size` bytes of samples.
+fn wav_header(sample_rate: u32, data_size: u32) -> [u8; 44] {
+    const CHANNELS: u16 = 2;
+    const BITS_PER_SAMPLE: u16 = 16;
+    let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+
+    let mut header = [0; 44];
+    let fields: [&[u8]; 13] = [
+        b"RIFF",
+        &(36 + data_size).to_le_bytes(),
+        b"WAVE",
+        b"fmt ",
+        &16u32.to_le_bytes(),
+        // PCM
+        &1u16.to_le_bytes(),
+        &CHANNELS.to_le_bytes(),
+        &sample_rate.to_le_bytes(),
+        &(sample_rate * u32::from(block_align)).to_le_bytes(),
+        &block_align.to_le_bytes(),
+        &BITS_PER_SAMPLE.to_le_bytes(),
+        b"data",
+        &data_size.to_le_bytes(),
+    ];
+
+    let mut offset = 0;
+    for field in fields.iter() {
+        header[offset..offset + field.len()].copy_from_slice(field);
+        offset += field.len();
+    }
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn wav_layout() {
+        let mut file = Vec::new();
+        write_wav(&mut file, 32_000, &[1, -1, 2, -2]).unwrap();
+
+        assert_eq!(file.len(), 44 + 8);
+        assert_eq!(&file[0..4], b"RIFF");
+        assert_eq!(&file[4..8], &44u32.to_le_bytes());
+        assert_eq!(&file[24..28], &32_000u32.to_le_bytes());
+        assert_eq!(&file[28..32], &128_000u32.to_le_bytes());
+        assert_eq!(&file[40..44], &8u32.to_le_bytes());
+        assert_eq!(&file[44..48], &[1, 0, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn recorder() {
+        let samples = vec![100; 2 * 3200];
+
+        let mut recorder = AudioRecorder::new(Cursor::new(Vec::new()), AudioFormat::Wav).unwrap();
+        recorder.write(&samples[..1000]).unwrap();
+        recorder.write(&samples[1000..]).unwrap();
+        let file = recorder.finish().unwrap().into_inner();
+
+        let mut expected = Vec::new();
+        write_wav(&mut expected, SAMPLE_RATE, &samples).unwrap();
+        assert_eq!(file, expected);
+
+        let mut recorder = AudioRecorder::with_resampler(
+            Cursor::new(Vec::new()),
+            AudioFormat::Raw,
+            48_000,
+            Quality::Cubic,
+        )
+        .unwrap();
+        recorder.write(&samples).unwrap();
+        let file = recorder.finish().unwrap().into_inner();
+
+        // A tenth of a second at 48 kHz, plus the flushed tail
+        assert!((4 * 4800..4 * 4810).contains(&file.len()), "{}", file.len());
+
+        let writer = Cursor::new(Vec::new());
+        let zero = AudioRecorder::with_resampler(writer, AudioFormat::Raw, 0, Quality::Linear);
+        assert_eq!(zero.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+
+        // The sizes in a WAV header are 32-bit
+        let mut recorder = AudioRecorder::new(Cursor::new(Vec::new()), AudioFormat::Wav).unwrap();
+        recorder.data_size = u64::from(MAX_WAV_DATA) - 2;
+        assert!(recorder.write(&[1]).is_ok());
+        assert!(recorder.write(&[1]).is_err());
+    }
+}
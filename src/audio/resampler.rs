@@ -0,0 +1,219 @@
+use std::f64::consts::PI;
+use std::io;
+
+/// How carefully audio is resampled, trading speed for fidelity.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Quality {
+    /// Linear interpolation between neighbouring samples
+    Linear,
+
+    /// Cubic Catmull-Rom interpolation over four samples
+    Cubic,
+
+    /// Windowed sinc interpolation over 32 samples
+    Sinc,
+}
+
+impl Quality {
+    /// Input frames needed on each side of an output frame.
+    fn half_width(self) -> usize {
+        match self {
+            Quality::Linear => 1,
+            Quality::Cubic => 2,
+            Quality::Sinc => 16,
+        }
+    }
+}
+
+/// Converts interleaved stereo audio from one sample rate to another, in a stream.
+pub(super) struct Resampler {
+    input_rate: u32,
+    output_rate: u32,
+    quality: Quality,
+
+    /// Input frames kept around the position of the next output frame
+    frames: Vec<[f64; 2]>,
+
+    /// Position of the next output frame: an index into `frames`, plus a fraction in units of
+    /// `1 / output_rate`
+    index: usize,
+    fraction: u32,
+
+    /// A left sample from the end of the last input, waiting for its right one
+    leftover: Option<i16>,
+}
+
+impl Resampler {
+    /// Fails if either rate is zero.
+    pub fn new(input_rate: u32, output_rate: u32, quality: Quality) -> io::Result<Resampler> {
+        if input_rate == 0 || output_rate == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "sample rates must be above zero",
+            ));
+        }
+
+        // Start with silence before the first frame, so it can be interpolated like the rest
+        let padding = quality.half_width() - 1;
+
+        Ok(Resampler {
+            input_rate,
+            output_rate,
+            quality,
+            frames: vec![[0.0; 2]; padding],
+            index: padding,
+            fraction: 0,
+            leftover: None,
+        })
+    }
+
+    /// Resample interleaved samples, appending the output that can be produced so far.
+    pub fn process(&mut self, mut input: &[i16], output: &mut Vec<i16>) {
+        if let Some(left) = self.leftover.take() {
+            match input.split_first() {
+                Some((&right, rest)) => {
+                    self.frames.push([f64::from(left), f64::from(right)]);
+                    input = rest;
+                }
+                None => self.leftover = Some(left),
+            }
+        }
+
+        let frames = input.chunks_exact(2);
+        if let [left] = frames.remainder() {
+            self.leftover = Some(*left);
+        }
+        self.frames
+            .extend(frames.map(|frame| [f64::from(frame[0]), f64::from(frame[1])]));
+
+        let half_width = self.quality.half_width();
+        while self.index + half_width < self.frames.len() {
+            let offset = f64::from(self.fraction) / f64::from(self.output_rate);
+            for channel in 0..2 {
+                let value = self.interpolate(channel, offset);
+                output.push(value.round().clamp(-32768.0, 32767.0) as i16);
+            }
+
+            self.fraction += self.input_rate;
+            while self.fraction >= self.output_rate {
+                self.fraction -= self.output_rate;
+                self.index += 1;
+            }
+        }
+
+        // Drop the frames that are no longer needed
+        let consumed = self.index + 1 - half_width;
+        self.frames.drain(..consumed);
+        self.index -= consumed;
+    }
+
+    /// Produce the output still waiting on frames after the end of the input, as if the input was
+    /// followed by silence.
+    pub fn flush(&mut self, output: &mut Vec<i16>) {
+        let silence = vec![0; 2 * self.quality.half_width()];
+        self.process(&silence, output);
+    }
+
+    fn interpolate(&self, channel: usize, offset: f64) -> f64 {
+        let sample = |delta: isize| self.frames[(self.index as isize + delta) as usize][channel];
+
+        match self.quality {
+            Quality::Linear => sample(0) + (sample(1) - sample(0)) * offset,
+            Quality::Cubic => {
+                let (p0, p1, p2, p3) = (sample(-1), sample(0), sample(1), sample(2));
+                let a = -0.5 * p0 + 1.5 * p1 - 1.5 * p2 + 0.5 * p3;
+                let b = p0 - 2.5 * p1 + 2.0 * p2 - 0.5 * p3;
+                let c = -0.5 * p0 + 0.5 * p2;
+                ((a * offset + b) * offset + c) * offset + p1
+            }
+            Quality::Sinc => {
+                let half_width = self.quality.half_width() as isize;
+
+                // Lower the cutoff when downsampling, to keep out aliasing
+                let cutoff =
+                    f64::from(self.output_rate.min(self.input_rate)) / f64::from(self.input_rate);
+
+                let mut sum = 0.0;
+                let mut weights = 0.0;
+                for delta in 1 - half_width..=half_width {
+                    let distance = delta as f64 - offset;
+                    let weight = cutoff * sinc(cutoff * distance) * blackman(distance, half_width);
+                    sum += sample(delta) * weight;
+                    weights += weight;
+                }
+                sum / weights
+            }
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// The Blackman window over `-half_width..half_width`.
+fn blackman(x: f64, half_width: isize) -> f64 {
+    let phase = PI * (x / half_width as f64 + 1.0);
+    0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_and_dc() {
+        let input = vec![1000; 2 * 32_000];
+
+        for &quality in &[Quality::Linear, Quality::Cubic, Quality::Sinc] {
+            for &rate in &[44_100, 48_000] {
+                let mut resampler = Resampler::new(32_000, rate, quality).unwrap();
+                let mut output = Vec::new();
+                resampler.process(&input, &mut output);
+                resampler.flush(&mut output);
+
+                // One second in, one second out
+                let frames = output.len() / 2;
+                assert!(
+                    (rate as usize..rate as usize + 64).contains(&frames),
+                    "{:?} at {} gave {} frames",
+                    quality,
+                    rate,
+                    frames
+                );
+
+                // A constant signal stays constant once past the silence at the start
+                let middle = &output[output.len() / 2..output.len() / 2 + 64];
+                assert!(middle.iter().all(|&sample| sample == 1000), "{:?}", quality);
+            }
+        }
+    }
+
+    #[test]
+    fn streaming_matches_one_shot() {
+        let input: Vec<i16> = (0..2000).map(|index| (index * 37 % 2001) as i16).collect();
+
+        let mut whole = Vec::new();
+        let mut resampler = Resampler::new(32_000, 48_000, Quality::Sinc).unwrap();
+        resampler.process(&input, &mut whole);
+
+        let mut pieces = Vec::new();
+        let mut resampler = Resampler::new(32_000, 48_000, Quality::Sinc).unwrap();
+        for chunk in input.chunks(66) {
+            resampler.process(chunk, &mut pieces);
+        }
+        assert_eq!(whole, pieces);
+
+        // Chunks can split a frame between its left and right samples
+        let mut pieces = Vec::new();
+        let mut resampler = Resampler::new(32_000, 48_000, Quality::Sinc).unwrap();
+        for chunk in input.chunks(67) {
+            resampler.process(chunk, &mut pieces);
+        }
+        assert_eq!(whole, pieces);
+    }
+}
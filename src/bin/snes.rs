@@ -0,0 +1,220 @@
+//! Run a ROM without a display, for scripts and continuous integration.
+//!
+//! Usage: snes run <rom> [options]
+//!
+//! Options:
+//!   --frames <n>                 frames to run, 60 by default
+//!   --until-pc <address>         stop early when the CPU reaches a 24-bit address
+//!   --input <movie>              play the buttons recorded in a movie
+//!   --trace <file>               write every instruction executed to a file
+//!   --symbols <file>             labels for the trace, by default the .sym file next to the ROM
+//!   --screenshot <file.png>      save the last frame
+//!   --dump-frame <file>          save the last frame as raw RGBA8888 pixels
+//!   --record <file.y4m | dir>    record every frame to a Y4M file, or to numbered PNGs in a
+//!                                directory, with the audio in a .wav file beside them
+//!   --dump-wram <file>           save the 128 KB of work RAM
+//!   --exit-code <address>[=<v>]  exit with the byte at an address, or with 0 if it equals v
+//!                                and 1 otherwise
+//!
+//! Addresses and values are hexadecimal, like 7E0010 or $80.
+
+use snes::{AudioFormat, AudioRecorder, Movie, PixelFormat, Region, Snes, Symbols, VideoRecorder};
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::Path;
+use std::{env, process};
+
+/// Frames run when no number is given.
+const DEFAULT_FRAMES: u32 = 60;
+
+const USAGE: &str = "run <rom> [--frames <n>] [--until-pc <address>] [--input <movie>] \
+                     [--trace <file>] [--symbols <file>] [--screenshot <file.png>] \
+                     [--dump-frame <file>] [--record <file.y4m | dir>] [--dump-wram <file>] \
+                     [--exit-code <address>[=<value>]]";
+
+#[derive(Default)]
+struct Options {
+    rom: String,
+    frames: Option<u32>,
+    until_pc: Option<u32>,
+    input: Option<String>,
+    trace: Option<String>,
+    symbols: Option<String>,
+    screenshot: Option<String>,
+    dump_frame: Option<String>,
+    record: Option<String>,
+    dump_wram: Option<String>,
+
+    /// The address of the exit code, and the value that means success
+    exit_code: Option<(u32, Option<u8>)>,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = match parse_options(&args[1..]) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}", error);
+            eprintln!("usage: {} {}", args[0], USAGE);
+            process::exit(2);
+        }
+    };
+
+    match run(&options) {
+        Ok(code) => process::exit(code),
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let (rom, rest) = match args {
+        [command, rom, rest @ ..] if command == "run" => (rom, rest),
+        _ => return Err("expected a command and a ROM".to_string()),
+    };
+
+    let mut options = Options {
+        rom: rom.clone(),
+        ..Options::default()
+    };
+
+    let mut rest = rest.iter();
+    while let Some(option) = rest.next() {
+        let value = rest
+            .next()
+            .ok_or_else(|| format!("missing value for {}", option))?;
+
+        match option.as_str() {
+            "--frames" => {
+                let frames = value
+                    .parse()
+                    .map_err(|error| format!("invalid frame count {:?}: {}", value, error))?;
+                options.frames = Some(frames);
+            }
+            "--until-pc" => options.until_pc = Some(parse_hex(value)?),
+            "--input" => options.input = Some(value.clone()),
+            "--trace" => options.trace = Some(value.clone()),
+            "--symbols" => options.symbols = Some(value.clone()),
+            "--screenshot" => options.screenshot = Some(value.clone()),
+            "--dump-frame" => options.dump_frame = Some(value.clone()),
+            "--record" => options.record = Some(value.clone()),
+            "--dump-wram" => options.dump_wram = Some(value.clone()),
+            "--exit-code" => {
+                let (address, expected) = match value.split_once('=') {
+                    Some((address, expected)) => (address, Some(parse_byte(expected)?)),
+                    None => (value.as_str(), None),
+                };
+                options.exit_code = Some((parse_hex(address)?, expected));
+            }
+            _ => return Err(format!("unknown option {}", option)),
+        }
+    }
+
+    Ok(options)
+}
+
+fn parse_hex(text: &str) -> Result<u32, String> {
+    let digits = text.trim_start_matches('$').trim_start_matches("0x");
+    u32::from_str_radix(digits, 16).map_err(|_| format!("invalid address or value {:?}", text))
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    u8::try_from(parse_hex(text)?).map_err(|_| format!("value {:?} is larger than a byte", text))
+}
+
+/// Record to a Y4M file if the path ends in .y4m, or else to PNG files in a directory. The audio
+/// goes to a .wav file beside the Y4M file, or to audio.wav in the directory.
+fn start_recording(
+    path: &Path,
+    region: Region,
+) -> Result<VideoRecorder<BufWriter<File>>, Box<dyn std::error::Error>> {
+    let create =
+        |path: &Path| -> io::Result<BufWriter<File>> { Ok(BufWriter::new(File::create(path)?)) };
+
+    if path.extension().is_some_and(|extension| extension == "y4m") {
+        let audio = AudioRecorder::new(create(&path.with_extension("wav"))?, AudioFormat::Wav)?;
+        Ok(VideoRecorder::y4m(create(path)?, audio, region))
+    } else {
+        fs::create_dir_all(path)?;
+        let audio = AudioRecorder::new(create(&path.join("audio.wav"))?, AudioFormat::Wav)?;
+        Ok(VideoRecorder::png_sequence(path, audio, region))
+    }
+}
+
+/// Run the ROM, returning the exit code.
+fn run(options: &Options) -> Result<i32, Box<dyn std::error::Error>> {
+    let rom = fs::read(&options.rom)?;
+    let mut snes = Snes::new(&rom);
+
+    let sibling = Path::new(&options.rom).with_extension("sym");
+    match &options.symbols {
+        Some(symbols) => snes.set_symbols(Symbols::parse(&fs::read_to_string(symbols)?)),
+        None if sibling.exists() => snes.set_symbols(Symbols::parse(&fs::read_to_string(sibling)?)),
+        None => {}
+    }
+
+    if let Some(input) = &options.input {
+        let movie = Movie::from_bytes(&fs::read(input)?)?;
+        snes.play_movie(movie)?;
+    }
+
+    if let Some(trace) = &options.trace {
+        snes.set_trace(Some(Box::new(BufWriter::new(File::create(trace)?))));
+    }
+
+    let mut recorder = match &options.record {
+        Some(path) => Some(start_recording(Path::new(path), snes.region())?),
+        None => None,
+    };
+
+    for _ in 0..options.frames.unwrap_or(DEFAULT_FRAMES) {
+        match options.until_pc {
+            Some(address) => {
+                if snes.run_until_pc(address) {
+                    break;
+                }
+            }
+            None => snes.run_frame(),
+        }
+
+        if let Some(recorder) = &mut recorder {
+            recorder.record(&mut snes)?;
+        }
+    }
+
+    if let Some(recorder) = recorder {
+        recorder.finish()?;
+    }
+
+    // Dropping the trace log flushes it
+    snes.set_trace(None);
+
+    if let Some(screenshot) = &options.screenshot {
+        snes.framebuffer(PixelFormat::Rgba8888)
+            .write_png(BufWriter::new(File::create(screenshot)?))?;
+    }
+
+    if let Some(dump) = &options.dump_frame {
+        fs::write(dump, snes.framebuffer(PixelFormat::Rgba8888).data)?;
+    }
+
+    if let Some(dump) = &options.dump_wram {
+        fs::write(dump, snes.wram())?;
+    }
+
+    Ok(match options.exit_code {
+        Some((address, expected)) => {
+            let value = snes
+                .read_byte(address)
+                .ok_or_else(|| format!("cannot read the exit code at {:06X}", address))?;
+            match expected {
+                Some(expected) => (value != expected) as i32,
+                None => i32::from(value),
+            }
+        }
+        None => 0,
+    })
+}
@@ -0,0 +1,73 @@
+//! Debug a ROM interactively, stepping its CPU from a prompt. With the `gdb` feature, GDB can
+//! connect on a local port instead.
+//!
+//! Labels are read from the symbol file given, or else from a `.sym` file next to the ROM.
+//!
+//! Usage: snesdbg <rom> [--symbols <file>] [--gdb <port>]
+
+use snes::{Debugger, Snes, Symbols};
+use std::path::Path;
+use std::{env, fs, process};
+
+const USAGE: &str = "<rom> [--symbols <file>] [--gdb <port>]";
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 || !args.len().is_multiple_of(2) {
+        eprintln!("usage: {} {}", args[0], USAGE);
+        process::exit(2);
+    }
+
+    let mut symbols = None;
+    let mut port = None;
+    for option in args[2..].chunks(2) {
+        match option[0].as_str() {
+            "--symbols" => symbols = Some(option[1].clone()),
+            "--gdb" => match option[1].parse::<u16>() {
+                Ok(number) => port = Some(number),
+                Err(error) => {
+                    eprintln!("invalid port {:?}: {}", option[1], error);
+                    process::exit(2);
+                }
+            },
+            _ => {
+                eprintln!("usage: {} {}", args[0], USAGE);
+                process::exit(2);
+            }
+        }
+    }
+
+    if let Err(error) = debug(&args[1], symbols, port) {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
+
+fn debug(
+    path: &str,
+    symbols: Option<String>,
+    port: Option<u16>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rom = fs::read(path)?;
+    let mut snes = Snes::new(&rom);
+
+    let sibling = Path::new(path).with_extension("sym");
+    match symbols {
+        Some(symbols) => snes.set_symbols(Symbols::parse(&fs::read_to_string(symbols)?)),
+        None if sibling.exists() => snes.set_symbols(Symbols::parse(&fs::read_to_string(sibling)?)),
+        None => {}
+    }
+
+    let mut debugger = Debugger::new(snes);
+    match port {
+        #[cfg(feature = "gdb")]
+        Some(port) => {
+            eprintln!("waiting for GDB on 127.0.0.1:{}", port);
+            debugger.serve_gdb(("127.0.0.1", port))?;
+        }
+        #[cfg(not(feature = "gdb"))]
+        Some(_) => return Err("built without the gdb feature".into()),
+        None => debugger.run()?,
+    }
+    Ok(())
+}
@@ -0,0 +1,48 @@
+//! Render an .spc sound snapshot to a WAV file, without a sound device.
+//!
+//! Usage: spc2wav <input.spc> <output.wav> [seconds]
+
+use snes::{write_wav, SpcPlayer, SAMPLE_RATE};
+use std::{env, fs, process};
+
+/// Seconds rendered when no length is given.
+const DEFAULT_SECONDS: u32 = 30;
+
+/// The longest length rendered, an hour. The whole file is rendered in memory first.
+const MAX_SECONDS: u32 = 60 * 60;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 || args.len() > 4 {
+        eprintln!("usage: {} <input.spc> <output.wav> [seconds]", args[0]);
+        process::exit(2);
+    }
+
+    let seconds = match args.get(3).map(|seconds| seconds.parse()) {
+        None => DEFAULT_SECONDS,
+        Some(Ok(seconds)) if seconds <= MAX_SECONDS => seconds,
+        Some(Ok(_)) => {
+            eprintln!("the length can be at most {} seconds", MAX_SECONDS);
+            process::exit(2);
+        }
+        Some(Err(error)) => {
+            eprintln!("invalid length {:?}: {}", args[3], error);
+            process::exit(2);
+        }
+    };
+
+    if let Err(error) = render(&args[1], &args[2], seconds) {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
+
+fn render(input: &str, output: &str, seconds: u32) -> Result<(), Box<dyn std::error::Error>> {
+    let file = fs::read(input)?;
+    let mut player = SpcPlayer::new(&file)?;
+    let samples = player.render(seconds).ok_or("too long to render")?;
+
+    let output = fs::File::create(output)?;
+    write_wav(std::io::BufWriter::new(output), SAMPLE_RATE, &samples)?;
+    Ok(())
+}
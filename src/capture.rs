@@ -0,0 +1,200 @@
+//! Recording video from the emulator, as a YUV4MPEG2 stream or numbered PNG files, with the
+//! audio recorded alongside.
+//!
+//! See https://wiki.multimedia.cx/index.php/YUV4MPEG2
+
+use crate::timing::CYCLES_PER_LINE;
+use crate::video::{Framebuffer, PixelFormat};
+use crate::*;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, Write};
+use std::path::PathBuf;
+
+/// Where the pictures go.
+enum Output {
+    Y4m {
+        writer: Box<dyn Write>,
+
+        /// The size of every picture in the stream, set by the first one. Later pictures of
+        /// another size, such as after switching to a hi-res mode, are scaled to it.
+        size: Option<(usize, usize)>,
+    },
+    Png {
+        directory: PathBuf,
+    },
+}
+
+/// Records every frame the emulator draws along with the audio it generates. Call `record` after
+/// every frame, then finish it to complete the files.
+///
+/// Pictures and samples both come from the emulated master clock, so as long as every frame is
+/// recorded and nothing else takes the audio, the audio lasts exactly as long as the video.
+pub struct VideoRecorder<A: Write + Seek> {
+    output: Output,
+    audio: AudioRecorder<A>,
+
+    /// Frames per second as a fraction, numerator first
+    frame_rate: (u32, u32),
+
+    frames: u64,
+}
+
+impl<A: Write + Seek> VideoRecorder<A> {
+    /// Record to a YUV4MPEG2 stream, at the frame rate of `region`.
+    pub fn y4m<W: Write + 'static>(
+        writer: W,
+        audio: AudioRecorder<A>,
+        region: Region,
+    ) -> VideoRecorder<A> {
+        let output = Output::Y4m {
+            writer: Box::new(writer),
+            size: None,
+        };
+        VideoRecorder::new(output, audio, region)
+    }
+
+    /// Record to PNG files numbered from 000000.png in `directory`, at the frame rate of
+    /// `region`.
+    pub fn png_sequence(
+        directory: impl Into<PathBuf>,
+        audio: AudioRecorder<A>,
+        region: Region,
+    ) -> VideoRecorder<A> {
+        let output = Output::Png {
+            directory: directory.into(),
+        };
+        VideoRecorder::new(output, audio, region)
+    }
+
+    fn new(output: Output, audio: AudioRecorder<A>, region: Region) -> VideoRecorder<A> {
+        VideoRecorder {
+            output,
+            audio,
+            frame_rate: frame_rate(region),
+            frames: 0,
+        }
+    }
+
+    /// The frames per second the video plays at, as a numerator and denominator.
+    pub fn frame_rate(&self) -> (u32, u32) {
+        self.frame_rate
+    }
+
+    /// Frames recorded so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Record the last frame drawn and the audio generated since the last call.
+    pub fn record(&mut self, snes: &mut Snes<'_>) -> io::Result<()> {
+        let framebuffer = snes.framebuffer(PixelFormat::Rgba8888);
+        match &mut self.output {
+            Output::Y4m { writer, size } => {
+                let (width, height) =
+                    *size.get_or_insert_with(|| (framebuffer.width, framebuffer.height));
+                if self.frames == 0 {
+                    let (numerator, denominator) = self.frame_rate;
+                    writeln!(
+                        writer,
+                        "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444",
+                        width, height, numerator, denominator
+                    )?;
+                }
+                writer.write_all(b"FRAME\n")?;
+                writer.write_all(&yuv444(&framebuffer, width, height))?;
+            }
+            Output::Png { directory } => {
+                let path = directory.join(format!("{:06}.png", self.frames));
+                framebuffer.write_png(BufWriter::new(File::create(path)?))?;
+            }
+        }
+        self.frames += 1;
+
+        self.audio.write(&snes.audio_samples())
+    }
+
+    /// Flush the video and complete the audio file, returning its writer.
+    pub fn finish(mut self) -> io::Result<A> {
+        if let Output::Y4m { writer, .. } = &mut self.output {
+            writer.flush()?;
+        }
+        self.audio.finish()
+    }
+}
+
+/// The frame rate of a region as a fraction in its lowest terms.
+fn frame_rate(region: Region) -> (u32, u32) {
+    let clock = region.master_clock();
+    let cycles_per_frame = CYCLES_PER_LINE * u32::from(region.lines_per_frame());
+
+    let (mut a, mut b) = (clock, cycles_per_frame);
+    while b != 0 {
+        let remainder = a % b;
+        a = b;
+        b = remainder;
+    }
+    (clock / a, cycles_per_frame / a)
+}
+
+/// Convert RGBA pixels to the Y, Cb and Cr planes of a `width` by `height` picture, in the
+/// limited range of BT.601, scaling them with the nearest pixel if the size differs.
+fn yuv444(framebuffer: &Framebuffer, width: usize, height: usize) -> Vec<u8> {
+    let size = width * height;
+    let mut planes = vec![0; 3 * size];
+    for y in 0..height {
+        let source_y = y * framebuffer.height / height;
+        for x in 0..width {
+            let source_x = x * framebuffer.width / width;
+            let pixel = 4 * (source_y * framebuffer.width + source_x);
+            let channel = |offset: usize| i32::from(framebuffer.data[pixel + offset]);
+            let (r, g, b) = (channel(0), channel(1), channel(2));
+
+            let index = y * width + x;
+            planes[index] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+            planes[size + index] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+            planes[2 * size + index] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+        }
+    }
+    planes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{build_rom, SharedWriter};
+    use std::io::Cursor;
+
+    #[test]
+    fn y4m() {
+        assert_eq!(frame_rate(Region::Ntsc), (2_684_659, 44_671));
+        assert_eq!(frame_rate(Region::Pal), (322_445, 6_448));
+
+        // Loop forever
+        let rom = build_rom(&[(0x8000, &[0x4c, 0x00, 0x80])]);
+        let mut snes = Snes::new(&rom);
+        let audio = AudioRecorder::new(Cursor::new(Vec::new()), AudioFormat::Raw).unwrap();
+        let video = SharedWriter::default();
+        let mut recorder = VideoRecorder::y4m(video.clone(), audio, snes.region());
+        for _ in 0..3 {
+            snes.run_frame();
+            recorder.record(&mut snes).unwrap();
+        }
+        assert_eq!(recorder.frames(), 3);
+        let audio = recorder.finish().unwrap().into_inner();
+
+        let video = video.contents();
+        let header = b"YUV4MPEG2 W256 H224 F2684659:44671 Ip A1:1 C444\n";
+        assert!(video.starts_with(header));
+        assert_eq!(video.len(), header.len() + 3 * (6 + 3 * 256 * 224));
+
+        // A black picture
+        let first = &video[header.len()..header.len() + 6 + 3 * 256 * 224];
+        assert_eq!(&first[..6], b"FRAME\n");
+        assert_eq!(&first[6..9], &[16, 16, 16]);
+        assert_eq!(first[6 + 256 * 224], 128);
+
+        // Three frames of stereo 16-bit samples at 32 kHz
+        let samples = audio.len() / 4;
+        assert!((1580..1620).contains(&samples), "{} samples", samples);
+    }
+}
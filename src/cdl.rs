@@ -0,0 +1,198 @@
+//! Code/data logging: marking every byte of ROM by how it has been used, to guide disassembly
+//! and measure how much of the ROM a test run covers.
+
+use crate::cpu::disassembler::instruction_size;
+use crate::*;
+
+/// Bytes of ROM in a LoROM bank.
+const BANK_SIZE: usize = 0x8000;
+
+/// How each byte of ROM has been used, as a combination of its flags.
+pub struct CodeDataLog {
+    flags: Vec<u8>,
+}
+
+impl CodeDataLog {
+    /// The first byte of an instruction that was executed
+    pub const OPCODE: u8 = 0x01;
+
+    /// A byte of an executed instruction after its opcode
+    pub const OPERAND: u8 = 0x02;
+
+    /// A byte read by the CPU as data
+    pub const DATA: u8 = 0x04;
+
+    /// A byte read by DMA or HDMA
+    pub const DMA: u8 = 0x08;
+
+    /// An opcode executed with 8-bit index registers
+    pub const INDEX_8BIT: u8 = 0x10;
+
+    /// An opcode executed with an 8-bit accumulator
+    pub const ACCUMULATOR_8BIT: u8 = 0x20;
+
+    pub(crate) fn new(rom_size: usize) -> CodeDataLog {
+        CodeDataLog {
+            flags: vec![0; rom_size],
+        }
+    }
+
+    pub(crate) fn mark(&mut self, index: usize, flags: u8) {
+        if let Some(byte) = self.flags.get_mut(index) {
+            *byte |= flags;
+        }
+    }
+
+    /// The flags of every byte of ROM.
+    pub fn flags(&self) -> &[u8] {
+        &self.flags
+    }
+
+    /// The log in the .cdl format read by Mesen-S and the disassemblers built around it: a byte
+    /// per byte of ROM, with bit 0 set for code, bit 1 for data, bit 4 for 8-bit index registers
+    /// and bit 5 for an 8-bit accumulator.
+    pub fn to_cdl(&self) -> Vec<u8> {
+        self.flags
+            .iter()
+            .map(|&flags| {
+                let mut cdl = flags & (Self::INDEX_8BIT | Self::ACCUMULATOR_8BIT);
+                if flags & (Self::OPCODE | Self::OPERAND) != 0 {
+                    cdl |= 0x01;
+                }
+                if flags & (Self::DATA | Self::DMA) != 0 {
+                    cdl |= 0x02;
+                }
+                cdl
+            })
+            .collect()
+    }
+
+    /// A table of the share of each bank used as code, data read by the CPU and DMA, and the
+    /// whole ROM at the end. Bytes used as both code and data count as code.
+    pub fn coverage_report(&self) -> String {
+        let mut report = String::from("Bank    Code    Data     DMA  Unused\n");
+
+        let row = |name: &str, bytes: &[u8]| {
+            let count =
+                |test: &dyn Fn(u8) -> bool| bytes.iter().filter(|&&flags| test(flags)).count();
+            let code = count(&|flags| flags & (Self::OPCODE | Self::OPERAND) != 0);
+            let data = count(&|flags| {
+                flags & (Self::OPCODE | Self::OPERAND) == 0 && flags & Self::DATA != 0
+            });
+            let dma = count(&|flags| {
+                flags & (Self::OPCODE | Self::OPERAND | Self::DATA) == 0 && flags & Self::DMA != 0
+            });
+            let unused = count(&|flags| flags == 0);
+
+            let percent = |count: usize| 100.0 * count as f64 / bytes.len().max(1) as f64;
+            format!(
+                "{:<4} {:>6.1}% {:>6.1}% {:>6.1}% {:>6.1}%\n",
+                name,
+                percent(code),
+                percent(data),
+                percent(dma),
+                percent(unused)
+            )
+        };
+
+        for (bank, bytes) in self.flags.chunks(BANK_SIZE).enumerate() {
+            report += &row(&format!("${:02X}", bank), bytes);
+        }
+        report += &row("All", &self.flags);
+        report
+    }
+}
+
+impl<'a> Snes<'a> {
+    /// Note where the instruction about to be executed is, so that fetching it isn't taken for
+    /// reading data, and mark its bytes if a code/data log is kept. Only the log and watchpoints
+    /// need it.
+    pub(crate) fn log_instruction(&mut self) {
+        if self.memory.cdl.is_none() && self.memory.watches.points.is_empty() {
+            return;
+        }
+
+        let registers = &self.core.registers;
+        let (bank, pc) = (registers.program_bank, registers.program_counter);
+        let (m, x) = registers.register_sizes();
+
+        let opcode = self.memory.peek(bank, pc).unwrap_or(0);
+        let size = instruction_size(opcode, m, x);
+        self.memory.set_instruction(registers.program_address(), size);
+
+        let mut flags = CodeDataLog::OPCODE;
+        if m {
+            flags |= CodeDataLog::ACCUMULATOR_8BIT;
+        }
+        if x {
+            flags |= CodeDataLog::INDEX_8BIT;
+        }
+
+        for offset in 0..u16::from(size) {
+            self.memory.log_rom(bank, pc.wrapping_add(offset), flags);
+            flags = CodeDataLog::OPERAND;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::build_rom;
+
+    #[test]
+    fn code_and_dma() {
+        // DMA 4 bytes from $00:8100 to VRAM, then loop forever
+        #[rustfmt::skip]
+        let code = [
+            0xa9, 0x01, 0x8d, 0x00, 0x43, // lda #$01; sta $4300
+            0xa9, 0x18, 0x8d, 0x01, 0x43, // lda #$18; sta $4301
+            0xa9, 0x00, 0x8d, 0x02, 0x43, // lda #$00; sta $4302
+            0xa9, 0x81, 0x8d, 0x03, 0x43, // lda #$81; sta $4303
+            0xa9, 0x00, 0x8d, 0x04, 0x43, // lda #$00; sta $4304
+            0xa9, 0x04, 0x8d, 0x05, 0x43, // lda #$04; sta $4305
+            0x9c, 0x06, 0x43,             // stz $4306
+            0xa9, 0x01, 0x8d, 0x0b, 0x42, // lda #$01; sta $420b
+            0x4c, 0x26, 0x80,             // jmp $8026
+        ];
+        let rom = build_rom(&[(0x8000, &code)]);
+        let mut snes = Snes::new(&rom);
+        snes.enable_code_data_log();
+        for _ in 0..20 {
+            snes.step();
+        }
+
+        let log = snes.disable_code_data_log().unwrap();
+        let flags = log.flags();
+        assert_eq!(
+            flags[0x0000],
+            CodeDataLog::OPCODE | CodeDataLog::ACCUMULATOR_8BIT | CodeDataLog::INDEX_8BIT
+        );
+        assert_eq!(flags[0x0001], CodeDataLog::OPERAND);
+        assert_eq!(
+            flags[0x0026],
+            CodeDataLog::OPCODE | CodeDataLog::ACCUMULATOR_8BIT | CodeDataLog::INDEX_8BIT
+        );
+        assert_eq!(flags[0x0029], 0);
+        assert_eq!(
+            &flags[0x00ff..0x0105],
+            &[
+                0,
+                CodeDataLog::DMA,
+                CodeDataLog::DMA,
+                CodeDataLog::DMA,
+                CodeDataLog::DMA,
+                0
+            ]
+        );
+        assert!(snes.code_data_log().is_none());
+
+        let cdl = log.to_cdl();
+        assert_eq!(cdl.len(), rom.len());
+        assert_eq!((cdl[0x0000], cdl[0x0001], cdl[0x0100]), (0x31, 0x01, 0x02));
+
+        let report = log.coverage_report();
+        assert!(report.starts_with("Bank    Code    Data     DMA  Unused\n$00     0.1%"));
+        assert!(report.lines().last().unwrap().starts_with("All"));
+    }
+}
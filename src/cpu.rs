@@ -1,15 +1,20 @@
 use crate::{memory_map::*, *};
 
 mod registers;
-use registers::*;
+pub(crate) use registers::*;
+
+pub(crate) mod disassembler;
 
 /// Rioch 5A22 CPU, executes uses 65C816 assembly
 pub struct Cpu {
     native_interrupts: InterruptVector,
     emulation_interrupts: InterruptVector,
-    registers: CpuRegisters,
+    pub(crate) registers: CpuRegisters,
 }
 
+// The interrupt vectors come from the ROM
+save_state! { Cpu { registers } }
+
 impl Cpu {
     pub(crate) fn new(memory: &MemoryMap) -> Self {
         let SnesHeader {
@@ -40,10 +45,71 @@ impl Cpu {
         self.registers.processor_status.set_carry(true);
     }
 
-    pub(crate) fn tick(&mut self, memory: &mut MemoryMap) {
-        let instruction = self.fetch_instruction(memory);
+    /// Execute the next instruction, returning its opcode.
+    pub(crate) fn tick(&mut self, memory: &mut MemoryMap) -> u8 {
+        let opcode = self.get_instruction_arg(memory, 0);
+        let instruction = self.fetch_instruction(opcode, memory);
         self.advance(instruction.size());
         self.execute(instruction, memory);
+        opcode
+    }
+
+    /// Are IRQs masked by the processor status?
+    pub(crate) fn irq_disabled(&self) -> bool {
+        self.registers.processor_status.get_irq()
+    }
+
+    /// Push the program counter and status on the stack and jump to an interrupt handler.
+    pub(crate) fn interrupt(&mut self, memory: &mut MemoryMap, interrupt: Interrupt) {
+        let vectors = if self.registers.emulation {
+            &self.emulation_interrupts
+        } else {
+            &self.native_interrupts
+        };
+
+        let vector = match interrupt {
+            Interrupt::Nmi => vectors.nmi,
+            Interrupt::Irq => vectors.irq,
+        };
+
+        log::trace!("Interrupt: {:?} -> {:04x}", interrupt, vector);
+
+        if !self.registers.emulation {
+            self.push(memory, self.registers.program_bank);
+        }
+        let [low, high] = self.registers.program_counter.to_le_bytes();
+        self.push(memory, high);
+        self.push(memory, low);
+        self.push(memory, self.registers.processor_status.0);
+
+        self.registers.processor_status.set_irq(true);
+        self.registers.processor_status.set_decimal(false);
+        self.registers.program_bank = 0;
+        self.registers.program_counter = vector;
+    }
+
+    // ===== //
+    // Stack //
+    // ===== //
+
+    /// In emulation mode the stack is confined to page 1.
+    fn step_stack_pointer(&mut self, step: fn(u16, u16) -> u16) {
+        let stack_pointer = step(self.registers.stack_pointer, 1);
+        self.registers.stack_pointer = if self.registers.emulation {
+            0x0100 | (stack_pointer & 0xff)
+        } else {
+            stack_pointer
+        };
+    }
+
+    fn push(&mut self, memory: &mut MemoryMap, value: u8) {
+        memory.set_byte(0, self.registers.stack_pointer, value);
+        self.step_stack_pointer(u16::wrapping_sub);
+    }
+
+    fn pull(&mut self, memory: &mut MemoryMap) -> u8 {
+        self.step_stack_pointer(u16::wrapping_add);
+        memory.get_byte(0, self.registers.stack_pointer)
     }
 
     // ======================== //
@@ -51,9 +117,8 @@ impl Cpu {
     // ======================== //
 
     /// Fetch the next instruction pointed to by the program counter and bank.
-    fn fetch_instruction(&self, memory: &MemoryMap) -> Instruction {
-        let opcode = self.get_instruction_arg(memory, 0);
-
+    /// Decode the instruction starting with `opcode`, reading its operands.
+    fn fetch_instruction(&self, opcode: u8, memory: &mut MemoryMap) -> Instruction {
         use Instruction::*;
         match opcode {
             0x18 => ClearCarry,
@@ -61,6 +126,13 @@ impl Cpu {
             0x4c => Jump(self.get_arg_absolute(memory)),
             0x5c => Jump(self.get_arg_absolute_long(memory)),
 
+            0x20 => JumpToSubroutine(self.get_arg_absolute(memory)),
+            0x22 => JumpToSubroutine(self.get_arg_absolute_long(memory)),
+            0x60 => ReturnFromSubroutine,
+            0x6b => ReturnFromSubroutineLong,
+
+            0x40 => ReturnFromInterrupt,
+
             0x78 => DisableInterruptRequests,
 
             0x9c => StoreZero(self.get_arg_absolute(memory)),
@@ -79,14 +151,14 @@ impl Cpu {
         }
     }
 
-    fn get_instruction_arg(&self, memory: &MemoryMap, delta: u16) -> u8 {
+    fn get_instruction_arg(&self, memory: &mut MemoryMap, delta: u16) -> u8 {
         memory.get_byte(
             self.registers.program_bank,
             self.registers.program_counter + delta,
         )
     }
 
-    fn get_arg_absolute(&self, memory: &MemoryMap) -> Address {
+    fn get_arg_absolute(&self, memory: &mut MemoryMap) -> Address {
         let low = self.get_instruction_arg(memory, 1);
         let high = self.get_instruction_arg(memory, 2);
         let addr = u16::from_le_bytes([low, high]);
@@ -94,7 +166,7 @@ impl Cpu {
         Address::Absolute { addr }
     }
 
-    fn get_arg_absolute_long(&self, memory: &MemoryMap) -> Address {
+    fn get_arg_absolute_long(&self, memory: &mut MemoryMap) -> Address {
         let low = self.get_instruction_arg(memory, 1);
         let high = self.get_instruction_arg(memory, 2);
         let addr = u16::from_le_bytes([low, high]);
@@ -104,12 +176,12 @@ impl Cpu {
         Address::AbsoluteLong { bank, addr }
     }
 
-    fn get_arg_immediate_8bit(&self, memory: &MemoryMap) -> Address {
+    fn get_arg_immediate_8bit(&self, memory: &mut MemoryMap) -> Address {
         let data = self.get_instruction_arg(memory, 1);
         Address::Immediate8 { data }
     }
 
-    fn get_arg_immediate_16bit(&self, memory: &MemoryMap) -> Address {
+    fn get_arg_immediate_16bit(&self, memory: &mut MemoryMap) -> Address {
         let low = self.get_instruction_arg(memory, 1);
         let high = self.get_instruction_arg(memory, 2);
         let data = u16::from_le_bytes([low, high]);
@@ -127,7 +199,13 @@ impl Cpu {
         match instruction {
             Jump(address) => self.jump(address),
 
-            DisableInterruptRequests => self.registers.processor_status.set_irq(false),
+            JumpToSubroutine(address) => self.jump_to_subroutine(memory, address),
+            ReturnFromSubroutine => self.return_from_subroutine(memory, false),
+            ReturnFromSubroutineLong => self.return_from_subroutine(memory, true),
+
+            ReturnFromInterrupt => self.return_from_interrupt(memory),
+
+            DisableInterruptRequests => self.registers.processor_status.set_irq(true),
 
             ClearCarry => self.registers.processor_status.set_carry(false),
 
@@ -170,7 +248,7 @@ impl Cpu {
     }
 
     /// Returns the data pointed to by an address
-    fn get_data(&self, memory: &MemoryMap, address: Address, wide: bool) -> u16 {
+    fn get_data(&self, memory: &mut MemoryMap, address: Address, wide: bool) -> u16 {
         use Address::*;
         match address {
             Immediate8 { data } => data as u16,
@@ -201,7 +279,55 @@ impl Cpu {
         self.registers.program_counter = addr;
     }
 
-    fn load_accumulator(&mut self, memory: &MemoryMap, address: Address) {
+    /// Push the address of the instruction's last byte, and its bank for a long address, then
+    /// jump to the subroutine. A short address stays in the program bank.
+    fn jump_to_subroutine(&mut self, memory: &mut MemoryMap, address: Address) {
+        let bank = match address {
+            Address::AbsoluteLong { bank, .. } => {
+                self.push(memory, self.registers.program_bank);
+                bank
+            }
+            _ => self.registers.program_bank,
+        };
+
+        let [low, high] = self.registers.program_counter.wrapping_sub(1).to_le_bytes();
+        self.push(memory, high);
+        self.push(memory, low);
+
+        let (_, addr) = self.raw_address(address);
+        self.registers.program_bank = bank;
+        self.registers.program_counter = addr;
+    }
+
+    /// Return past the address pushed by JSR, or by JSL when `long`.
+    fn return_from_subroutine(&mut self, memory: &mut MemoryMap, long: bool) {
+        let low = self.pull(memory);
+        let high = self.pull(memory);
+        self.registers.program_counter = u16::from_le_bytes([low, high]).wrapping_add(1);
+
+        if long {
+            self.registers.program_bank = self.pull(memory);
+        }
+    }
+
+    /// Restore the status and program counter pushed by an interrupt.
+    fn return_from_interrupt(&mut self, memory: &mut MemoryMap) {
+        self.registers.processor_status.0 = self.pull(memory);
+        if self.registers.emulation {
+            self.registers.processor_status.set_accumulator(true);
+            self.registers.processor_status.set_index(true);
+        }
+
+        let low = self.pull(memory);
+        let high = self.pull(memory);
+        self.registers.program_counter = u16::from_le_bytes([low, high]);
+
+        if !self.registers.emulation {
+            self.registers.program_bank = self.pull(memory);
+        }
+    }
+
+    fn load_accumulator(&mut self, memory: &mut MemoryMap, address: Address) {
         let wide = !self.registers.processor_status.get_accumulator();
         self.registers.accumulator = self.get_data(memory, address, wide);
     }
@@ -228,6 +354,15 @@ impl Cpu {
     }
 }
 
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum Interrupt {
+    /// Non-maskable interrupt, raised when V-blank begins
+    Nmi,
+
+    /// Interrupt request, raised by the H/V timer
+    Irq,
+}
+
 #[derive(Debug)]
 enum Address {
     /// DBR | addr
@@ -376,6 +511,18 @@ enum Instruction {
     /// JMP, jump to address
     Jump(Address),
 
+    /// JSR and JSL, jump to a subroutine
+    JumpToSubroutine(Address),
+
+    /// RTS, return from subroutine
+    ReturnFromSubroutine,
+
+    /// RTL, return from subroutine long
+    ReturnFromSubroutineLong,
+
+    /// RTI, return from interrupt
+    ReturnFromInterrupt,
+
     // ========== //
     // Load/Store //
     // ========== //
@@ -410,6 +557,7 @@ impl Instruction {
 
         match self {
             Jump(addr) 
+                | JumpToSubroutine(addr) 
                 | StoreZero(addr) 
                 | LoadAccumulator(addr) 
                 | StoreAccumulator(addr) 
@@ -417,7 +565,13 @@ impl Instruction {
 
             ResetStatusFlags(_) => 2,
 
-            DisableInterruptRequests | ClearCarry | ExchangeCarryEmulator => 1,
+            DisableInterruptRequests
+                | ClearCarry
+                | ExchangeCarryEmulator
+                | ReturnFromSubroutine
+                | ReturnFromSubroutineLong
+                | ReturnFromInterrupt
+                => 1,
         }
     }
 }
@@ -0,0 +1,477 @@
+//! Turns machine code back into 65C816 assembly, for debugging.
+
+use crate::symbols::Symbols;
+
+/// How an instruction finds its operand, as far as printing it goes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Mode {
+    Implied,
+    Accumulator,
+    /// One byte, like REP and SEP
+    Immediate8,
+    /// Sized by the M flag
+    ImmediateM,
+    /// Sized by the X flag
+    ImmediateX,
+    Relative,
+    RelativeLong,
+    Direct,
+    DirectX,
+    DirectY,
+    DirectIndirect,
+    DirectIndexedIndirect,
+    DirectIndirectIndexed,
+    DirectIndirectLong,
+    DirectIndirectLongIndexed,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    AbsoluteLong,
+    AbsoluteLongX,
+    AbsoluteIndirect,
+    AbsoluteIndexedIndirect,
+    AbsoluteIndirectLong,
+    StackRelative,
+    StackRelativeIndirectIndexed,
+    BlockMove,
+}
+
+use Mode::*;
+
+/// Mnemonic and addressing mode of every opcode.
+const OPCODES: [(&str, Mode); 256] = [
+    // $00
+    ("BRK", Immediate8),
+    ("ORA", DirectIndexedIndirect),
+    ("COP", Immediate8),
+    ("ORA", StackRelative),
+    ("TSB", Direct),
+    ("ORA", Direct),
+    ("ASL", Direct),
+    ("ORA", DirectIndirectLong),
+    ("PHP", Implied),
+    ("ORA", ImmediateM),
+    ("ASL", Accumulator),
+    ("PHD", Implied),
+    ("TSB", Absolute),
+    ("ORA", Absolute),
+    ("ASL", Absolute),
+    ("ORA", AbsoluteLong),
+    // $10
+    ("BPL", Relative),
+    ("ORA", DirectIndirectIndexed),
+    ("ORA", DirectIndirect),
+    ("ORA", StackRelativeIndirectIndexed),
+    ("TRB", Direct),
+    ("ORA", DirectX),
+    ("ASL", DirectX),
+    ("ORA", DirectIndirectLongIndexed),
+    ("CLC", Implied),
+    ("ORA", AbsoluteY),
+    ("INC", Accumulator),
+    ("TCS", Implied),
+    ("TRB", Absolute),
+    ("ORA", AbsoluteX),
+    ("ASL", AbsoluteX),
+    ("ORA", AbsoluteLongX),
+    // $20
+    ("JSR", Absolute),
+    ("AND", DirectIndexedIndirect),
+    ("JSL", AbsoluteLong),
+    ("AND", StackRelative),
+    ("BIT", Direct),
+    ("AND", Direct),
+    ("ROL", Direct),
+    ("AND", DirectIndirectLong),
+    ("PLP", Implied),
+    ("AND", ImmediateM),
+    ("ROL", Accumulator),
+    ("PLD", Implied),
+    ("BIT", Absolute),
+    ("AND", Absolute),
+    ("ROL", Absolute),
+    ("AND", AbsoluteLong),
+    // $30
+    ("BMI", Relative),
+    ("AND", DirectIndirectIndexed),
+    ("AND", DirectIndirect),
+    ("AND", StackRelativeIndirectIndexed),
+    ("BIT", DirectX),
+    ("AND", DirectX),
+    ("ROL", DirectX),
+    ("AND", DirectIndirectLongIndexed),
+    ("SEC", Implied),
+    ("AND", AbsoluteY),
+    ("DEC", Accumulator),
+    ("TSC", Implied),
+    ("BIT", AbsoluteX),
+    ("AND", AbsoluteX),
+    ("ROL", AbsoluteX),
+    ("AND", AbsoluteLongX),
+    // $40
+    ("RTI", Implied),
+    ("EOR", DirectIndexedIndirect),
+    ("WDM", Immediate8),
+    ("EOR", StackRelative),
+    ("MVP", BlockMove),
+    ("EOR", Direct),
+    ("LSR", Direct),
+    ("EOR", DirectIndirectLong),
+    ("PHA", Implied),
+    ("EOR", ImmediateM),
+    ("LSR", Accumulator),
+    ("PHK", Implied),
+    ("JMP", Absolute),
+    ("EOR", Absolute),
+    ("LSR", Absolute),
+    ("EOR", AbsoluteLong),
+    // $50
+    ("BVC", Relative),
+    ("EOR", DirectIndirectIndexed),
+    ("EOR", DirectIndirect),
+    ("EOR", StackRelativeIndirectIndexed),
+    ("MVN", BlockMove),
+    ("EOR", DirectX),
+    ("LSR", DirectX),
+    ("EOR", DirectIndirectLongIndexed),
+    ("CLI", Implied),
+    ("EOR", AbsoluteY),
+    ("PHY", Implied),
+    ("TCD", Implied),
+    ("JML", AbsoluteLong),
+    ("EOR", AbsoluteX),
+    ("LSR", AbsoluteX),
+    ("EOR", AbsoluteLongX),
+    // $60
+    ("RTS", Implied),
+    ("ADC", DirectIndexedIndirect),
+    ("PER", RelativeLong),
+    ("ADC", StackRelative),
+    ("STZ", Direct),
+    ("ADC", Direct),
+    ("ROR", Direct),
+    ("ADC", DirectIndirectLong),
+    ("PLA", Implied),
+    ("ADC", ImmediateM),
+    ("ROR", Accumulator),
+    ("RTL", Implied),
+    ("JMP", AbsoluteIndirect),
+    ("ADC", Absolute),
+    ("ROR", Absolute),
+    ("ADC", AbsoluteLong),
+    // $70
+    ("BVS", Relative),
+    ("ADC", DirectIndirectIndexed),
+    ("ADC", DirectIndirect),
+    ("ADC", StackRelativeIndirectIndexed),
+    ("STZ", DirectX),
+    ("ADC", DirectX),
+    ("ROR", DirectX),
+    ("ADC", DirectIndirectLongIndexed),
+    ("SEI", Implied),
+    ("ADC", AbsoluteY),
+    ("PLY", Implied),
+    ("TDC", Implied),
+    ("JMP", AbsoluteIndexedIndirect),
+    ("ADC", AbsoluteX),
+    ("ROR", AbsoluteX),
+    ("ADC", AbsoluteLongX),
+    // $80
+    ("BRA", Relative),
+    ("STA", DirectIndexedIndirect),
+    ("BRL", RelativeLong),
+    ("STA", StackRelative),
+    ("STY", Direct),
+    ("STA", Direct),
+    ("STX", Direct),
+    ("STA", DirectIndirectLong),
+    ("DEY", Implied),
+    ("BIT", ImmediateM),
+    ("TXA", Implied),
+    ("PHB", Implied),
+    ("STY", Absolute),
+    ("STA", Absolute),
+    ("STX", Absolute),
+    ("STA", AbsoluteLong),
+    // $90
+    ("BCC", Relative),
+    ("STA", DirectIndirectIndexed),
+    ("STA", DirectIndirect),
+    ("STA", StackRelativeIndirectIndexed),
+    ("STY", DirectX),
+    ("STA", DirectX),
+    ("STX", DirectY),
+    ("STA", DirectIndirectLongIndexed),
+    ("TYA", Implied),
+    ("STA", AbsoluteY),
+    ("TXS", Implied),
+    ("TXY", Implied),
+    ("STZ", Absolute),
+    ("STA", AbsoluteX),
+    ("STZ", AbsoluteX),
+    ("STA", AbsoluteLongX),
+    // $A0
+    ("LDY", ImmediateX),
+    ("LDA", DirectIndexedIndirect),
+    ("LDX", ImmediateX),
+    ("LDA", StackRelative),
+    ("LDY", Direct),
+    ("LDA", Direct),
+    ("LDX", Direct),
+    ("LDA", DirectIndirectLong),
+    ("TAY", Implied),
+    ("LDA", ImmediateM),
+    ("TAX", Implied),
+    ("PLB", Implied),
+    ("LDY", Absolute),
+    ("LDA", Absolute),
+    ("LDX", Absolute),
+    ("LDA", AbsoluteLong),
+    // $B0
+    ("BCS", Relative),
+    ("LDA", DirectIndirectIndexed),
+    ("LDA", DirectIndirect),
+    ("LDA", StackRelativeIndirectIndexed),
+    ("LDY", DirectX),
+    ("LDA", DirectX),
+    ("LDX", DirectY),
+    ("LDA", DirectIndirectLongIndexed),
+    ("CLV", Implied),
+    ("LDA", AbsoluteY),
+    ("TSX", Implied),
+    ("TYX", Implied),
+    ("LDY", AbsoluteX),
+    ("LDA", AbsoluteX),
+    ("LDX", AbsoluteY),
+    ("LDA", AbsoluteLongX),
+    // $C0
+    ("CPY", ImmediateX),
+    ("CMP", DirectIndexedIndirect),
+    ("REP", Immediate8),
+    ("CMP", StackRelative),
+    ("CPY", Direct),
+    ("CMP", Direct),
+    ("DEC", Direct),
+    ("CMP", DirectIndirectLong),
+    ("INY", Implied),
+    ("CMP", ImmediateM),
+    ("DEX", Implied),
+    ("WAI", Implied),
+    ("CPY", Absolute),
+    ("CMP", Absolute),
+    ("DEC", Absolute),
+    ("CMP", AbsoluteLong),
+    // $D0
+    ("BNE", Relative),
+    ("CMP", DirectIndirectIndexed),
+    ("CMP", DirectIndirect),
+    ("CMP", StackRelativeIndirectIndexed),
+    ("PEI", DirectIndirect),
+    ("CMP", DirectX),
+    ("DEC", DirectX),
+    ("CMP", DirectIndirectLongIndexed),
+    ("CLD", Implied),
+    ("CMP", AbsoluteY),
+    ("PHX", Implied),
+    ("STP", Implied),
+    ("JML", AbsoluteIndirectLong),
+    ("CMP", AbsoluteX),
+    ("DEC", AbsoluteX),
+    ("CMP", AbsoluteLongX),
+    // $E0
+    ("CPX", ImmediateX),
+    ("SBC", DirectIndexedIndirect),
+    ("SEP", Immediate8),
+    ("SBC", StackRelative),
+    ("CPX", Direct),
+    ("SBC", Direct),
+    ("INC", Direct),
+    ("SBC", DirectIndirectLong),
+    ("INX", Implied),
+    ("SBC", ImmediateM),
+    ("NOP", Implied),
+    ("XBA", Implied),
+    ("CPX", Absolute),
+    ("SBC", Absolute),
+    ("INC", Absolute),
+    ("SBC", AbsoluteLong),
+    // $F0
+    ("BEQ", Relative),
+    ("SBC", DirectIndirectIndexed),
+    ("SBC", DirectIndirect),
+    ("SBC", StackRelativeIndirectIndexed),
+    ("PEA", Absolute),
+    ("SBC", DirectX),
+    ("INC", DirectX),
+    ("SBC", DirectIndirectLongIndexed),
+    ("SED", Implied),
+    ("SBC", AbsoluteY),
+    ("PLX", Implied),
+    ("XCE", Implied),
+    ("JSR", AbsoluteIndexedIndirect),
+    ("SBC", AbsoluteX),
+    ("INC", AbsoluteX),
+    ("SBC", AbsoluteLongX),
+];
+
+impl Mode {
+    /// Bytes of operand, given whether the accumulator and index registers are 8-bit.
+    fn operand_size(self, accumulator_8bit: bool, index_8bit: bool) -> u8 {
+        match self {
+            Implied | Accumulator => 0,
+            ImmediateM => 2 - accumulator_8bit as u8,
+            ImmediateX => 2 - index_8bit as u8,
+            Immediate8
+            | Relative
+            | Direct
+            | DirectX
+            | DirectY
+            | DirectIndirect
+            | DirectIndexedIndirect
+            | DirectIndirectIndexed
+            | DirectIndirectLong
+            | DirectIndirectLongIndexed
+            | StackRelative
+            | StackRelativeIndirectIndexed => 1,
+            RelativeLong
+            | Absolute
+            | AbsoluteX
+            | AbsoluteY
+            | AbsoluteIndirect
+            | AbsoluteIndexedIndirect
+            | AbsoluteIndirectLong
+            | BlockMove => 2,
+            AbsoluteLong | AbsoluteLongX => 3,
+        }
+    }
+}
+
+/// The mnemonic of an opcode, like "JSR".
+pub(crate) fn mnemonic(opcode: u8) -> &'static str {
+    OPCODES[usize::from(opcode)].0
+}
+
+/// The size of an instruction in bytes, opcode included.
+pub(crate) fn instruction_size(opcode: u8, accumulator_8bit: bool, index_8bit: bool) -> u8 {
+    1 + OPCODES[usize::from(opcode)]
+        .1
+        .operand_size(accumulator_8bit, index_8bit)
+}
+
+/// Disassemble the instruction in `bytes`, which is at `address`. Only as many bytes as the
+/// instruction takes are used. Jumps, branches and long addresses are shown by their label when
+/// they have one. Returns the text and the instruction's size.
+pub(crate) fn disassemble(
+    bytes: [u8; 4],
+    address: u32,
+    accumulator_8bit: bool,
+    index_8bit: bool,
+    symbols: &Symbols,
+) -> (String, u8) {
+    let (mnemonic, mode) = OPCODES[usize::from(bytes[0])];
+    let size = instruction_size(bytes[0], accumulator_8bit, index_8bit);
+
+    let byte = bytes[1];
+    let word = u16::from_le_bytes([bytes[1], bytes[2]]);
+    let long = u32::from_le_bytes([bytes[1], bytes[2], bytes[3], 0]);
+
+    // Branches stay in the bank, relative to the next instruction
+    let next = (address as u16).wrapping_add(u16::from(size));
+    let bank = address & 0xff0000;
+    let label = |address: u32| symbols.label(address).map(str::to_string);
+
+    let operand = match mode {
+        Relative => label(bank | u32::from(next.wrapping_add(byte as i8 as u16))),
+        RelativeLong => label(bank | u32::from(next.wrapping_add(word))),
+        Absolute if matches!(mnemonic, "JMP" | "JSR") => label(bank | u32::from(word)),
+        AbsoluteLong => label(long),
+        AbsoluteLongX => label(long).map(|label| format!("{},X", label)),
+        _ => None,
+    };
+
+    let operand = operand.unwrap_or_else(|| match mode {
+        Implied => String::new(),
+        Accumulator => "A".to_string(),
+        Immediate8 => format!("#${:02X}", byte),
+        ImmediateM | ImmediateX if size == 2 => format!("#${:02X}", byte),
+        ImmediateM | ImmediateX => format!("#${:04X}", word),
+        Relative => format!("${:04X}", next.wrapping_add(byte as i8 as u16)),
+        RelativeLong => format!("${:04X}", next.wrapping_add(word)),
+        Direct => format!("${:02X}", byte),
+        DirectX => format!("${:02X},X", byte),
+        DirectY => format!("${:02X},Y", byte),
+        DirectIndirect => format!("(${:02X})", byte),
+        DirectIndexedIndirect => format!("(${:02X},X)", byte),
+        DirectIndirectIndexed => format!("(${:02X}),Y", byte),
+        DirectIndirectLong => format!("[${:02X}]", byte),
+        DirectIndirectLongIndexed => format!("[${:02X}],Y", byte),
+        Absolute => format!("${:04X}", word),
+        AbsoluteX => format!("${:04X},X", word),
+        AbsoluteY => format!("${:04X},Y", word),
+        AbsoluteLong => format!("${:06X}", long),
+        AbsoluteLongX => format!("${:06X},X", long),
+        AbsoluteIndirect => format!("(${:04X})", word),
+        AbsoluteIndexedIndirect => format!("(${:04X},X)", word),
+        AbsoluteIndirectLong => format!("[${:04X}]", word),
+        StackRelative => format!("${:02X},S", byte),
+        StackRelativeIndirectIndexed => format!("(${:02X},S),Y", byte),
+        // The destination bank comes first in machine code, but last in assembly
+        BlockMove => format!("${:02X},${:02X}", bytes[2], bytes[1]),
+    });
+
+    let text = if operand.is_empty() {
+        mnemonic.to_string()
+    } else {
+        format!("{} {}", mnemonic, operand)
+    };
+    (text, size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instructions() {
+        let mut symbols = Symbols::new();
+        let disassemble = |bytes, m, x| disassemble(bytes, 0x008000, m, x, &symbols);
+
+        assert_eq!(
+            disassemble([0xa9, 0x34, 0x12, 0], true, true),
+            ("LDA #$34".into(), 2)
+        );
+        assert_eq!(
+            disassemble([0xa9, 0x34, 0x12, 0], false, true),
+            ("LDA #$1234".into(), 3)
+        );
+        assert_eq!(
+            disassemble([0xa2, 0x34, 0x12, 0], true, false),
+            ("LDX #$1234".into(), 3)
+        );
+        assert_eq!(
+            disassemble([0x22, 0x56, 0x34, 0x12], true, true),
+            ("JSL $123456".into(), 4)
+        );
+        assert_eq!(
+            disassemble([0xd0, 0xfe, 0, 0], true, true),
+            ("BNE $8000".into(), 2)
+        );
+        assert_eq!(
+            disassemble([0xb7, 0x10, 0, 0], true, true),
+            ("LDA [$10],Y".into(), 2)
+        );
+        assert_eq!(
+            disassemble([0x54, 0x7e, 0x7f, 0], true, true),
+            ("MVN $7F,$7E".into(), 3)
+        );
+        assert_eq!(disassemble([0xfb, 0, 0, 0], true, true), ("XCE".into(), 1));
+
+        symbols.insert("loop", 0x008000);
+        symbols.insert("far", 0x123456);
+        let disassemble = |bytes| super::disassemble(bytes, 0x008000, true, true, &symbols).0;
+        assert_eq!(disassemble([0xd0, 0xfe, 0, 0]), "BNE loop");
+        assert_eq!(disassemble([0x20, 0x00, 0x80, 0]), "JSR loop");
+        assert_eq!(disassemble([0xbf, 0x56, 0x34, 0x12]), "LDA far,X");
+        assert_eq!(disassemble([0xad, 0x00, 0x80, 0]), "LDA $8000");
+    }
+}
@@ -1,3 +1,4 @@
+use std::fmt;
 
 #[derive(Default)]
 pub struct CpuRegisters {
@@ -17,6 +18,58 @@ pub struct CpuRegisters {
     pub emulation: bool
 }
 
+save_state! {
+    CpuRegisters {
+        accumulator, index_x, index_y, stack_pointer, data_bank, direct_page, program_bank,
+        program_counter, processor_status, emulation
+    }
+}
+
+impl CpuRegisters {
+    /// The 24-bit address of the next instruction.
+    pub fn program_address(&self) -> u32 {
+        u32::from(self.program_bank) << 16 | u32::from(self.program_counter)
+    }
+
+    /// Are the accumulator and index registers 8-bit?
+    pub fn register_sizes(&self) -> (bool, bool) {
+        (
+            self.emulation || self.processor_status.get_accumulator(),
+            self.emulation || self.processor_status.get_index(),
+        )
+    }
+}
+
+impl fmt::Display for CpuRegisters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Set flags in upper case, clear ones in lower case
+        let flags: String = "NVMXDIZC"
+            .chars()
+            .enumerate()
+            .map(|(index, flag)| {
+                if self.processor_status.0 & (0x80 >> index) != 0 {
+                    flag
+                } else {
+                    flag.to_ascii_lowercase()
+                }
+            })
+            .collect();
+
+        write!(
+            f,
+            "A={:04X} X={:04X} Y={:04X} S={:04X} D={:04X} DB={:02X} P={} E={}",
+            self.accumulator,
+            self.index_x,
+            self.index_y,
+            self.stack_pointer,
+            self.direct_page,
+            self.data_bank,
+            flags,
+            self.emulation as u8,
+        )
+    }
+}
+
 macro_rules! impl_register {
     (register $register:ty {type=$type:ty; $(($offset:expr, $get:ident, $set:ident);)+}) => {
         impl $register {
@@ -26,7 +79,7 @@ macro_rules! impl_register {
                 }
 
                 pub fn $set(&mut self, state: bool) {
-                    self.0 |= (state as $type) << $offset;
+                    self.0 = (self.0 & !(1 << $offset)) | (state as $type) << $offset;
                 }
             )+
         }
@@ -37,6 +90,8 @@ macro_rules! impl_register {
 #[derive(Default)]
 pub struct ProcessorStatus(pub u8);
 
+save_state! { ProcessorStatus { 0 } }
+
 impl_register! (
     register ProcessorStatus {
         type = u8;
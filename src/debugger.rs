@@ -0,0 +1,714 @@
+//! An interactive debugger for the CPU, driven by commands typed at a prompt.
+
+use crate::cpu::disassembler::*;
+use crate::*;
+use std::io::{self, BufRead, Write};
+use std::panic::{self, AssertUnwindSafe};
+
+#[cfg(feature = "gdb")]
+mod gdb;
+
+const HELP: &str = "\
+step [n]             execute one or n instructions
+next                 execute one instruction, stepping over JSR and JSL
+finish               run until the current subroutine returns
+continue             run until a breakpoint
+break <address>      set a breakpoint on an address or symbol
+delete <n>           delete a breakpoint
+breakpoints          list the breakpoints
+watch [rwx] <range> [<op> <v>]
+                     watch reads, writes or execution of an address or range like
+                     7E0010..7E001F, when the value is ==, !=, < or > v. Watches writes by default
+unwatch <n>          delete a watchpoint
+watchpoints          list the watchpoints
+registers            show the registers
+set <register> <v>   set a, x, y, s, d, db, pb, pc, p or e
+x <address> [n]      dump n bytes of memory
+dis [address] [n]    disassemble n instructions
+quit                 leave the debugger
+Addresses are 24-bit hexadecimal, like $7E0010 or 7E:0010. An empty line repeats the last
+command.";
+
+/// Why the debugger stopped running the system.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    /// The requested instructions were executed
+    Done,
+
+    /// The program counter reached a breakpoint
+    Breakpoint(u32),
+
+    /// A watchpoint was triggered by the instruction at `pc`, or by DMA started from there
+    Watchpoint { pc: u32, hit: WatchHit },
+
+    /// The emulator panicked, usually at something it doesn't implement
+    Panic(String),
+}
+
+/// Wraps a system, stepping its CPU under the control of commands.
+pub struct Debugger<'a> {
+    snes: Snes<'a>,
+    breakpoints: Vec<u32>,
+    last_command: String,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(snes: Snes<'a>) -> Debugger<'a> {
+        Debugger {
+            snes,
+            breakpoints: Vec::new(),
+            last_command: String::new(),
+        }
+    }
+
+    pub fn snes(&mut self) -> &mut Snes<'a> {
+        &mut self.snes
+    }
+
+    /// Read commands from the standard input until it ends or `quit` is entered.
+    pub fn run(&mut self) -> io::Result<()> {
+        let stdin = io::stdin();
+        let mut stdout = io::stdout();
+        println!("{}", self.command("registers"));
+
+        loop {
+            print!("(snes) ");
+            stdout.flush()?;
+
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+
+            let line = line.trim();
+            if line == "quit" || line == "q" {
+                return Ok(());
+            }
+            println!("{}", self.command(line));
+        }
+    }
+
+    /// Run a command, returning what it prints.
+    pub fn command(&mut self, line: &str) -> String {
+        let line = if line.trim().is_empty() {
+            self.last_command.clone()
+        } else {
+            line.trim().to_string()
+        };
+        self.last_command = line.clone();
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let result = match words.as_slice() {
+            [] => Ok(String::new()),
+            ["step"] | ["s"] => Ok(self.step(1)),
+            ["step", count] | ["s", count] => parse_number(count).map(|count| self.step(count)),
+            ["next"] | ["n"] => Ok(self.next()),
+            ["finish"] | ["f"] => Ok(self.finish()),
+            ["continue"] | ["c"] => Ok(self.run_to_breakpoint()),
+            ["break", address] | ["b", address] => self.parse_address(address).map(|address| {
+                let index = self.add_breakpoint(address);
+                format!("Breakpoint {} at {}", index, self.name(address))
+            }),
+            ["delete", index] | ["d", index] => {
+                parse_number(index).and_then(|index| match self.remove_breakpoint(index as usize) {
+                    Some(_) => Ok(format!("Deleted breakpoint {}", index)),
+                    None => Err(format!("No breakpoint {}", index)),
+                })
+            }
+            ["breakpoints"] => Ok(self.list_breakpoints()),
+            ["watch", arguments @ ..] => self.watch(arguments),
+            ["unwatch", index] => parse_number(index).and_then(|index| {
+                match self.snes.remove_watchpoint(index as usize) {
+                    Some(_) => Ok(format!("Deleted watchpoint {}", index)),
+                    None => Err(format!("No watchpoint {}", index)),
+                }
+            }),
+            ["watchpoints"] => Ok(self.list_watchpoints()),
+            ["registers"] | ["r"] => Ok(self.registers()),
+            ["set", register, value] => self.set_register(register, value),
+            ["x", address] => self
+                .parse_address(address)
+                .map(|address| self.dump(address, 64)),
+            ["x", address, length] => self
+                .parse_address(address)
+                .and_then(|address| parse_number(length).map(|length| self.dump(address, length))),
+            ["dis"] => Ok(self.disassemble(self.program_address(), 10)),
+            ["dis", address] => self
+                .parse_address(address)
+                .map(|address| self.disassemble(address, 10)),
+            ["dis", address, count] => self.parse_address(address).and_then(|address| {
+                parse_number(count).map(|count| self.disassemble(address, count))
+            }),
+            ["help"] | ["h"] => Ok(HELP.to_string()),
+            _ => Err(format!("Unknown command: {}. Try help", line)),
+        };
+
+        result.unwrap_or_else(|error| error)
+    }
+
+    // ========= //
+    // Execution //
+    // ========= //
+
+    /// The 24-bit address of the next instruction.
+    fn program_address(&self) -> u32 {
+        self.snes.core.registers.program_address()
+    }
+
+    /// Step the system until `done` returns true after an instruction, a breakpoint or
+    /// watchpoint is reached or the emulator panics. `done` is given the opcode that was executed.
+    fn run_until(&mut self, mut done: impl FnMut(&Snes, u8) -> bool) -> StopReason {
+        let snes = &mut self.snes;
+        let breakpoints = &self.breakpoints;
+        snes.take_watch_hit();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| loop {
+            let pc = snes.core.registers.program_address();
+            let opcode = snes.memory.peek((pc >> 16) as u8, pc as u16).unwrap_or(0);
+            snes.step();
+
+            // Execute watchpoints trigger before the instruction they watch
+            let address = snes.core.registers.program_address();
+            if !snes.watchpoints().is_empty() {
+                let (bank, addr) = ((address >> 16) as u8, address as u16);
+                let next = snes.memory.peek(bank, addr).unwrap_or(0);
+                snes.memory.watches.check(Access::Execute, bank, addr, next);
+            }
+
+            if let Some(hit) = snes.take_watch_hit() {
+                let pc = if hit.access == Access::Execute {
+                    address
+                } else {
+                    pc
+                };
+                return StopReason::Watchpoint { pc, hit };
+            }
+
+            if done(snes, opcode) {
+                return StopReason::Done;
+            }
+
+            if breakpoints.contains(&address) {
+                return StopReason::Breakpoint(address);
+            }
+        }));
+
+        result.unwrap_or_else(|panic| {
+            let message = panic
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| {
+                    panic
+                        .downcast_ref::<&str>()
+                        .map(|message| message.to_string())
+                })
+                .unwrap_or_default();
+            StopReason::Panic(message)
+        })
+    }
+
+    /// Describe why execution stopped, and where.
+    fn stopped(&mut self, reason: StopReason) -> String {
+        let position = self.disassemble(self.program_address(), 1);
+        match reason {
+            StopReason::Done => position,
+            StopReason::Breakpoint(address) => {
+                format!("Breakpoint at {}\n{}", self.name(address), position)
+            }
+            StopReason::Watchpoint { pc, hit } => {
+                let access = match hit.access {
+                    Access::Read => "read of",
+                    Access::Write => "write of",
+                    Access::Execute => "execution of",
+                };
+                format!(
+                    "Watchpoint {}: {} ${:02X} at {} by {}\n{}",
+                    hit.index,
+                    access,
+                    hit.value,
+                    self.name(hit.address),
+                    self.name(pc),
+                    position
+                )
+            }
+            StopReason::Panic(message) => format!("Emulator stopped: {}\n{}", message, position),
+        }
+    }
+
+    /// Stop whenever the program counter reaches an address, returning the breakpoint's index.
+    pub fn add_breakpoint(&mut self, address: u32) -> usize {
+        self.breakpoints.push(address);
+        self.breakpoints.len() - 1
+    }
+
+    /// Delete a breakpoint, moving the ones after it down an index.
+    pub fn remove_breakpoint(&mut self, index: usize) -> Option<u32> {
+        if index < self.breakpoints.len() {
+            Some(self.breakpoints.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// Execute a number of instructions, stopping early at breakpoints and watchpoints.
+    pub fn step_instructions(&mut self, count: u32) -> StopReason {
+        let mut left = count;
+        self.run_until(|_, _| {
+            left = left.saturating_sub(1);
+            left == 0
+        })
+    }
+
+    /// Run until a breakpoint or watchpoint is reached.
+    pub fn resume(&mut self) -> StopReason {
+        self.run_until(|_, _| false)
+    }
+
+    fn step(&mut self, count: u32) -> String {
+        let reason = self.step_instructions(count);
+        self.stopped(reason)
+    }
+
+    /// Step, but run through subroutines called by JSR and JSL until they return.
+    fn next(&mut self) -> String {
+        let address = self.program_address();
+        let opcode = self.peek(address).unwrap_or(0);
+        if !matches!(mnemonic(opcode), "JSR" | "JSL") {
+            return self.step(1);
+        }
+
+        let (m, x) = self.snes.core.registers.register_sizes();
+        let size = instruction_size(opcode, m, x);
+        let return_address =
+            (address & 0xff0000) | (address as u16).wrapping_add(size.into()) as u32;
+        let stack_pointer = self.snes.core.registers.stack_pointer;
+
+        let reason = self.run_until(|snes, _| {
+            let registers = &snes.core.registers;
+            registers.program_address() == return_address
+                && registers.stack_pointer >= stack_pointer
+        });
+        self.stopped(reason)
+    }
+
+    /// Run until the subroutine or interrupt handler being executed returns.
+    fn finish(&mut self) -> String {
+        let stack_pointer = self.snes.core.registers.stack_pointer;
+        let reason = self.run_until(|snes, opcode| {
+            matches!(mnemonic(opcode), "RTS" | "RTL" | "RTI")
+                && snes.core.registers.stack_pointer > stack_pointer
+        });
+        self.stopped(reason)
+    }
+
+    fn run_to_breakpoint(&mut self) -> String {
+        let reason = self.resume();
+        self.stopped(reason)
+    }
+
+    // ========= //
+    // Registers //
+    // ========= //
+
+    fn registers(&mut self) -> String {
+        let text = self.snes.core.registers.to_string();
+        format!("{}\n{}", text, self.disassemble(self.program_address(), 1))
+    }
+
+    fn set_register(&mut self, register: &str, value: &str) -> Result<String, String> {
+        let value = parse_number(value)?;
+        let registers = &mut self.snes.core.registers;
+        match register.to_ascii_lowercase().as_str() {
+            "a" => registers.accumulator = value as u16,
+            "x" => registers.index_x = value as u16,
+            "y" => registers.index_y = value as u16,
+            "s" => registers.stack_pointer = value as u16,
+            "d" => registers.direct_page = value as u16,
+            "db" => registers.data_bank = value as u8,
+            "pb" => registers.program_bank = value as u8,
+            "pc" => registers.program_counter = value as u16,
+            "p" => registers.processor_status.0 = value as u8,
+            "e" => registers.emulation = value != 0,
+            _ => return Err(format!("Unknown register: {}", register)),
+        }
+        Ok(self.registers())
+    }
+
+    // ====== //
+    // Memory //
+    // ====== //
+
+    /// Read a byte without changing anything, if it is in RAM or ROM.
+    fn peek(&self, address: u32) -> Option<u8> {
+        self.snes.memory.peek((address >> 16) as u8, address as u16)
+    }
+
+    /// Write a byte without changing anything else, if it is in RAM.
+    fn poke(&mut self, address: u32, value: u8) -> Option<()> {
+        self.snes
+            .memory
+            .poke((address >> 16) as u8, address as u16, value)
+    }
+
+    fn dump(&mut self, address: u32, length: u32) -> String {
+        let mut lines = Vec::new();
+        for line in (0..length).step_by(16) {
+            let start = address.wrapping_add(line) & 0xffffff;
+            let bytes: Vec<Option<u8>> = (0..16.min(length - line))
+                .map(|offset| self.peek(start.wrapping_add(offset) & 0xffffff))
+                .collect();
+
+            // I/O ports and unmapped addresses are shown as --
+            let hex: Vec<String> = bytes
+                .iter()
+                .map(|byte| match byte {
+                    Some(byte) => format!("{:02X}", byte),
+                    None => "--".to_string(),
+                })
+                .collect();
+            let text: String = bytes
+                .iter()
+                .map(|&byte| match byte {
+                    Some(byte @ 0x20..=0x7e) => byte as char,
+                    _ => '.',
+                })
+                .collect();
+            lines.push(format!(
+                "{:02X}:{:04X}  {:<47}  {}",
+                start >> 16,
+                start & 0xffff,
+                hex.join(" "),
+                text
+            ));
+        }
+        lines.join("\n")
+    }
+
+    fn disassemble(&mut self, mut address: u32, count: u32) -> String {
+        let (mut m, mut x) = self.snes.core.registers.register_sizes();
+        let emulation = self.snes.core.registers.emulation;
+
+        let mut lines = Vec::new();
+        for _ in 0..count {
+            let bank = address & 0xff0000;
+            let bytes = [0, 1, 2, 3]
+                .map(|offset| self.peek(bank | (address as u16).wrapping_add(offset) as u32))
+                .map(|byte| byte.unwrap_or(0));
+            let (text, size) = disassemble(bytes, address, m, x, &self.snes.symbols);
+
+            let hex: Vec<String> = bytes[..usize::from(size)]
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+            let marker = if address == self.program_address() {
+                '>'
+            } else {
+                ' '
+            };
+            let label = match self.snes.symbols.label(address) {
+                Some(label) => format!("{}:\n", label),
+                None => String::new(),
+            };
+            lines.push(format!(
+                "{}{} {:02X}:{:04X}  {:<12} {}",
+                label,
+                marker,
+                address >> 16,
+                address & 0xffff,
+                hex.join(" "),
+                text
+            ));
+
+            // Follow changes to the register sizes, as far as they can be known
+            match (bytes[0], emulation) {
+                (0xc2, false) => {
+                    m &= bytes[1] & 0x20 == 0;
+                    x &= bytes[1] & 0x10 == 0;
+                }
+                (0xe2, _) => {
+                    m |= bytes[1] & 0x20 != 0;
+                    x |= bytes[1] & 0x10 != 0;
+                }
+                _ => {}
+            }
+
+            address = bank | (address as u16).wrapping_add(size.into()) as u32;
+        }
+        lines.join("\n")
+    }
+
+    // ========= //
+    // Addresses //
+    // ========= //
+
+    /// Parse a symbol, or a 24-bit hexadecimal address like $7E0010, 0x7E0010 or 7E:0010.
+    fn parse_address(&self, text: &str) -> Result<u32, String> {
+        if let Some(address) = self.snes.symbols.address(text) {
+            return Ok(address);
+        }
+
+        let digits = text
+            .trim_start_matches('$')
+            .trim_start_matches("0x")
+            .replace(':', "");
+        match u32::from_str_radix(&digits, 16) {
+            Ok(address) if address <= 0xffffff => Ok(address),
+            _ => Err(format!("Not an address or symbol: {}", text)),
+        }
+    }
+
+    /// An address with its label, if it has one.
+    fn name(&self, address: u32) -> String {
+        match self.snes.symbols.label(address) {
+            Some(label) => format!("{:02X}:{:04X} <{}>", address >> 16, address & 0xffff, label),
+            None => format!("{:02X}:{:04X}", address >> 16, address & 0xffff),
+        }
+    }
+
+    /// Parse and set a watchpoint, like `rw 7E0010..7E001F == 3`.
+    fn watch(&mut self, mut arguments: &[&str]) -> Result<String, String> {
+        let (mut read, mut write, mut execute) = (false, true, false);
+        if let Some(kinds) = arguments.first() {
+            if kinds.chars().all(|kind| "rwx".contains(kind)) {
+                read = kinds.contains('r');
+                write = kinds.contains('w');
+                execute = kinds.contains('x');
+                arguments = &arguments[1..];
+            }
+        }
+
+        let (range, condition) = match arguments {
+            [range] => (range, Condition::Any),
+            [range, operator, value] => {
+                let value = parse_number(value)? as u8;
+                let condition = match *operator {
+                    "==" => Condition::Equal(value),
+                    "!=" => Condition::NotEqual(value),
+                    "<" => Condition::Less(value),
+                    ">" => Condition::Greater(value),
+                    _ => return Err(format!("Unknown comparison: {}", operator)),
+                };
+                (range, condition)
+            }
+            _ => return Err("Usage: watch [rwx] <address>[..<end>] [<op> <value>]".to_string()),
+        };
+
+        let addresses = match range.split_once("..") {
+            Some((start, end)) => self.parse_address(start)?..=self.parse_address(end)?,
+            None => {
+                let address = self.parse_address(range)?;
+                address..=address
+            }
+        };
+
+        let index = self.snes.add_watchpoint(Watchpoint {
+            addresses,
+            read,
+            write,
+            execute,
+            condition,
+        });
+        Ok(format!(
+            "Watchpoint {}: {}",
+            index,
+            self.describe_watchpoint(&self.snes.watchpoints()[index])
+        ))
+    }
+
+    fn describe_watchpoint(&self, watchpoint: &Watchpoint) -> String {
+        let kinds: String = [
+            (watchpoint.read, 'r'),
+            (watchpoint.write, 'w'),
+            (watchpoint.execute, 'x'),
+        ]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, kind)| kind)
+        .collect();
+
+        let (start, end) = (*watchpoint.addresses.start(), *watchpoint.addresses.end());
+        let range = if start == end {
+            self.name(start)
+        } else {
+            format!("{}..{}", self.name(start), self.name(end))
+        };
+
+        let condition = match watchpoint.condition {
+            Condition::Any => String::new(),
+            Condition::Equal(value) => format!(" == ${:02X}", value),
+            Condition::NotEqual(value) => format!(" != ${:02X}", value),
+            Condition::Less(value) => format!(" < ${:02X}", value),
+            Condition::Greater(value) => format!(" > ${:02X}", value),
+        };
+        format!("{} {}{}", kinds, range, condition)
+    }
+
+    fn list_watchpoints(&self) -> String {
+        if self.snes.watchpoints().is_empty() {
+            return "No watchpoints".to_string();
+        }
+
+        let lines: Vec<String> = self
+            .snes
+            .watchpoints()
+            .iter()
+            .enumerate()
+            .map(|(index, watchpoint)| {
+                format!("{}: {}", index, self.describe_watchpoint(watchpoint))
+            })
+            .collect();
+        lines.join("\n")
+    }
+
+    fn list_breakpoints(&self) -> String {
+        if self.breakpoints.is_empty() {
+            return "No breakpoints".to_string();
+        }
+
+        let lines: Vec<String> = self
+            .breakpoints
+            .iter()
+            .enumerate()
+            .map(|(index, &address)| format!("{}: {}", index, self.name(address)))
+            .collect();
+        lines.join("\n")
+    }
+}
+
+/// Parse a hexadecimal number, with an optional $ or 0x.
+fn parse_number(text: &str) -> Result<u32, String> {
+    let digits = text.trim_start_matches('$').trim_start_matches("0x");
+    u32::from_str_radix(digits, 16).map_err(|_| format!("Not a number: {}", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::build_rom;
+
+    #[test]
+    fn commands() {
+        // lda #$80; sta $4200; loop: jmp loop
+        let rom = build_rom(&[(0x8000, &[0xa9, 0x80, 0x8d, 0x00, 0x42, 0x4c, 0x05, 0x80])]);
+        let mut debugger = Debugger::new(Snes::new(&rom));
+        let mut symbols = Symbols::new();
+        symbols.insert("loop", 0x008005);
+        debugger.snes().set_symbols(symbols);
+
+        assert!(debugger
+            .command("dis")
+            .contains("> 00:8000  A9 80        LDA #$80"));
+        assert!(debugger
+            .command("step")
+            .contains("00:8002  8D 00 42     STA $4200"));
+        assert!(
+            debugger.command("").contains("loop:\n> 00:8005"),
+            "repeats the step"
+        );
+        assert_eq!(debugger.program_address(), 0x008005);
+
+        assert_eq!(
+            debugger.command("break loop"),
+            "Breakpoint 0 at 00:8005 <loop>"
+        );
+        assert!(debugger
+            .command("continue")
+            .starts_with("Breakpoint at 00:8005 <loop>"));
+
+        assert!(debugger.command("set a 1234").starts_with("A=1234"));
+        assert_eq!(debugger.snes.core.registers.accumulator, 0x1234);
+
+        debugger.snes.memory.set_byte(0x7e, 0x0010, 0x41);
+        assert!(debugger
+            .command("x 7E:0010 4")
+            .starts_with("7E:0010  41 00 00 00"));
+
+        // Inspecting the WRAM port doesn't move its address on
+        debugger.snes.memory.set_byte(0x00, 0x2181, 0x10);
+        assert!(debugger.command("x 2180 2").starts_with("00:2180  -- --"));
+        assert_eq!(debugger.snes.memory.get_byte(0x00, 0x2180), 0x41);
+        assert!(debugger.command("delete 3").starts_with("No breakpoint"));
+        assert!(debugger.command("bogus").starts_with("Unknown command"));
+    }
+
+    #[test]
+    fn next_and_finish() {
+        // jsr $9100; jsl $009200; loop: jmp loop
+        let main = [0x20, 0x00, 0x91, 0x22, 0x00, 0x92, 0x00, 0x4c, 0x07, 0x80];
+        // lda #$01; rts
+        let short = [0xa9, 0x01, 0x60];
+        // jsr $9100; rtl
+        let long = [0x20, 0x00, 0x91, 0x6b];
+        let rom = build_rom(&[(0x8000, &main), (0x9100, &short), (0x9200, &long)]);
+        let mut debugger = Debugger::new(Snes::new(&rom));
+
+        // Leave room on the stack, as games do
+        debugger.command("set s 1ff");
+        assert!(debugger.command("next").contains("> 00:8003  22 00 92 00"));
+        assert_eq!(debugger.snes.core.registers.accumulator & 0xff, 0x01);
+        debugger.command("next");
+        assert_eq!(debugger.program_address(), 0x008007);
+        assert_eq!(debugger.snes.core.registers.stack_pointer, 0x01ff);
+
+        debugger.snes.core.reset();
+        debugger.command("set s 1ff");
+        debugger.command("step");
+        assert_eq!(debugger.program_address(), 0x009100);
+        debugger.command("finish");
+        assert_eq!(debugger.program_address(), 0x008003);
+
+        debugger.command("step 2");
+        assert_eq!(debugger.program_address(), 0x009100);
+        debugger.command("finish");
+        assert_eq!(debugger.program_address(), 0x009203);
+        debugger.command("finish");
+        assert_eq!(debugger.program_address(), 0x008007);
+    }
+
+    #[test]
+    fn watchpoints() {
+        // lda #$80; sta $4200; loop: jmp loop
+        let rom = build_rom(&[(0x8000, &[0xa9, 0x80, 0x8d, 0x00, 0x42, 0x4c, 0x05, 0x80])]);
+        let mut debugger = Debugger::new(Snes::new(&rom));
+
+        assert_eq!(
+            debugger.command("watch w 4200 != 80"),
+            "Watchpoint 0: w 00:4200 != $80"
+        );
+        assert_eq!(
+            debugger.command("watch 4200..4201 == 80"),
+            "Watchpoint 1: w 00:4200..00:4201 == $80"
+        );
+        assert_eq!(
+            debugger.command("step 3"),
+            "Watchpoint 1: write of $80 at 00:4200 by 00:8002\n> 00:8005  4C 05 80     JMP $8005"
+        );
+        assert!(debugger.command("unwatch 0").starts_with("Deleted"));
+
+        debugger.snes.core.reset();
+        assert!(debugger
+            .command("continue")
+            .starts_with("Watchpoint 0: write of $80 at 00:4200 by 00:8002"));
+
+        assert_eq!(debugger.command("watch x 8005"), "Watchpoint 1: x 00:8005");
+        assert!(debugger
+            .command("continue")
+            .starts_with("Watchpoint 1: execution of $4C at 00:8005 by 00:8005"));
+    }
+
+    #[test]
+    fn read_watchpoints() {
+        // jsr sub; loop: jmp loop; sub: rts
+        let rom = build_rom(&[(0x8000, &[0x20, 0x06, 0x80, 0x4c, 0x03, 0x80, 0x60])]);
+        let mut debugger = Debugger::new(Snes::new(&rom));
+        debugger.command("set s 1ff");
+
+        // Fetching the code being run isn't reading it
+        debugger.command("watch r 8000..8006");
+        debugger.command("watch r 1fe");
+        assert!(debugger
+            .command("continue")
+            .starts_with("Watchpoint 1: read of $02 at 00:01FE by 00:8006"));
+        assert!(debugger.command("step 100").contains("> 00:8003"));
+    }
+}
@@ -0,0 +1,466 @@
+//! A server for the GDB remote serial protocol, so that GDB and the frontends built on it can
+//! debug the emulated CPU.
+//!
+//! GDB has no 65C816 target, so the registers are described to it with a target description.
+//! Memory is the 24-bit address space laid out flat, the bank in bits 16 to 23 of the address.
+//! See https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+
+use super::*;
+use std::io::{ErrorKind, Read};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+/// Instructions run between checks for an interrupt from GDB while continuing.
+const INSTRUCTIONS_PER_POLL: u32 = 10_000;
+
+/// Sent by GDB, outside of a packet, to stop a running target.
+const INTERRUPT: u8 = 0x03;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.snes.65c816.core">
+    <reg name="a" bitsize="16" type="uint16" regnum="0"/>
+    <reg name="x" bitsize="16" type="uint16"/>
+    <reg name="y" bitsize="16" type="uint16"/>
+    <reg name="sp" bitsize="16" type="uint16"/>
+    <reg name="d" bitsize="16" type="uint16"/>
+    <reg name="pc" bitsize="32" type="code_ptr"/>
+    <reg name="p" bitsize="8" type="uint8"/>
+    <reg name="db" bitsize="8" type="uint8"/>
+    <reg name="e" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/// The size in bytes of each register, in the order of the target description.
+const REGISTER_SIZES: [usize; 9] = [2, 2, 2, 2, 2, 4, 1, 1, 1];
+
+impl<'a> Debugger<'a> {
+    /// Wait for GDB to connect on a local address, like `127.0.0.1:2345`, then serve it until it
+    /// detaches or disconnects.
+    pub fn serve_gdb(&mut self, address: impl ToSocketAddrs) -> io::Result<()> {
+        let listener = TcpListener::bind(address)?;
+        let (stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        Connection { stream }.serve(self)
+    }
+
+    /// Answer a packet, or return `None` when GDB is done with the target. `interrupted` is
+    /// polled while continuing, which stops when it returns true.
+    fn gdb_packet(
+        &mut self,
+        packet: &str,
+        interrupted: &mut dyn FnMut() -> bool,
+    ) -> Option<String> {
+        let (command, arguments) = packet.split_at(packet.len().min(1));
+        let reply = match command {
+            "?" => "S05".to_string(),
+            "g" => self.gdb_registers(),
+            "G" => self.gdb_set_registers(arguments),
+            "p" => match usize::from_str_radix(arguments, 16) {
+                Ok(register) if register < REGISTER_SIZES.len() => {
+                    let offset: usize = REGISTER_SIZES[..register].iter().sum();
+                    let size = REGISTER_SIZES[register];
+                    self.gdb_registers()[2 * offset..2 * (offset + size)].to_string()
+                }
+                _ => "E01".to_string(),
+            },
+            "P" => self.gdb_set_register(arguments),
+            "m" => self.gdb_read_memory(arguments),
+            "M" => self.gdb_write_memory(arguments),
+            "s" => {
+                let reason = self.step_instructions(1);
+                self.gdb_stopped(reason)
+            }
+            "c" => loop {
+                match self.step_instructions(INSTRUCTIONS_PER_POLL) {
+                    StopReason::Done if interrupted() => break "S02".to_string(),
+                    StopReason::Done => {}
+                    reason => break self.gdb_stopped(reason),
+                }
+            },
+            "Z" | "z" => self.gdb_breakpoint(command == "Z", arguments),
+            "H" => "OK".to_string(),
+            "k" => return None,
+            "D" => return None,
+            "q" if arguments.starts_with("Supported") => {
+                "PacketSize=4000;qXfer:features:read+".to_string()
+            }
+            "q" if arguments == "Attached" => "1".to_string(),
+            "q" if arguments == "C" => "QC1".to_string(),
+            "q" if arguments.starts_with("Xfer:features:read:target.xml:") => {
+                let range = &arguments["Xfer:features:read:target.xml:".len()..];
+                match parse_pair(range, ',') {
+                    Some((offset, length)) => {
+                        let start = (offset as usize).min(TARGET_XML.len());
+                        let end = (start + length as usize).min(TARGET_XML.len());
+                        let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+                        format!("{}{}", more, &TARGET_XML[start..end])
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    /// The stop reply for why execution stopped.
+    fn gdb_stopped(&self, reason: StopReason) -> String {
+        match reason {
+            StopReason::Done | StopReason::Breakpoint(_) => "S05".to_string(),
+            StopReason::Watchpoint { hit, .. } => {
+                let watchpoint = &self.snes.watchpoints()[hit.index];
+                let kind = match hit.access {
+                    Access::Execute => return "S05".to_string(),
+                    _ if watchpoint.read && watchpoint.write => "awatch",
+                    Access::Read => "rwatch",
+                    Access::Write => "watch",
+                };
+                format!("T05{}:{:x};", kind, hit.address)
+            }
+            // Reported as SIGABRT, as the emulator can't go on
+            StopReason::Panic(_) => "S06".to_string(),
+        }
+    }
+
+    // ========= //
+    // Registers //
+    // ========= //
+
+    fn gdb_registers(&self) -> String {
+        let registers = &self.snes.core.registers;
+        let mut bytes = Vec::new();
+        for value in &[
+            registers.accumulator,
+            registers.index_x,
+            registers.index_y,
+            registers.stack_pointer,
+            registers.direct_page,
+        ] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&registers.program_address().to_le_bytes());
+        bytes.push(registers.processor_status.0);
+        bytes.push(registers.data_bank);
+        bytes.push(registers.emulation as u8);
+        to_hex(&bytes)
+    }
+
+    fn gdb_set_registers(&mut self, arguments: &str) -> String {
+        let bytes = match from_hex(arguments) {
+            Some(bytes) if bytes.len() == REGISTER_SIZES.iter().sum() => bytes,
+            _ => return "E01".to_string(),
+        };
+
+        let mut offset = 0;
+        for (register, size) in REGISTER_SIZES.iter().enumerate() {
+            self.gdb_write_register(register, &bytes[offset..offset + size]);
+            offset += size;
+        }
+        "OK".to_string()
+    }
+
+    fn gdb_set_register(&mut self, arguments: &str) -> String {
+        let (register, value) = match arguments.split_once('=') {
+            Some((register, value)) => (usize::from_str_radix(register, 16), from_hex(value)),
+            None => return "E01".to_string(),
+        };
+
+        match (register, value) {
+            (Ok(register), Some(value))
+                if register < REGISTER_SIZES.len() && value.len() == REGISTER_SIZES[register] =>
+            {
+                self.gdb_write_register(register, &value);
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    /// Set a register from its little endian bytes.
+    fn gdb_write_register(&mut self, register: usize, bytes: &[u8]) {
+        let value = bytes
+            .iter()
+            .rev()
+            .fold(0u32, |value, &byte| value << 8 | u32::from(byte));
+
+        let registers = &mut self.snes.core.registers;
+        match register {
+            0 => registers.accumulator = value as u16,
+            1 => registers.index_x = value as u16,
+            2 => registers.index_y = value as u16,
+            3 => registers.stack_pointer = value as u16,
+            4 => registers.direct_page = value as u16,
+            5 => {
+                registers.program_bank = (value >> 16) as u8;
+                registers.program_counter = value as u16;
+            }
+            6 => registers.processor_status.0 = value as u8,
+            7 => registers.data_bank = value as u8,
+            _ => registers.emulation = value != 0,
+        }
+    }
+
+    // ====== //
+    // Memory //
+    // ====== //
+
+    fn gdb_read_memory(&mut self, arguments: &str) -> String {
+        match parse_pair(arguments, ',') {
+            Some((address, length))
+                if address
+                    .checked_add(length)
+                    .is_some_and(|end| end <= 0x1000000) =>
+            {
+                let bytes: Option<Vec<u8>> = (address..address + length)
+                    .map(|address| self.peek(address))
+                    .collect();
+                match bytes {
+                    Some(bytes) => to_hex(&bytes),
+                    None => "E0E".to_string(),
+                }
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn gdb_write_memory(&mut self, arguments: &str) -> String {
+        let (range, data) = match arguments.split_once(':') {
+            Some((range, data)) => (parse_pair(range, ','), from_hex(data)),
+            None => return "E01".to_string(),
+        };
+
+        match (range, data) {
+            (Some((address, length)), Some(data))
+                if address
+                    .checked_add(length)
+                    .is_some_and(|end| end <= 0x1000000)
+                    && data.len() == length as usize =>
+            {
+                let written = (address..)
+                    .zip(data)
+                    .all(|(address, value)| self.poke(address, value).is_some());
+                if written {
+                    "OK".to_string()
+                } else {
+                    "E0E".to_string()
+                }
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    // =========== //
+    // Breakpoints //
+    // =========== //
+
+    /// Insert or remove a breakpoint or watchpoint, from arguments like `2,7e0010,2`.
+    fn gdb_breakpoint(&mut self, insert: bool, arguments: &str) -> String {
+        let mut fields = arguments.split(',');
+        let kind = fields.next();
+        let address = fields
+            .next()
+            .and_then(|field| u32::from_str_radix(field, 16).ok());
+        let length = fields
+            .next()
+            .and_then(|field| u32::from_str_radix(field, 16).ok());
+        let (address, length) = match (address, length) {
+            (Some(address), Some(length)) if address < 0x1000000 => (address, length.max(1)),
+            _ => return "E01".to_string(),
+        };
+        let last = match address.checked_add(length - 1) {
+            Some(last) => last.min(0xffffff),
+            None => return "E01".to_string(),
+        };
+
+        let (read, write) = match kind {
+            Some("0") | Some("1") => {
+                if insert {
+                    self.add_breakpoint(address);
+                } else {
+                    self.breakpoints.retain(|&breakpoint| breakpoint != address);
+                }
+                return "OK".to_string();
+            }
+            Some("2") => (false, true),
+            Some("3") => (true, false),
+            Some("4") => (true, true),
+            _ => return String::new(),
+        };
+
+        let watchpoint = Watchpoint {
+            addresses: address..=last,
+            read,
+            write,
+            execute: false,
+            condition: Condition::Any,
+        };
+        if insert {
+            self.snes.add_watchpoint(watchpoint);
+        } else if let Some(index) = self
+            .snes
+            .watchpoints()
+            .iter()
+            .position(|existing| *existing == watchpoint)
+        {
+            self.snes.remove_watchpoint(index);
+        }
+        "OK".to_string()
+    }
+}
+
+/// A connection to GDB, which sends packets like `$m7e0010,4#ab`.
+struct Connection {
+    stream: TcpStream,
+}
+
+impl Connection {
+    fn serve(&mut self, debugger: &mut Debugger) -> io::Result<()> {
+        while let Some(packet) = self.receive()? {
+            let stream = &self.stream;
+            let mut interrupted = || interrupt_pending(stream);
+            match debugger.gdb_packet(&packet, &mut interrupted) {
+                Some(reply) => self.send(&reply)?,
+                None => {
+                    self.send("OK")?;
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the next packet, acknowledging it. Returns `None` when GDB disconnects.
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        let mut byte = [0];
+        loop {
+            // Skip acknowledgements, and interrupts sent while already stopped
+            loop {
+                if self.stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'$' {
+                    break;
+                }
+            }
+
+            let mut packet = Vec::new();
+            loop {
+                if self.stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'#' {
+                    break;
+                }
+                packet.push(byte[0]);
+            }
+
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+
+            if expected == Some(checksum_of(&packet)) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&packet).into_owned()));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, reply: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", reply, checksum_of(reply.as_bytes()));
+        self.stream.write_all(packet.as_bytes())
+    }
+}
+
+/// Has GDB sent an interrupt? Anything else sent while running is dropped.
+fn interrupt_pending(stream: &TcpStream) -> bool {
+    let mut stream = stream;
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+
+    let mut byte = [0];
+    let interrupted = match stream.read(&mut byte) {
+        Ok(1) => byte[0] == INTERRUPT,
+        Err(error) if error.kind() == ErrorKind::WouldBlock => false,
+        // Stop if GDB has gone away, so that the next read notices
+        _ => true,
+    };
+
+    let _ = stream.set_nonblocking(false);
+    interrupted
+}
+
+fn checksum_of(packet: &[u8]) -> u8 {
+    packet.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+/// Parse two hexadecimal numbers separated by `separator`.
+fn parse_pair(text: &str, separator: char) -> Option<(u32, u32)> {
+    let (first, second) = text.split_once(separator)?;
+    Some((
+        u32::from_str_radix(first, 16).ok()?,
+        u32::from_str_radix(second, 16).ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::build_rom;
+
+    #[test]
+    fn packets() {
+        // lda #$80; sta $4200; loop: jmp loop
+        let rom = build_rom(&[(0x8000, &[0xa9, 0x80, 0x8d, 0x00, 0x42, 0x4c, 0x05, 0x80])]);
+        let mut debugger = Debugger::new(Snes::new(&rom));
+        let packet =
+            |debugger: &mut Debugger, packet| debugger.gdb_packet(packet, &mut || false).unwrap();
+
+        assert_eq!(packet(&mut debugger, "p5"), "00800000");
+        assert_eq!(packet(&mut debugger, "m8000,3"), "a9808d");
+        assert_eq!(packet(&mut debugger, "M7e0010,2:1234"), "OK");
+        assert_eq!(packet(&mut debugger, "m7e0010,2"), "1234");
+        assert_eq!(packet(&mut debugger, "M8000,1:00"), "E0E");
+        assert_eq!(packet(&mut debugger, "mffffffff,1"), "E01");
+        assert_eq!(packet(&mut debugger, "Mffffffff,1:00"), "E01");
+        assert_eq!(packet(&mut debugger, "Z2,ffffff,ffffffff"), "E01");
+
+        assert_eq!(packet(&mut debugger, "s"), "S05");
+        assert_eq!(packet(&mut debugger, "p0"), "8000");
+        assert_eq!(packet(&mut debugger, "P0=3412"), "OK");
+        assert_eq!(debugger.snes.core.registers.accumulator, 0x1234);
+
+        assert_eq!(packet(&mut debugger, "Z2,4200,1"), "OK");
+        assert_eq!(packet(&mut debugger, "c"), "T05watch:4200;");
+        assert_eq!(packet(&mut debugger, "z2,4200,1"), "OK");
+        assert!(debugger.snes.watchpoints().is_empty());
+
+        assert_eq!(packet(&mut debugger, "Z0,8005,1"), "OK");
+        assert_eq!(packet(&mut debugger, "c"), "S05");
+        assert_eq!(packet(&mut debugger, "p5"), "05800000");
+
+        let target = packet(&mut debugger, "qXfer:features:read:target.xml:0,20");
+        assert!(target.starts_with("m<?xml"));
+        assert_eq!(debugger.gdb_packet("k", &mut || false), None);
+    }
+}
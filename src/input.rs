@@ -0,0 +1,329 @@
+//! See https://wiki.superfamicom.org/controller-input
+
+use crate::state::*;
+use std::any::Any;
+
+mod joypad;
+pub use joypad::*;
+
+mod mouse;
+pub use mouse::Mouse;
+
+mod multitap;
+pub use multitap::Multitap;
+
+mod super_scope;
+pub use super_scope::SuperScope;
+
+/// Something plugged into one of the two controller ports.
+///
+/// The console talks to controllers through a latch line, written through $4016, and a clock
+/// that pulses every time the port is read. Each read returns the data lines D0 and D1 in bits 0
+/// and 1.
+pub trait Controller: Any {
+    /// Set the level of the latch line. Controllers capture their state while it is high.
+    fn set_latch(&mut self, latched: bool);
+
+    /// Clock out the next bits on the data lines.
+    fn read(&mut self) -> u8;
+
+    /// Set the level of the IOBIT pin, driven through WRIO.
+    fn set_io(&mut self, _level: bool) {}
+
+    /// The H/V counters at which the controller pulls IOBIT low, for light guns that see the beam.
+    /// Only the second port is wired to the counter latch.
+    fn light_position(&self) -> Option<(u16, u16)> {
+        None
+    }
+
+    /// Save what the console has driven the controller into, such as the latch and the bits not
+    /// yet clocked out. What the player holds is left to the host.
+    fn save_state(&self, _state: &mut Vec<u8>) {}
+
+    /// Restore what `save_state` saved, advancing past what was read.
+    fn load_state(&mut self, _state: &mut &[u8]) -> Result<(), StateError> {
+        Ok(())
+    }
+}
+
+/// A port with nothing plugged in reads as zeros.
+pub struct Unplugged;
+
+impl Controller for Unplugged {
+    fn set_latch(&mut self, _latched: bool) {}
+
+    fn read(&mut self) -> u8 {
+        0
+    }
+}
+
+/// The controller ports, read manually through $4016/$4017 or automatically into $4218-$421F.
+pub(crate) struct Input {
+    pub ports: [Box<dyn Controller>; 2],
+
+    /// WRIO: the levels driven on the IOBIT pins, port 1 in bit 6 and port 2 in bit 7
+    io: u8,
+
+    /// JOY1-JOY4: the results of the last auto-read
+    auto_read: [u16; 4],
+}
+
+// Controllers are plugged in by the host, so only their own state is kept. Each one is sized, so
+// that a state made with another kind of controller plugged in doesn't load.
+impl SaveState for Input {
+    fn save(&self, state: &mut Vec<u8>) {
+        self.io.save(state);
+        self.auto_read.save(state);
+        for port in &self.ports {
+            let mut controller = Vec::new();
+            port.save_state(&mut controller);
+            controller.save(state);
+        }
+    }
+
+    fn load(&mut self, state: &mut &[u8]) -> Result<(), StateError> {
+        self.io.load(state)?;
+        self.auto_read.load(state)?;
+        for port in &mut self.ports {
+            let mut controller: Vec<u8> = Vec::new();
+            controller.load(state)?;
+
+            let mut rest = controller.as_slice();
+            port.load_state(&mut rest)?;
+            if !rest.is_empty() {
+                return Err(StateError::Corrupted);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Input {
+    pub fn new() -> Input {
+        Input {
+            ports: [Box::new(Joypad::new()), Box::new(Joypad::new())],
+            io: 0xff,
+            auto_read: [0; 4],
+        }
+    }
+
+    /// Write WRIO. Returns whether the H/V counters are latched, as they are when the second
+    /// port's IOBIT goes from high to low.
+    pub fn write_io(&mut self, value: u8) -> bool {
+        let latch = self.io & 0x80 != 0 && value & 0x80 == 0;
+        self.io = value;
+        self.ports[0].set_io(value & 0x40 != 0);
+        self.ports[1].set_io(value & 0x80 != 0);
+        latch
+    }
+
+    /// The horizontal counter at which a light gun in the second port latches the counters on a
+    /// line, if it does. Nothing is latched unless IOBIT is left high.
+    pub fn light(&self, line: u16) -> Option<u16> {
+        if self.io & 0x80 == 0 {
+            return None;
+        }
+
+        match self.ports[1].light_position() {
+            Some((h, v)) if v == line => Some(h),
+            _ => None,
+        }
+    }
+
+    /// Read the controllers into JOY1-JOY4, as done at the start of V-blank when enabled in
+    /// NMITIMEN. JOY1 and JOY2 come from D0 of each port, JOY3 and JOY4 from D1.
+    pub fn auto_read(&mut self) {
+        for port in &mut self.ports {
+            port.set_latch(true);
+            port.set_latch(false);
+        }
+
+        self.auto_read = [0; 4];
+        for _ in 0..16 {
+            for (index, port) in self.ports.iter_mut().enumerate() {
+                let data = port.read();
+                let joy = &mut self.auto_read;
+                joy[index] = joy[index] << 1 | u16::from(data & 0x01);
+                joy[index + 2] = joy[index + 2] << 1 | u16::from(data >> 1 & 0x01);
+            }
+        }
+    }
+
+    pub fn read_port(&mut self, addr: u16) -> u8 {
+        match addr {
+            // JOYSER0
+            0x4016 => self.ports[0].read() & 0x03,
+
+            // JOYSER1, bits 2-4 are always set
+            0x4017 => self.ports[1].read() & 0x03 | 0x1c,
+
+            // RDIO
+            0x4213 => self.io,
+
+            // JOY1L-JOY4H
+            _ => {
+                let joy = self.auto_read[usize::from(addr - 0x4218) / 2];
+                if addr & 1 == 0 {
+                    joy as u8
+                } else {
+                    (joy >> 8) as u8
+                }
+            }
+        }
+    }
+
+    pub fn write_port(&mut self, addr: u16, value: u8) {
+        match addr {
+            // JOYWR, the latch of both ports
+            0x4016 => {
+                for port in &mut self.ports {
+                    port.set_latch(value & 0x01 != 0);
+                }
+            }
+
+            // WRIO
+            0x4201 => {
+                self.write_io(value);
+            }
+
+            // Read-only registers
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serial_read() {
+        let mut input = Input::new();
+        let mut pad = Joypad::new();
+        pad.set_buttons(Buttons::B | Buttons::START | Buttons::R);
+        input.ports[0] = Box::new(pad);
+
+        input.write_port(0x4016, 1);
+        assert_eq!(input.read_port(0x4016), 1, "B is read while latched");
+        assert_eq!(input.read_port(0x4016), 1);
+        input.write_port(0x4016, 0);
+
+        let bits: Vec<u8> = (0..17).map(|_| input.read_port(0x4016)).collect();
+        assert_eq!(
+            bits,
+            [1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1],
+            "buttons, then the ID bits, then ones"
+        );
+
+        assert_eq!(input.read_port(0x4017), 0x1c);
+    }
+
+    #[test]
+    fn auto_read() {
+        let mut input = Input::new();
+        let mut pad = Joypad::new();
+        pad.set_buttons(Buttons::A | Buttons::LEFT);
+        input.ports[1] = Box::new(pad);
+
+        input.auto_read();
+        assert_eq!(input.read_port(0x4218), 0);
+        assert_eq!(input.read_port(0x421A), 0x80);
+        assert_eq!(input.read_port(0x421B), 0x02);
+        assert_eq!(input.read_port(0x421E), 0);
+    }
+
+    #[test]
+    fn save_state() {
+        let mut input = Input::new();
+        let mut pad = Joypad::new();
+        pad.set_buttons(Buttons::B | Buttons::A);
+        input.ports[0] = Box::new(pad);
+
+        // Partway through reading the pad
+        input.write_port(0x4016, 1);
+        input.write_port(0x4016, 0);
+        input.read_port(0x4016);
+        let mut state = Vec::new();
+        input.save(&mut state);
+        let bits: Vec<u8> = (0..16).map(|_| input.read_port(0x4016)).collect();
+
+        let mut loaded = Input::new();
+        loaded.load(&mut state.as_slice()).unwrap();
+        let loaded_bits: Vec<u8> = (0..16).map(|_| loaded.read_port(0x4016)).collect();
+        assert_eq!(loaded_bits, bits);
+
+        loaded.ports[0] = Box::new(Mouse::new());
+        assert!(loaded.load(&mut state.as_slice()).is_err(), "another kind of controller");
+    }
+
+    /// Read 32 bits from the first port.
+    fn read_bits(input: &mut Input) -> u32 {
+        input.write_port(0x4016, 1);
+        input.write_port(0x4016, 0);
+        (0..32).fold(0, |bits, _| bits << 1 | u32::from(input.read_port(0x4016) & 0x01))
+    }
+
+    #[test]
+    fn mouse() {
+        let mut input = Input::new();
+        let mut mouse = Mouse::new();
+        mouse.set_buttons(true, false);
+        mouse.move_by(-5, 3);
+        input.ports[0] = Box::new(mouse);
+
+        assert_eq!(read_bits(&mut input), 0x0041_0385);
+        assert_eq!(read_bits(&mut input), 0x0041_0000, "motion is reported once");
+
+        // Clocking while latched cycles the speed
+        input.write_port(0x4016, 1);
+        input.read_port(0x4016);
+        input.write_port(0x4016, 0);
+        let any: &mut dyn Any = input.ports[0].as_mut();
+        let mouse = any.downcast_mut::<Mouse>().unwrap();
+        assert_eq!(mouse.speed(), 1);
+        mouse.move_by(10, 0);
+        assert_eq!(read_bits(&mut input), 0x0051_000f);
+    }
+
+    #[test]
+    fn multitap() {
+        let mut input = Input::new();
+        let mut tap = Multitap::new();
+        tap.pad_mut(0).set_buttons(Buttons::B);
+        tap.pad_mut(1).set_buttons(Buttons::Y);
+        tap.pad_mut(3).set_buttons(Buttons::B);
+        input.ports[1] = Box::new(tap);
+
+        input.write_port(0x4016, 1);
+        assert_eq!(input.read_port(0x4017) & 0x03, 0x02, "identifies itself");
+        input.write_port(0x4016, 0);
+        assert_eq!(input.read_port(0x4017) & 0x03, 0x01);
+        assert_eq!(input.read_port(0x4017) & 0x03, 0x02);
+
+        input.write_port(0x4201, 0x7f);
+        assert_eq!(input.read_port(0x4017) & 0x03, 0x02);
+        assert_eq!(input.read_port(0x4017) & 0x03, 0x00);
+    }
+
+    #[test]
+    fn super_scope() {
+        let mut input = Input::new();
+        let mut scope = SuperScope::new();
+        scope.aim(Some((100, 50)));
+        scope.set_buttons(true, false, false);
+        input.ports[1] = Box::new(scope);
+
+        assert_eq!(input.light(51), Some(122));
+        assert_eq!(input.light(50), None);
+        assert!(!input.write_io(0xff));
+        assert!(input.write_io(0x7f), "IOBIT going low latches");
+        assert_eq!(input.light(51), None);
+
+        input.write_port(0x4016, 1);
+        input.write_port(0x4016, 0);
+        assert_eq!(input.read_port(0x4017) & 0x01, 1, "fire");
+        input.write_port(0x4016, 1);
+        input.write_port(0x4016, 0);
+        assert_eq!(input.read_port(0x4017) & 0x01, 0, "not while held");
+    }
+}
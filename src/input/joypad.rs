@@ -0,0 +1,96 @@
+use super::*;
+use std::ops::{BitOr, BitOrAssign};
+
+/// The buttons held on a standard pad, laid out as they are read: B first, in bit 15, down to R
+/// in bit 4. This is also the layout of the auto-read registers.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Buttons(pub u16);
+
+impl Buttons {
+    pub const NONE: Buttons = Buttons(0);
+    pub const B: Buttons = Buttons(0x8000);
+    pub const Y: Buttons = Buttons(0x4000);
+    pub const SELECT: Buttons = Buttons(0x2000);
+    pub const START: Buttons = Buttons(0x1000);
+    pub const UP: Buttons = Buttons(0x0800);
+    pub const DOWN: Buttons = Buttons(0x0400);
+    pub const LEFT: Buttons = Buttons(0x0200);
+    pub const RIGHT: Buttons = Buttons(0x0100);
+    pub const A: Buttons = Buttons(0x0080);
+    pub const X: Buttons = Buttons(0x0040);
+    pub const L: Buttons = Buttons(0x0020);
+    pub const R: Buttons = Buttons(0x0010);
+
+    pub fn contains(self, buttons: Buttons) -> bool {
+        self.0 & buttons.0 == buttons.0
+    }
+}
+
+impl BitOr for Buttons {
+    type Output = Buttons;
+
+    fn bitor(self, other: Buttons) -> Buttons {
+        Buttons(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for Buttons {
+    fn bitor_assign(&mut self, other: Buttons) {
+        self.0 |= other.0;
+    }
+}
+
+/// The standard SNES pad. It shifts out its 12 buttons, then 4 zero bits that identify it, then
+/// ones.
+#[derive(Debug, Default)]
+pub struct Joypad {
+    buttons: Buttons,
+    latched: bool,
+    shift: u32,
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad::default()
+    }
+
+    /// Set the buttons held, until they are changed again.
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        // The identification bits are always zero
+        self.buttons = Buttons(buttons.0 & 0xfff0);
+    }
+
+    pub fn buttons(&self) -> Buttons {
+        self.buttons
+    }
+}
+
+impl Controller for Joypad {
+    fn set_latch(&mut self, latched: bool) {
+        self.latched = latched;
+        if latched {
+            self.shift = u32::from(self.buttons.0) << 16 | 0xffff;
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        // While latched the pad keeps reloading, and only B can be read
+        if self.latched {
+            return (self.buttons.0 >> 15) as u8;
+        }
+
+        let bit = (self.shift >> 31) as u8;
+        self.shift = self.shift << 1 | 1;
+        bit
+    }
+
+    fn save_state(&self, state: &mut Vec<u8>) {
+        self.latched.save(state);
+        self.shift.save(state);
+    }
+
+    fn load_state(&mut self, state: &mut &[u8]) -> Result<(), StateError> {
+        self.latched.load(state)?;
+        self.shift.load(state)
+    }
+}
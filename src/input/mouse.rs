@@ -0,0 +1,87 @@
+use super::*;
+
+/// The SNES Mouse. It reports the motion since it was last latched, scaled by one of three
+/// speeds, which the game cycles through by clocking the mouse while the latch is high.
+#[derive(Debug, Default)]
+pub struct Mouse {
+    left: bool,
+    right: bool,
+
+    /// Motion not yet reported, positive to the right and down
+    x: i32,
+    y: i32,
+
+    speed: u8,
+    latched: bool,
+    shift: u32,
+}
+
+impl Mouse {
+    pub fn new() -> Mouse {
+        Mouse::default()
+    }
+
+    pub fn set_buttons(&mut self, left: bool, right: bool) {
+        self.left = left;
+        self.right = right;
+    }
+
+    /// Move the mouse. Motion adds up until the game reads it.
+    pub fn move_by(&mut self, x: i32, y: i32) {
+        self.x += x;
+        self.y += y;
+    }
+
+    /// The speed selected by the game: 0 (slow), 1 (normal) or 2 (fast).
+    pub fn speed(&self) -> u8 {
+        self.speed
+    }
+
+    /// Motion along one axis as sent: a direction bit then 7 bits of magnitude.
+    fn axis(&self, motion: i32) -> u32 {
+        let magnitude = (motion.unsigned_abs() * (2 + u32::from(self.speed)) / 2).min(127);
+        ((motion < 0) as u32) << 7 | magnitude
+    }
+}
+
+impl Controller for Mouse {
+    fn set_latch(&mut self, latched: bool) {
+        if latched && !self.latched {
+            // Eight unused bits, the buttons, the speed, the signature 0001, then Y and X
+            let status = (self.right as u32) << 7
+                | (self.left as u32) << 6
+                | u32::from(self.speed) << 4
+                | 0x01;
+            self.shift = status << 16 | self.axis(self.y) << 8 | self.axis(self.x);
+            self.x = 0;
+            self.y = 0;
+        }
+        self.latched = latched;
+    }
+
+    fn read(&mut self) -> u8 {
+        if self.latched {
+            self.speed = (self.speed + 1) % 3;
+            return 0;
+        }
+
+        let bit = (self.shift >> 31) as u8;
+        self.shift = self.shift << 1 | 1;
+        bit
+    }
+
+    fn save_state(&self, state: &mut Vec<u8>) {
+        self.speed.save(state);
+        self.latched.save(state);
+        self.shift.save(state);
+    }
+
+    fn load_state(&mut self, state: &mut &[u8]) -> Result<(), StateError> {
+        self.speed.load(state)?;
+        if self.speed > 2 {
+            return Err(StateError::Corrupted);
+        }
+        self.latched.load(state)?;
+        self.shift.load(state)
+    }
+}
@@ -0,0 +1,73 @@
+use super::*;
+
+/// The Multitap, which plugs four pads into one port. The IOBIT pin selects which pair of pads is
+/// read on the two data lines: the first two when high, the last two when low.
+#[derive(Debug)]
+pub struct Multitap {
+    pads: [Joypad; 4],
+    select: bool,
+    latched: bool,
+}
+
+impl Default for Multitap {
+    fn default() -> Multitap {
+        Multitap {
+            pads: Default::default(),
+            select: true,
+            latched: false,
+        }
+    }
+}
+
+impl Multitap {
+    pub fn new() -> Multitap {
+        Multitap::default()
+    }
+
+    /// One of the four pads, which are players 2-5 when the tap is in the second port.
+    pub fn pad_mut(&mut self, index: usize) -> &mut Joypad {
+        &mut self.pads[index]
+    }
+}
+
+impl Controller for Multitap {
+    fn set_latch(&mut self, latched: bool) {
+        self.latched = latched;
+        for pad in &mut self.pads {
+            pad.set_latch(latched);
+        }
+    }
+
+    fn set_io(&mut self, level: bool) {
+        self.select = level;
+    }
+
+    fn read(&mut self) -> u8 {
+        // While latched, D1 reads high to tell the tap apart from a single pad
+        if self.latched {
+            return 0x02;
+        }
+
+        let pair = if self.select { 0 } else { 2 };
+        let d0 = self.pads[pair].read() & 0x01;
+        let d1 = self.pads[pair + 1].read() & 0x01;
+        d1 << 1 | d0
+    }
+
+    fn save_state(&self, state: &mut Vec<u8>) {
+        self.select.save(state);
+        self.latched.save(state);
+        for pad in &self.pads {
+            pad.save_state(state);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut &[u8]) -> Result<(), StateError> {
+        self.select.load(state)?;
+        self.latched.load(state)?;
+        for pad in &mut self.pads {
+            pad.load_state(state)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use super::*;
+
+/// The dot where the picture begins, relative to the horizontal counter.
+const PICTURE_START: u16 = 22;
+
+/// The Super Scope light gun. Its sensor sees the beam pass where it is aimed, which pulls the
+/// port's IOBIT pin low and latches the H/V counters.
+#[derive(Debug, Default)]
+pub struct SuperScope {
+    /// The pixel aimed at, or `None` when off screen
+    aim: Option<(u16, u16)>,
+
+    trigger: bool,
+    cursor: bool,
+    turbo: bool,
+    pause: bool,
+
+    /// Trigger and pause as they were at the last latch. Both are only reported once per press,
+    /// except the trigger in turbo mode.
+    last_trigger: bool,
+    last_pause: bool,
+
+    latched: bool,
+    shift: u32,
+}
+
+impl SuperScope {
+    pub fn new() -> SuperScope {
+        SuperScope::default()
+    }
+
+    /// Aim at a pixel of the picture, or off screen.
+    pub fn aim(&mut self, aim: Option<(u16, u16)>) {
+        self.aim = aim;
+    }
+
+    pub fn set_buttons(&mut self, trigger: bool, cursor: bool, pause: bool) {
+        self.trigger = trigger;
+        self.cursor = cursor;
+        self.pause = pause;
+    }
+
+    /// Flip the turbo switch.
+    pub fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+    }
+}
+
+impl Controller for SuperScope {
+    fn set_latch(&mut self, latched: bool) {
+        if latched && !self.latched {
+            let fire = self.trigger && (self.turbo || !self.last_trigger);
+            let pause = self.pause && !self.last_pause;
+            self.last_trigger = self.trigger;
+            self.last_pause = self.pause;
+
+            // Fire, cursor, turbo, pause, two unused bits, off screen, noise, then ones
+            let status = (fire as u32) << 7
+                | (self.cursor as u32) << 6
+                | (self.turbo as u32) << 5
+                | (pause as u32) << 4
+                | (self.aim.is_none() as u32) << 1;
+            self.shift = status << 24 | 0x00ff_ffff;
+        }
+        self.latched = latched;
+    }
+
+    fn read(&mut self) -> u8 {
+        let bit = (self.shift >> 31) as u8;
+        if !self.latched {
+            self.shift = self.shift << 1 | 1;
+        }
+        bit
+    }
+
+    fn light_position(&self) -> Option<(u16, u16)> {
+        // Line 0 is never drawn, the picture begins on line 1
+        self.aim.map(|(x, y)| (x + PICTURE_START, y + 1))
+    }
+
+    fn save_state(&self, state: &mut Vec<u8>) {
+        self.last_trigger.save(state);
+        self.last_pause.save(state);
+        self.latched.save(state);
+        self.shift.save(state);
+    }
+
+    fn load_state(&mut self, state: &mut &[u8]) -> Result<(), StateError> {
+        self.last_trigger.load(state)?;
+        self.last_pause.load(state)?;
+        self.latched.load(state)?;
+        self.shift.load(state)
+    }
+}
@@ -3,6 +3,11 @@
 
 use log::*;
 
+#[macro_use]
+mod state;
+use state::*;
+pub use state::StateError;
+
 mod snes_header;
 use snes_header::*;
 
@@ -11,39 +16,98 @@ use cpu::*;
 
 mod memory_map;
 use memory_map::*;
+pub use memory_map::{Access, Condition, WatchHit, Watchpoint};
+
+mod video;
+pub use video::{Frame, Framebuffer, PixelFormat};
+
+mod sound;
+pub use sound::{SpcError, SpcPlayer, SAMPLE_RATE};
+
+mod audio;
+pub use audio::{write_wav, AudioFormat, AudioRecorder, Quality};
+
+mod png;
+pub use png::write_png;
+
+mod capture;
+pub use capture::VideoRecorder;
+
+mod input;
+pub use input::{Buttons, Controller, Joypad, Mouse, Multitap, SuperScope, Unplugged};
+
+mod rewind;
+use rewind::Rewind;
+
+mod movie;
+use movie::MovieMode;
+pub use movie::{Movie, MovieError};
+
+mod symbols;
+pub use symbols::Symbols;
+
+mod debugger;
+pub use debugger::{Debugger, StopReason};
+
+mod trace;
+
+mod cdl;
+pub use cdl::CodeDataLog;
+
+mod profiler;
+pub use profiler::Profiler;
+
+mod timing;
+use timing::*;
+pub use timing::Region;
 
 /// Emulated Super Nintendo Entertainment System
 pub struct Snes<'a> {
     core: Cpu,
-    video: Video,
-    sound: Sound,
     memory: MemoryMap<'a>,
-}
 
-/// Picture Processing Unit (ppu).
-#[derive(Default)]
-struct Video {
-    memory: VideoRam
-}
+    /// CRC-32 of the ROM, without its SMC header
+    rom_hash: u32,
 
-/// Sound Controller Chip: 8-bit Sony SPC700
-#[derive(Default)]
-struct Sound {
-    memory: SoundRam
-}
+    /// States kept at the start of every frame, when rewinding is enabled
+    rewind: Option<Rewind>,
+
+    /// The movie being recorded or played
+    movie: Option<MovieMode>,
 
-/// Video RAM: 64 KB (VRAM)
-#[derive(Default)]
-struct VideoRam;
+    /// Labels shown when disassembling
+    symbols: Symbols,
 
-/// Sound RAM: 512 kilobit (SRAM)
-#[derive(Default)]
-struct SoundRam;
+    /// Where a line is written for every instruction, when tracing
+    trace: Option<Box<dyn std::io::Write>>,
 
+    /// Cycles spent in each routine, when profiling
+    profiler: Option<Profiler>,
 
+    /// Has `run_until_pc` stopped partway through a frame?
+    in_frame: bool,
+}
+
+/// Master clock cycles spent on the internal operations of each instruction.
+const INSTRUCTION_OVERHEAD: u64 = 6;
 
 impl<'a> Snes<'a> {
-    pub fn start(mut rom: &'a[u8]) {
+    pub fn start(rom: &'a[u8]) {
+        let snes = Snes::new(rom);
+        snes.run();
+    }
+
+    /// Load a ROM and reset the system. The region is given by the ROM's header.
+    pub fn new(rom: &'a[u8]) -> Snes<'a> {
+        Snes::load(rom, None)
+    }
+
+    /// Load a ROM and reset the system, running in a region other than the one in the header.
+    pub fn with_region(rom: &'a[u8], region: Region) -> Snes<'a> {
+        Snes::load(rom, Some(region))
+    }
+
+    fn load(mut rom: &'a[u8], region: Option<Region>) -> Snes<'a> {
         let smc_header_size = rom.len() % 1024;
         info!("SMC header size: {}", smc_header_size);
 
@@ -55,23 +119,491 @@ impl<'a> Snes<'a> {
             rom = &rom[512..];
         }
 
-        let memory = MemoryMap::new(rom);
+        let mut memory = MemoryMap::new(rom);
 
-        let snes = Snes {
+        let region = region.unwrap_or_else(|| memory.get_snes_header().region());
+        info!("Region: {:?}", region);
+        memory.timing.region = region;
+
+        let mut snes = Snes {
             core: Cpu::new(&memory),
-            video: Video::default(),
-            sound: Sound::default(),
-            memory
+            memory,
+            rom_hash: crc32(rom),
+            rewind: None,
+            movie: None,
+            symbols: Symbols::new(),
+            trace: None,
+            profiler: None,
+            in_frame: false,
         };
 
-        snes.run();
+        snes.core.reset();
+        snes
     }
 
     fn run(mut self) {
-        self.core.reset();
+        loop {
+            self.step();
+        }
+    }
 
+    /// Run until the next frame begins.
+    pub fn run_frame(&mut self) {
+        self.run_frame_until(None);
+    }
+
+    /// Run until the next frame begins, or stop early when the CPU is about to execute the
+    /// instruction at a 24-bit address. Returns true if it stopped there. The next call finishes
+    /// the frame.
+    pub fn run_until_pc(&mut self, address: u32) -> bool {
+        self.run_frame_until(Some(address))
+    }
+
+    fn run_frame_until(&mut self, address: Option<u32>) -> bool {
+        if !self.in_frame {
+            self.update_movie();
+            self.in_frame = true;
+        }
+
+        let frame = self.memory.timing.frame();
         loop {
-            self.core.tick(&mut self.memory)
+            self.step();
+            if self.memory.timing.frame() != frame {
+                break;
+            }
+            if address == Some(self.core.registers.program_address()) {
+                return true;
+            }
+        }
+
+        self.in_frame = false;
+        if let Some(mut rewind) = self.rewind.take() {
+            rewind.push(&self.machine_state());
+            self.rewind = Some(rewind);
+        }
+        address == Some(self.core.registers.program_address())
+    }
+
+    /// Execute one instruction, or enter a pending interrupt, then advance the rest of the system
+    /// by the time it took.
+    pub fn step(&mut self) {
+        let start = self.memory.cycles;
+
+        // The opcode executed, or none if an interrupt was entered
+        let executed = if self.memory.timing.take_nmi() {
+            self.core.interrupt(&mut self.memory, Interrupt::Nmi);
+            None
+        } else if self.memory.timing.irq() && !self.core.irq_disabled() {
+            self.core.interrupt(&mut self.memory, Interrupt::Irq);
+            None
+        } else {
+            self.trace_instruction();
+            self.log_instruction();
+            Some(self.core.tick(&mut self.memory))
+        };
+
+        self.memory.cycles += INSTRUCTION_OVERHEAD;
+        let elapsed = self.memory.cycles - start;
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(executed, elapsed, self.core.registers.program_address());
+        }
+        self.advance(elapsed);
+
+        // The APU runs on its own clock. It is also caught up whenever the CPU touches its ports
+        self.memory.sync_sound();
+    }
+
+    /// Advance the PPU, DMA and timers by a number of master clock cycles, handling every event on
+    /// the way.
+    fn advance(&mut self, mut cycles: u64) {
+        while cycles > 0 {
+            let memory = &mut self.memory;
+            let max = cycles.min(u64::from(CYCLES_PER_LINE)) as u32;
+            let (elapsed, event) = memory.timing.step(max, &memory.video);
+            cycles -= u64::from(elapsed);
+
+            let line = memory.timing.v_counter();
+            match event {
+                Some(Event::HBlank) => {
+                    memory.video.render_scanline(line);
+
+                    // A light gun sees the beam pass where it is aimed
+                    if let Some(h) = memory.input.light(line) {
+                        memory.timing.latch_counters(h, line);
+                    }
+
+                    // HDMA runs on every visible line and halts the CPU while it does
+                    if line < memory.video.visible_lines() {
+                        let start = memory.cycles;
+                        memory.run_hdma();
+                        cycles += memory.cycles - start;
+                    }
+                }
+                Some(Event::VBlank) => {
+                    memory.video.start_vblank();
+                    if memory.timing.auto_joypad() {
+                        memory.input.auto_read();
+                    }
+                }
+                Some(Event::Frame) => {
+                    memory.video.start_frame(memory.timing.field());
+                    memory.init_hdma();
+                }
+                None => {}
+            }
+        }
+    }
+
+    pub fn region(&self) -> Region {
+        self.memory.timing.region
+    }
+
+    /// Take the audio generated since the last call: stereo samples at `SAMPLE_RATE`, interleaved
+    /// left then right. Only the last second is kept if it isn't taken in time.
+    pub fn audio_samples(&mut self) -> Vec<i16> {
+        self.memory.sound.take_samples()
+    }
+
+    /// Dump the state of the APU as a .spc file, tagged with the title from the ROM's header.
+    pub fn save_spc(&self) -> Vec<u8> {
+        let title = self.memory.get_snes_header().title;
+        self.memory.sound.save_spc(title)
+    }
+
+    /// The last picture drawn by the PPU.
+    pub fn frame(&self) -> Frame<'_> {
+        self.memory.video.frame()
+    }
+
+    /// A copy of the last picture drawn by the PPU, converted to the given format.
+    pub fn framebuffer(&self, format: PixelFormat) -> Framebuffer {
+        self.frame().to_framebuffer(format)
+    }
+
+    /// Save the state of the whole system. It can only be loaded with the same ROM.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = self.machine_state();
+        self.memory.video.save_picture(&mut state);
+        state
+    }
+
+    /// Save everything but the last picture drawn, which a state for rewinding can do without.
+    /// It changes every frame in most games and would make up most of the state.
+    fn machine_state(&self) -> Vec<u8> {
+        let mut state = Vec::new();
+        write_header(&mut state, self.rom_hash);
+        self.core.save(&mut state);
+        self.memory.save(&mut state);
+        state
+    }
+
+    /// Restore a state made by `save_state`. The system is left as it was if it can't be loaded.
+    /// A movie being played stops, as its frames no longer line up with the system's. No state
+    /// can be loaded while a movie is being recorded, stop the recording first.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        if let Some(MovieMode::Recording(_)) = self.movie {
+            return Err(StateError::Recording);
+        }
+        self.restore_state(state)?;
+        if let Some(MovieMode::Playing { .. }) = self.movie {
+            self.movie = None;
+        }
+        Ok(())
+    }
+
+    fn restore_state(&mut self, mut state: &[u8]) -> Result<(), StateError> {
+        read_header(&mut state, self.rom_hash)?;
+
+        let backup = self.save_state();
+        let result = self
+            .core
+            .load(&mut state)
+            .and_then(|_| self.memory.load(&mut state))
+            .and_then(|_| match state {
+                [] => Ok(()),
+                _ => self.memory.video.load_picture(&mut state),
+            });
+        match result {
+            // The next frame run starts from the loaded state, updating the movie first
+            Ok(()) => self.in_frame = false,
+            Err(_) => self.restore_state(&backup).expect("the backup state loads"),
+        }
+        result
+    }
+
+    /// Keep a state at the start of every frame to rewind to, using at most about `budget` bytes.
+    /// The oldest states are dropped to stay within it.
+    pub fn enable_rewind(&mut self, budget: usize) {
+        let mut rewind = Rewind::new(budget);
+        rewind.push(&self.machine_state());
+        self.rewind = Some(rewind);
+    }
+
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    /// Go back to the start of an earlier frame, as far back as has been kept. Returns the number
+    /// of frames gone back. The picture isn't kept for rewinding, so `frame` shows the same one
+    /// until the next frame is run.
+    pub fn rewind(&mut self, frames: usize) -> usize {
+        let rewind = match &mut self.rewind {
+            Some(rewind) => rewind,
+            None => return 0,
+        };
+
+        let kept = rewind.len();
+        let state = rewind.rewind(frames).expect("the current frame is kept");
+        let frames = kept - rewind.len();
+
+        // Partway through a frame, its buttons have already been taken from the movie
+        let movie_frames = frames + self.in_frame as usize;
+        self.restore_state(&state).expect("states kept for rewinding load");
+
+        // Play the movie on from the frame gone back to, or forget the frames recorded since. Stop
+        // if the movie started after it
+        let started_later = match &mut self.movie {
+            Some(MovieMode::Playing { frame, .. }) => match frame.checked_sub(movie_frames) {
+                Some(earlier) => {
+                    *frame = earlier;
+                    false
+                }
+                None => true,
+            },
+            Some(MovieMode::Recording(movie)) => {
+                match movie.frames().len().checked_sub(movie_frames) {
+                    Some(kept) => {
+                        movie.truncate(kept);
+                        false
+                    }
+                    None => true,
+                }
+            }
+            None => false,
+        };
+        if started_later {
+            self.movie = None;
+        }
+        frames
+    }
+
+    /// Reset the whole system to how it is at power on. Controllers stay plugged in.
+    fn power_on(&mut self) {
+        let mut memory = MemoryMap::new(self.memory.rom());
+        memory.timing.region = self.region();
+        std::mem::swap(&mut memory.input.ports, &mut self.memory.input.ports);
+        std::mem::swap(&mut memory.watches, &mut self.memory.watches);
+        std::mem::swap(&mut memory.cdl, &mut self.memory.cdl);
+
+        self.memory = memory;
+        self.core = Cpu::new(&self.memory);
+        self.core.reset();
+        self.in_frame = false;
+
+        // Nothing from before can be rewound to, and no routine is being executed
+        if let Some(mut rewind) = self.rewind.take() {
+            rewind.clear();
+            rewind.push(&self.machine_state());
+            self.rewind = Some(rewind);
+        }
+        if self.profiler.is_some() {
+            self.enable_profiler();
+        }
+    }
+
+    /// Start recording the buttons held on the pads at the start of every frame. The movie
+    /// starts from power on, which resets the system, or from a save state of where it is now.
+    /// Both ports must hold standard pads.
+    pub fn record_movie(&mut self, from_power_on: bool) -> Result<(), MovieError> {
+        self.check_pads()?;
+        let start = if from_power_on {
+            self.power_on();
+            None
+        } else {
+            Some(self.save_state())
+        };
+        self.movie = Some(MovieMode::Recording(Movie::new(self.rom_hash, start)));
+
+        // The movie's first frame starts here, even partway through one
+        self.in_frame = false;
+        Ok(())
+    }
+
+    /// Go back to where a movie starts and play it, setting the buttons of the pads at the start
+    /// of every frame. Both ports must hold standard pads. Nothing is changed if it can't be
+    /// played.
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), MovieError> {
+        if movie.rom_hash() != self.rom_hash {
+            return Err(MovieError::WrongRom);
+        }
+        self.check_pads()?;
+        if movie.emulator_version() != env!("CARGO_PKG_VERSION") {
+            warn!("Movie recorded with version {}", movie.emulator_version());
+        }
+
+        match movie.start() {
+            Some(state) => self.restore_state(state)?,
+            None => self.power_on(),
+        }
+        self.movie = Some(MovieMode::Playing { movie, frame: 0 });
+        Ok(())
+    }
+
+    /// Is a movie being played, with frames left to play?
+    pub fn playing_movie(&self) -> bool {
+        match &self.movie {
+            Some(MovieMode::Playing { movie, frame }) => *frame < movie.frames().len(),
+            _ => false,
+        }
+    }
+
+    /// Stop recording or playing, and return the movie.
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        match self.movie.take()? {
+            MovieMode::Recording(movie) | MovieMode::Playing { movie, .. } => Some(movie),
+        }
+    }
+
+    /// Movies only hold the buttons of standard pads.
+    fn check_pads(&mut self) -> Result<(), MovieError> {
+        match (0..2).find(|&port| self.controller_mut::<Joypad>(port).is_none()) {
+            Some(port) => Err(MovieError::UnsupportedController(port)),
+            None => Ok(()),
+        }
+    }
+
+    /// Record the buttons held for the coming frame, or set them from the movie being played.
+    fn update_movie(&mut self) {
+        let held = [self.buttons(0), self.buttons(1)];
+        let next = match &mut self.movie {
+            Some(MovieMode::Recording(movie)) => {
+                if held.contains(&None) {
+                    warn!("Only the buttons of standard pads are recorded");
+                }
+                movie.push([held[0].unwrap_or(Buttons::NONE), held[1].unwrap_or(Buttons::NONE)]);
+                None
+            }
+            Some(MovieMode::Playing { movie, frame }) => {
+                let buttons = movie.frames().get(*frame).copied();
+                *frame += 1;
+                buttons
+            }
+            None => None,
+        };
+
+        if let Some(buttons) = next {
+            self.set_buttons(0, buttons[0]);
+            self.set_buttons(1, buttons[1]);
+        }
+    }
+
+    /// The buttons held on the pad in port 0 or 1, if it is a standard pad.
+    fn buttons(&mut self, port: usize) -> Option<Buttons> {
+        self.controller_mut::<Joypad>(port).map(|pad| pad.buttons())
+    }
+
+    /// Plug a controller into port 0 or 1, returning whatever was there. Gives `None` and drops
+    /// the controller if there is no such port.
+    pub fn set_controller(
+        &mut self,
+        port: usize,
+        controller: Box<dyn Controller>,
+    ) -> Option<Box<dyn Controller>> {
+        let plugged = self.memory.input.ports.get_mut(port)?;
+        Some(std::mem::replace(plugged, controller))
+    }
+
+    /// Start logging how each byte of ROM is used, as code, data or a DMA source.
+    pub fn enable_code_data_log(&mut self) {
+        let rom_size = self.memory.rom().len();
+        self.memory.cdl = Some(CodeDataLog::new(rom_size));
+    }
+
+    /// Stop logging, returning the log kept.
+    pub fn disable_code_data_log(&mut self) -> Option<CodeDataLog> {
+        self.memory.cdl.take()
+    }
+
+    pub fn code_data_log(&self) -> Option<&CodeDataLog> {
+        self.memory.cdl.as_ref()
+    }
+
+    /// Read a byte at a 24-bit address, without spending any time or touching any port. Only work
+    /// RAM, save RAM and ROM can be read, other addresses give `None`.
+    pub fn read_byte(&self, address: u32) -> Option<u8> {
+        self.memory.peek((address >> 16) as u8, address as u16)
+    }
+
+    /// The 128 KB of work RAM.
+    pub fn wram(&self) -> &[u8] {
+        self.memory.wram()
+    }
+
+    /// Start attributing the cycles the CPU spends to the routines it is in.
+    pub fn enable_profiler(&mut self) {
+        let address = self.core.registers.program_address();
+        self.profiler = Some(Profiler::new(address));
+    }
+
+    /// Stop profiling, returning the profile taken.
+    pub fn disable_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Name addresses in disassembly, trace logs and the debugger.
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    /// Write every instruction executed to `output`, or stop tracing with `None`.
+    pub fn set_trace(&mut self, output: Option<Box<dyn std::io::Write>>) {
+        self.trace = output;
+    }
+
+    /// Watch accesses the CPU and DMA make to memory, returning the watchpoint's index.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        self.memory.watches.points.push(watchpoint);
+        self.memory.watches.points.len() - 1
+    }
+
+    /// Stop watching, moving the watchpoints after it down an index.
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        let points = &mut self.memory.watches.points;
+        if index < points.len() {
+            Some(points.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.memory.watches.points
+    }
+
+    /// The first access to trigger a watchpoint since this was last called.
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.memory.watches.hit.take()
+    }
+
+    /// The controller plugged into port 0 or 1, if there is such a port and it is a `T`.
+    pub fn controller_mut<T: Controller>(&mut self, port: usize) -> Option<&mut T> {
+        let controller: &mut dyn std::any::Any = self.memory.input.ports.get_mut(port)?.as_mut();
+        controller.downcast_mut()
+    }
+
+    /// Set the buttons held on the pad in port 0 or 1, until they are changed again. Does nothing
+    /// if there is no such port, or it has something other than a standard pad plugged in.
+    pub fn set_buttons(&mut self, port: usize, buttons: Buttons) {
+        if let Some(pad) = self.controller_mut::<Joypad>(port) {
+            pad.set_buttons(buttons);
         }
     }
 }
@@ -81,6 +613,397 @@ impl<'a> Snes<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    /// Build a 32 KB LoROM image with code placed at addresses in bank $00. Execution begins at
+    /// $8000, the NMI handler is at $9000 and the IRQ handler at $A000.
+    pub(crate) fn build_rom(segments: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        for (addr, code) in segments {
+            let start = usize::from(addr - 0x8000);
+            rom[start..start + code.len()].copy_from_slice(code);
+        }
+
+        let header = &mut rom[0x7fc0..];
+        header[..21].copy_from_slice(b"TEST ROM             ");
+        header[21] = 0x20;
+
+        for vectors in &[0x24, 0x34] {
+            let mut vector = |offset: usize, addr: u16| {
+                header[vectors + 2 * offset..vectors + 2 * offset + 2]
+                    .copy_from_slice(&addr.to_le_bytes())
+            };
+            vector(3, 0x9000);
+            vector(4, 0x8000);
+            vector(5, 0xA000);
+        }
+
+        rom
+    }
+
+    /// A writer that can still be read after whatever it was given to is gone, such as a trace
+    /// log or a recording.
+    #[derive(Clone, Default)]
+    pub(crate) struct SharedWriter(Rc<RefCell<Vec<u8>>>);
+
+    impl SharedWriter {
+        pub(crate) fn contents(&self) -> Vec<u8> {
+            self.0.borrow().clone()
+        }
+    }
+
+    impl Write for SharedWriter {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn nmi_at_vblank() {
+        let rom = build_rom(&[
+            // lda #$80; sta $4200; loop: jmp loop
+            (0x8000, &[0xa9, 0x80, 0x8d, 0x00, 0x42, 0x4c, 0x05, 0x80]),
+            // lda #$01; sta $0010; rti
+            (0x9000, &[0xa9, 0x01, 0x8d, 0x10, 0x00, 0x40]),
+        ]);
+
+        let mut snes = Snes::new(&rom);
+        for _ in 0..2 {
+            snes.memory.set_byte(0x7e, 0x0010, 0);
+            snes.run_frame();
+            assert_eq!(snes.memory.get_byte(0x7e, 0x0010), 1);
+            assert_eq!(snes.memory.timing.v_counter(), 0);
+        }
+    }
+
+    #[test]
+    fn region() {
+        let mut rom = build_rom(&[(0x8000, &[0x4c, 0x00, 0x80])]);
+
+        let mut snes = Snes::new(&rom);
+        assert_eq!(snes.region(), Region::Ntsc);
+        assert_eq!(snes.memory.get_byte(0x00, 0x213F) & 0x10, 0);
+
+        rom[0x7fd9] = 0x09;
+        let mut snes = Snes::new(&rom);
+        assert_eq!(snes.region(), Region::Pal);
+        assert_eq!(snes.memory.get_byte(0x00, 0x213F) & 0x10, 0x10);
+
+        snes.run_frame();
+        snes.run_frame();
+        assert_eq!(snes.memory.cycles / u64::from(CYCLES_PER_LINE) / 312, 2);
+
+        let snes = Snes::with_region(&rom, Region::Ntsc);
+        assert_eq!(snes.region(), Region::Ntsc);
+    }
+
+    #[test]
+    fn h_v_timer() {
+        // Enable the V-timer on line 100 and spin
+        let rom = build_rom(&[(
+            0x8000,
+            &[
+                0xa9, 0x64, 0x8d, 0x09, 0x42, 0x9c, 0x0a, 0x42, 0xa9, 0x20, 0x8d, 0x00, 0x42, 0x4c,
+                0x0d, 0x80,
+            ],
+        )]);
+
+        let mut snes = Snes::new(&rom);
+        while snes.memory.timing.v_counter() < 100 {
+            assert_eq!(snes.memory.get_byte(0x00, 0x4211), 0);
+            snes.step();
+        }
+
+        assert_eq!(snes.memory.get_byte(0x00, 0x4211), 0x80);
+        assert_eq!(snes.memory.get_byte(0x00, 0x4211), 0x00, "TIMEUP is cleared when read");
+        assert_eq!(snes.memory.get_byte(0x00, 0x4212) & 0x80, 0);
+
+        while snes.memory.timing.v_counter() != 225 {
+            snes.step();
+        }
+        assert_eq!(snes.memory.get_byte(0x00, 0x4212) & 0x80, 0x80);
+    }
+
+    #[test]
+    fn auto_joypad() {
+        // Enable the auto-read, then spin
+        let rom = build_rom(&[(0x8000, &[0xa9, 0x01, 0x8d, 0x00, 0x42, 0x4c, 0x05, 0x80])]);
+
+        let mut snes = Snes::new(&rom);
+        snes.set_buttons(0, Buttons::A | Buttons::START);
+        while snes.memory.timing.v_counter() != 225 {
+            snes.step();
+        }
+
+        assert_eq!(snes.memory.get_byte(0x00, 0x4212) & 0x01, 0x01, "busy");
+        assert_eq!(snes.memory.get_byte(0x00, 0x4218), 0x80);
+        assert_eq!(snes.memory.get_byte(0x00, 0x4219), 0x10);
+
+        while snes.memory.timing.v_counter() != 230 {
+            snes.step();
+        }
+        assert_eq!(snes.memory.get_byte(0x00, 0x4212) & 0x01, 0);
+
+        assert!(snes.controller_mut::<Unplugged>(1).is_none());
+        assert!(snes.set_controller(1, Box::new(Unplugged)).is_some());
+        assert!(snes.controller_mut::<Unplugged>(1).is_some());
+
+        // There are only two ports
+        assert!(snes.set_controller(2, Box::new(Unplugged)).is_none());
+        assert!(snes.controller_mut::<Joypad>(2).is_none());
+        snes.set_buttons(2, Buttons::A);
+    }
+
+    #[test]
+    fn save_state() {
+        let rom = build_rom(&[
+            (0x8000, &[0xa9, 0x80, 0x8d, 0x00, 0x42, 0x4c, 0x05, 0x80]),
+            (0x9000, &[0xa9, 0x01, 0x8d, 0x10, 0x00, 0x40]),
+        ]);
+
+        let mut snes = Snes::new(&rom);
+        snes.run_frame();
+        snes.memory.set_byte(0x7e, 0x0010, 0x42);
+        let state = snes.save_state();
+        for _ in 0..3 {
+            snes.run_frame();
+        }
+        let later = snes.save_state();
+        assert_eq!(snes.memory.get_byte(0x7e, 0x0010), 1);
+
+        snes.load_state(&state).unwrap();
+        assert_eq!(snes.memory.timing.frame(), 1);
+        assert_eq!(snes.memory.get_byte(0x7e, 0x0010), 0x42);
+
+        // Reading memory takes time, so start over
+        snes.load_state(&state).unwrap();
+        for _ in 0..3 {
+            snes.run_frame();
+        }
+        assert_eq!(snes.save_state(), later, "runs the same after loading");
+
+        assert_eq!(
+            snes.load_state(&state[..state.len() - 1]),
+            Err(StateError::TooShort)
+        );
+        assert_eq!(snes.save_state(), later, "left alone by a failed load");
+
+        let other = build_rom(&[(0x8000, &[0x4c, 0x00, 0x80])]);
+        assert_eq!(
+            Snes::new(&other).load_state(&state),
+            Err(StateError::WrongRom)
+        );
+    }
+
+    #[test]
+    fn run_until_pc() {
+        // lda #$2a; sta $0010; loop: jmp loop
+        let rom = build_rom(&[(0x8000, &[0xa9, 0x2a, 0x8d, 0x10, 0x00, 0x4c, 0x05, 0x80])]);
+        let mut snes = Snes::new(&rom);
+
+        assert!(snes.run_until_pc(0x008005));
+        assert_eq!(snes.read_byte(0x7e0010), Some(0x2a));
+        assert_eq!(snes.wram()[0x10], 0x2a);
+
+        // The loop is at the address after every jump, until the frame ends
+        assert!(snes.run_until_pc(0x008005));
+        assert!(!snes.run_until_pc(0x008000));
+        assert_eq!(snes.memory.timing.frame(), 1);
+    }
+
+    #[test]
+    fn read_byte() {
+        let mut rom = build_rom(&[(0x8000, &[0x4c, 0x00, 0x80])]);
+        assert_eq!(Snes::new(&rom).read_byte(0x700000), None);
+
+        // 2 KB of save RAM, repeated through the banks
+        rom[0x7fd8] = 1;
+        let mut snes = Snes::new(&rom);
+        snes.memory.poke(0x70, 0x0001, 0x2a).unwrap();
+        assert_eq!(snes.read_byte(0x700801), Some(0x2a));
+        assert_eq!(snes.read_byte(0xf00001), Some(0x2a));
+
+        assert_eq!(snes.read_byte(0x808000), Some(0x4c));
+        assert_eq!(snes.read_byte(0x018000), None);
+        assert_eq!(snes.read_byte(0x002140), None);
+        assert_eq!(snes.read_byte(0x400000), None);
+    }
+
+    #[test]
+    fn run_until_pc_then_record_movie() {
+        // Read the pads automatically every frame
+        let rom = build_rom(&[(0x8000, &[0xa9, 0x01, 0x8d, 0x00, 0x42, 0x4c, 0x05, 0x80])]);
+
+        for from_power_on in [true, false] {
+            let mut snes = Snes::new(&rom);
+            assert!(snes.run_until_pc(0x008005));
+            snes.record_movie(from_power_on).unwrap();
+            for _ in 0..3 {
+                snes.run_frame();
+            }
+            assert_eq!(snes.stop_movie().unwrap().frames().len(), 3);
+        }
+    }
+
+    #[test]
+    fn rewind_with_changing_picture() {
+        // Turn the screen on, then keep switching the backdrop between red and black, so that
+        // the stripes drawn move every frame
+        #[rustfmt::skip]
+        let code = [
+            0xa9, 0x0f, 0x8d, 0x00, 0x21, // lda #$0f; sta $2100
+            0x9c, 0x21, 0x21,             // loop: stz $2121
+            0xa9, 0x1f, 0x8d, 0x22, 0x21, // lda #$1f; sta $2122
+            0x9c, 0x22, 0x21,             // stz $2122
+            0x9c, 0x21, 0x21,             // stz $2121
+            0x9c, 0x22, 0x21,             // stz $2122
+            0x9c, 0x22, 0x21,             // stz $2122
+            0x4c, 0x05, 0x80,             // jmp loop
+        ];
+        let rom = build_rom(&[(0x8000, &code)]);
+        let mut snes = Snes::new(&rom);
+        snes.run_frame();
+        snes.enable_rewind(1 << 20);
+        snes.run_frame();
+        let keyframe = snes.rewind.as_ref().unwrap().size();
+
+        let mut picture = snes.frame().pixels.to_vec();
+        for _ in 0..10 {
+            snes.run_frame();
+            assert_ne!(snes.frame().pixels, &picture[..], "the picture changes");
+            picture = snes.frame().pixels.to_vec();
+        }
+
+        // The picture is drawn again every frame, so it isn't kept
+        let deltas = snes.rewind.as_ref().unwrap().size() - keyframe;
+        assert!(deltas < 10 * 4096, "{} bytes for 10 frames", deltas);
+    }
+
+    #[test]
+    fn rewind() {
+        let rom = build_rom(&[(0x8000, &[0x4c, 0x00, 0x80])]);
+
+        let mut snes = Snes::new(&rom);
+        assert_eq!(snes.rewind(1), 0);
+
+        snes.enable_rewind(1 << 20);
+        let mut states = vec![snes.save_state()];
+        for _ in 0..70 {
+            snes.run_frame();
+            states.push(snes.save_state());
+        }
+
+        assert_eq!(snes.rewind(5), 5);
+        assert_eq!(snes.save_state(), states[65]);
+        snes.run_frame();
+        assert_eq!(snes.save_state(), states[66]);
+
+        assert_eq!(snes.rewind(100), 66);
+        assert_eq!(snes.save_state(), states[0]);
+    }
+
+    #[test]
+    fn movie() {
+        // Read the pads automatically every frame
+        let rom = build_rom(&[(0x8000, &[0xa9, 0x01, 0x8d, 0x00, 0x42, 0x4c, 0x05, 0x80])]);
+        let press = |frame: usize| Buttons(((frame * 0x1230) & 0xfff0) as u16);
+
+        let mut snes = Snes::new(&rom);
+        snes.run_frame();
+        snes.record_movie(true).unwrap();
+        for frame in 0..10 {
+            snes.set_buttons(0, press(frame));
+            snes.run_frame();
+        }
+        let recorded = snes.save_state();
+        let movie = snes.stop_movie().unwrap();
+        assert_eq!(movie.frames().len(), 10);
+        assert_eq!(movie.frames()[3], [press(3), Buttons::NONE]);
+
+        let mut snes = Snes::new(&rom);
+        snes.enable_rewind(1 << 20);
+        for _ in 0..5 {
+            snes.run_frame();
+        }
+        snes.play_movie(Movie::from_bytes(&movie.to_bytes()).unwrap())
+            .unwrap();
+        assert_eq!(snes.rewind(1), 0, "power on forgets the frames before");
+
+        // Rewinding goes back in the movie too
+        for _ in 0..6 {
+            snes.run_frame();
+        }
+        assert_eq!(snes.rewind(3), 3);
+        while snes.playing_movie() {
+            snes.run_frame();
+        }
+        assert_eq!(snes.save_state(), recorded);
+
+        snes.load_state(&recorded).unwrap();
+        assert!(snes.stop_movie().is_none(), "loading a state stops playback");
+
+        let other = build_rom(&[(0x8000, &[0x4c, 0x00, 0x80])]);
+        assert_eq!(
+            Snes::new(&other).play_movie(movie.clone()),
+            Err(MovieError::WrongRom)
+        );
+
+        // Only the buttons of standard pads are recorded
+        snes.set_controller(1, Box::new(Mouse::new()));
+        assert_eq!(snes.play_movie(movie), Err(MovieError::UnsupportedController(1)));
+        assert_eq!(snes.record_movie(true), Err(MovieError::UnsupportedController(1)));
+    }
+
+    #[test]
+    fn rewind_while_recording() {
+        // Read the pads automatically every frame
+        let rom = build_rom(&[(0x8000, &[0xa9, 0x01, 0x8d, 0x00, 0x42, 0x4c, 0x05, 0x80])]);
+        let press = |frame: usize| Buttons(((frame * 0x1230) & 0xfff0) as u16);
+
+        let mut snes = Snes::new(&rom);
+        snes.enable_rewind(1 << 20);
+        snes.record_movie(true).unwrap();
+        for frame in 0..10 {
+            snes.set_buttons(0, press(frame));
+            snes.run_frame();
+        }
+
+        // The frames gone back over are recorded again
+        assert_eq!(snes.rewind(4), 4);
+        for frame in 0..4 {
+            snes.set_buttons(0, press(20 + frame));
+            snes.run_frame();
+        }
+        let recorded = snes.save_state();
+        assert_eq!(snes.load_state(&recorded), Err(StateError::Recording));
+
+        let movie = snes.stop_movie().unwrap();
+        assert_eq!(movie.frames().len(), 10);
+        assert_eq!(movie.frames()[5], [press(5), Buttons::NONE]);
+        assert_eq!(movie.frames()[6], [press(20), Buttons::NONE]);
+
+        let mut snes = Snes::new(&rom);
+        snes.play_movie(movie).unwrap();
+        while snes.playing_movie() {
+            snes.run_frame();
+        }
+        assert_eq!(snes.save_state(), recorded);
+
+        // Going back to before the recording started stops it
+        snes.run_frame();
+        snes.enable_rewind(1 << 20);
+        snes.run_frame();
+        snes.record_movie(false).unwrap();
+        snes.run_frame();
+        assert_eq!(snes.rewind(2), 2);
+        assert!(snes.stop_movie().is_none());
+    }
 
     #[test]
     fn load_test_rom() {
@@ -1,4 +1,10 @@
+use crate::cdl::CodeDataLog;
+use crate::input::*;
 use crate::snes_header::*;
+use crate::sound::*;
+use crate::state::*;
+use crate::timing::*;
+use crate::video::*;
 
 #[macro_use]
 mod registers;
@@ -9,33 +15,92 @@ use hardware_registers::HardwareRegisters;
 #[macro_use]
 mod macros;
 
+mod dma;
+use dma::Dma;
+
+mod math;
+use math::Math;
+
+mod watch;
+pub use watch::*;
+
 /// Maps different memory adresses to memory storages in the CPU
 pub struct MemoryMap<'a> {
     rom: &'a [u8],
     wram: WorkRam,
     sram: SaveRam,
     hardware_registers: HardwareRegisters,
+    dma: Dma,
+    math: Math,
+    pub(crate) video: Video,
+    pub(crate) sound: Sound,
+    pub(crate) timing: Timing,
+    pub(crate) input: Input,
+    pub(crate) watches: Watches,
+
+    /// How each byte of ROM has been used, when it is being logged
+    pub(crate) cdl: Option<CodeDataLog>,
+
+    /// The 24-bit address and size of the instruction being executed, kept while logging or
+    /// watching. Reads of its bytes are fetches rather than data.
+    instruction: (u32, u8),
+
+    /// Master clock cycles since power on, counted as the CPU and DMA access memory
+    pub(crate) cycles: u64,
 }
 
 define_memory_access! {
     hardware_registers = [
-        0x2100 => ScreenDisplayRegister   ( screen_display   ),
-        0x2140 => ApuIoRegister0          ( apu_io0          ),
-        0x2141 => ApuIoRegister1          ( apu_io1          ),
-        0x2142 => ApuIoRegister2          ( apu_io2          ),
-        0x2143 => ApuIoRegister3          ( apu_io3          ),
-        0x4200 => InterruptEnableRegister ( interrupt_enable ),
-        0x420c => HdmaEnableRegister      ( hdma_enable      ),
-        0x420b => DmaEnableRegister       ( dma_enable       )
+        0x420d => RomSpeedRegister        ( rom_speed        )
     ]
     other {
-        Rom(usize)
+        Rom(usize),
+        Wram(usize),
+        Sram(usize),
+        WramPort(u16),
+        Video(u16),
+        Sound(u16),
+        Timing(u16),
+        Dma(u16),
+        Math(u16),
+        Input(u16)
     }
     get(memory) {
-        Rom(index) => memory.rom[index]
+        Rom(index) => memory.rom[index],
+        Wram(index) => memory.wram.data[index],
+        Sram(index) => memory.sram.data[index],
+        WramPort(addr) => memory.wram.read_port(addr),
+        Video(addr) => memory.video.read_port(addr),
+        Sound(addr) => {
+            memory.sync_sound();
+            memory.sound.read_port(addr)
+        },
+        Timing(addr) => memory.timing.read_port(addr, &memory.video),
+        Dma(addr) => memory.dma.read_port(addr),
+        Math(addr) => memory.math.read_port(addr, memory.cycles),
+        Input(addr) => memory.input.read_port(addr)
     }
-    get_mut(memory) {
-        Rom(_) => panic!("Attempted write to ROM!")
+    set(memory, value) {
+        Rom(_) => panic!("Attempted write to ROM!"),
+        Wram(index) => memory.wram.data[index] = value,
+        Sram(index) => memory.sram.data[index] = value,
+        WramPort(addr) => memory.wram.write_port(addr, value),
+        Video(addr) => memory.video.write_port(addr, value),
+        Sound(addr) => {
+            memory.sync_sound();
+            memory.sound.write_port(addr, value)
+        },
+        Timing(addr) => memory.timing.write_port(addr, value),
+        Dma(0x420b) => memory.run_dma(value),
+        Dma(addr) => memory.dma.write_port(addr, value),
+        Math(addr) => memory.math.write_port(addr, value, memory.cycles),
+        Input(0x4201) => {
+            if memory.input.write_io(value) {
+                let timing = &mut memory.timing;
+                timing.latch_counters(timing.h_counter(), timing.v_counter());
+            }
+        },
+        Input(addr) => memory.input.write_port(addr, value)
     }
 }
 
@@ -43,6 +108,33 @@ impl WorkRam {
     pub fn new() -> WorkRam {
         WorkRam {
             data: [0; 128 * 1024],
+            port_address: 0,
+        }
+    }
+
+    /// Read WMDATA, the B-bus port into WRAM.
+    fn read_port(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x2180 => {
+                let value = self.data[self.port_address];
+                self.port_address = (self.port_address + 1) % WRAM_SIZE;
+                value
+            }
+            _ => 0,
+        }
+    }
+
+    /// Write WMDATA or WMADDL/M/H.
+    fn write_port(&mut self, addr: u16, value: u8) {
+        let value = usize::from(value);
+        match addr {
+            0x2180 => {
+                self.data[self.port_address] = value as u8;
+                self.port_address = (self.port_address + 1) % WRAM_SIZE;
+            }
+            0x2181 => self.port_address = (self.port_address & 0x1ff00) | value,
+            0x2182 => self.port_address = (self.port_address & 0x100ff) | value << 8,
+            _ => self.port_address = (self.port_address & 0x0ffff) | (value & 0x01) << 16,
         }
     }
 }
@@ -52,33 +144,228 @@ const WRAM_SIZE: usize = 128 * 1024;
 /// 128 KB of Work RAM (WRAM)
 struct WorkRam {
     data: [u8; WRAM_SIZE],
+
+    /// WMADDL/M/H: the address accessed through WMDATA
+    port_address: usize,
 }
 
+save_state! { WorkRam { data, port_address } }
+
 /// Save RAM, stores saves files on the cartridge
-struct SaveRam;
+struct SaveRam {
+    /// Empty if the cartridge has none
+    data: Vec<u8>,
+}
+
+save_state! { SaveRam { data } }
+
+impl SaveRam {
+    /// The save RAM declared in the header of a LoROM image: 1 KB shifted left by the size byte,
+    /// or none if it is zero or larger than the banks it is mapped to.
+    fn for_rom(rom: &[u8]) -> Self {
+        let size = match rom.get(0x7fd8) {
+            Some(&size @ 1..=8) => 1024 << size,
+            _ => 0,
+        };
+        SaveRam {
+            data: vec![0; size],
+        }
+    }
+}
+
+// The ROM is checked by its hash instead
+impl<'a> SaveState for MemoryMap<'a> {
+    fn save(&self, state: &mut Vec<u8>) {
+        self.wram.save(state);
+        self.sram.save(state);
+        self.hardware_registers.save(state);
+        self.dma.save(state);
+        self.math.save(state);
+        self.video.save(state);
+        self.sound.save(state);
+        self.timing.save(state);
+        self.input.save(state);
+        self.cycles.save(state);
+    }
+
+    fn load(&mut self, state: &mut &[u8]) -> Result<(), StateError> {
+        self.wram.load(state)?;
+        self.sram.load(state)?;
+        self.hardware_registers.load(state)?;
+        self.dma.load(state)?;
+        self.math.load(state)?;
+        self.video.load(state)?;
+        self.sound.load(state)?;
+        self.timing.load(state)?;
+        self.input.load(state)?;
+        self.cycles.load(state)
+    }
+}
 
 impl<'a> MemoryMap<'a> {
     pub fn new(rom: &'a [u8]) -> Self {
         MemoryMap {
             rom,
             wram: WorkRam::new(),
-            sram: SaveRam,
+            sram: SaveRam::for_rom(rom),
             hardware_registers: HardwareRegisters::default(),
+            dma: Dma::default(),
+            math: Math::default(),
+            video: Video::new(),
+            sound: Sound::new(),
+            timing: Timing::new(),
+            input: Input::new(),
+            watches: Watches::default(),
+            cdl: None,
+            instruction: (0, 0),
+            cycles: 0,
         }
     }
 
+    pub(crate) fn rom(&self) -> &'a [u8] {
+        self.rom
+    }
+
+    pub(crate) fn wram(&self) -> &[u8] {
+        &self.wram.data
+    }
+
     pub fn get_snes_header(&self) -> SnesHeader<'a> {
         self.get_lorom_header()
     }
 
-    pub fn get_byte(&self, bank: u8, addr: u16) -> u8 {
+    pub fn get_byte(&mut self, bank: u8, addr: u16) -> u8 {
+        self.cycles += self.access_cycles(bank, addr);
+        let value = self.read_bus(bank, addr);
+
+        // The instruction's own bytes are logged as code before it runs, and only watched for
+        // being executed
+        if !self.in_instruction(bank, addr) {
+            self.watches.check(Access::Read, bank, addr, value);
+            self.log_rom(bank, addr, CodeDataLog::DATA);
+        }
+        value
+    }
+
+    pub fn set_byte(&mut self, bank: u8, addr: u16, value: u8) {
+        self.cycles += self.access_cycles(bank, addr);
+        self.watches.check(Access::Write, bank, addr, value);
+        self.write_bus(bank, addr, value);
+    }
+
+    pub(crate) fn set_instruction(&mut self, address: u32, size: u8) {
+        self.instruction = (address, size);
+    }
+
+    /// Is the byte at `bank:addr` part of the instruction being executed?
+    fn in_instruction(&self, bank: u8, addr: u16) -> bool {
+        let (address, size) = self.instruction;
+        u32::from(bank) << 16 == address & 0xff0000
+            && addr.wrapping_sub(address as u16) < u16::from(size)
+    }
+
+    /// Mark a byte in the code/data log, if it is in ROM and a log is being kept.
+    pub(crate) fn log_rom(&mut self, bank: u8, addr: u16, flags: u8) {
+        if self.cdl.is_none() {
+            return;
+        }
+
+        if let (Some(index), Some(cdl)) = (Self::rom_index(bank, addr), &mut self.cdl) {
+            cdl.mark(index, flags);
+        }
+    }
+
+    /// Run the APU up to the current time, before the CPU or DMA touches its ports.
+    pub(crate) fn sync_sound(&mut self) {
+        let master_clock = self.timing.region.master_clock();
+        self.sound.catch_up(self.cycles, master_clock);
+    }
+
+    /// Read a byte without spending any time, as done by DMA.
+    pub(crate) fn read_bus(&mut self, bank: u8, addr: u16) -> u8 {
         let access = self.get_memory_access_lorom(bank, addr);
         self.access_byte(access)
     }
 
-    pub fn set_byte(&mut self, bank: u8, addr: u16, value: u8) {
+    pub(crate) fn write_bus(&mut self, bank: u8, addr: u16, value: u8) {
         let access = self.get_memory_access_lorom(bank, addr);
-        *self.access_byte_mut(access) = value;
+        self.write_byte(access, value);
+    }
+
+    /// Read a byte for a debugger, without spending time or touching any port. Only work RAM,
+    /// save RAM and ROM can be read this way, other addresses give `None`.
+    pub(crate) fn peek(&self, bank: u8, addr: u16) -> Option<u8> {
+        if let Some(index) = Self::wram_index(bank, addr) {
+            return Some(self.wram.data[index]);
+        }
+        if let Some(index) = self.sram_index(bank, addr) {
+            return Some(self.sram.data[index]);
+        }
+
+        self.rom.get(Self::rom_index(bank, addr)?).copied()
+    }
+
+    /// Write a byte for a debugger, without spending time or touching any port. Only work RAM
+    /// and save RAM can be written this way, other addresses give `None`.
+    pub(crate) fn poke(&mut self, bank: u8, addr: u16, value: u8) -> Option<()> {
+        if let Some(index) = Self::wram_index(bank, addr) {
+            self.wram.data[index] = value;
+        } else {
+            let index = self.sram_index(bank, addr)?;
+            self.sram.data[index] = value;
+        }
+        Some(())
+    }
+
+    /// Where an address is in the LoROM mapping, if it is.
+    fn rom_index(bank: u8, addr: u16) -> Option<usize> {
+        match (bank & 0x7f, addr) {
+            (0x00..=0x3f, 0x8000..=0xffff) => {
+                Some(usize::from(bank & 0x7f) * 0x8000 + usize::from(addr) - 0x8000)
+            }
+            _ => None,
+        }
+    }
+
+    /// Where an address is in work RAM, if it is: banks $7E and $7F, and the first 8 KB mirrored
+    /// in the system banks.
+    fn wram_index(bank: u8, addr: u16) -> Option<usize> {
+        match (bank, addr) {
+            (0x7e..=0x7f, _) => Some(usize::from(bank - 0x7e) << 16 | usize::from(addr)),
+            (0x00..=0x3f | 0x80..=0xbf, 0x0000..=0x1fff) => Some(usize::from(addr)),
+            _ => None,
+        }
+    }
+
+    /// Where an address is in save RAM, if the cartridge has any: the lower half of banks $70 to
+    /// $7D and their mirrors, repeating the save RAM through them.
+    fn sram_index(&self, bank: u8, addr: u16) -> Option<usize> {
+        match (bank, addr) {
+            (0x70..=0x7d | 0xf0..=0xfd, 0x0000..=0x7fff) if !self.sram.data.is_empty() => {
+                let index = usize::from(bank & 0x0f) * 0x8000 + usize::from(addr);
+                Some(index % self.sram.data.len())
+            }
+            _ => None,
+        }
+    }
+
+    /// The number of master clock cycles it takes the CPU to access an address.
+    fn access_cycles(&self, bank: u8, addr: u16) -> u64 {
+        let fast_rom = self.hardware_registers.rom_speed.0 & 0x01 != 0;
+
+        match bank {
+            0x40..=0x7F => 8,
+            0x80..=0xBF if addr >= 0x8000 && fast_rom => 6,
+            0xC0..=0xFF if fast_rom => 6,
+            0xC0..=0xFF => 8,
+            _ => match addr {
+                0x0000..=0x1FFF => 8,
+                0x2000..=0x3FFF => 6,
+                0x4000..=0x41FF => 12,
+                0x4200..=0x5FFF => 6,
+                _ => 8,
+            },
+        }
     }
 
     /*
@@ -165,8 +452,18 @@ impl<'a> MemoryMap<'a> {
             // Unused
             0x2000..=0x20FF => unimplemented!(),
 
-            // PPU1, APU, hardware registers
-            0x2100..=0x21FF => Self::get_hardware_register(addr),
+            // PPU1, the H/V counters are kept by the timing
+            0x2137 | 0x213C | 0x213D | 0x213F => MemoryAccess::Timing(addr),
+            0x2100..=0x213F => MemoryAccess::Video(addr),
+
+            // WRAM port
+            0x2180..=0x2183 => MemoryAccess::WramPort(addr),
+
+            // APU communication ports, mirrored every four bytes
+            0x2140..=0x217F => MemoryAccess::Sound(addr),
+
+            // Hardware registers
+            0x2184..=0x21FF => Self::get_hardware_register(addr),
 
             // Unused
             0x2200..=0x2FFF => unimplemented!(),
@@ -175,12 +472,17 @@ impl<'a> MemoryMap<'a> {
             0x3000..=0x3FFF => unimplemented!(),
 
             // Old Style Joypad Registers
+            0x4016 | 0x4017 => MemoryAccess::Input(addr),
             0x4000..=0x40FF => Self::get_hardware_register(addr),
 
             // Unused
             0x4100..=0x41FF => unimplemented!(),
 
             // DMA, PPU2, hardware registers
+            0x4200 | 0x4207..=0x420A | 0x4210..=0x4212 => MemoryAccess::Timing(addr),
+            0x420B | 0x420C | 0x4300..=0x437F => MemoryAccess::Dma(addr),
+            0x4202..=0x4206 | 0x4214..=0x4217 => MemoryAccess::Math(addr),
+            0x4201 | 0x4213 | 0x4218..=0x421F => MemoryAccess::Input(addr),
             0x4200..=0x44FF => Self::get_hardware_register(addr),
 
             // Unused
@@ -210,7 +512,10 @@ impl<'a> MemoryMap<'a> {
     fn get_bank_70_7d(&self, bank: u8, addr: u16) -> MemoryAccess {
         match addr {
             // Cartridge SRAM
-            0x0000..=0x7FFF => unimplemented!(),
+            0x0000..=0x7FFF => match self.sram_index(bank, addr) {
+                Some(index) => MemoryAccess::Sram(index),
+                None => unimplemented!(),
+            },
 
             // LoROM (380000-3EFFFF)
             0x8000..=0xFFFF => unimplemented!(),
@@ -220,20 +525,20 @@ impl<'a> MemoryMap<'a> {
     fn get_bank_7e(&self, addr: u16) -> MemoryAccess {
         match addr {
             // LowRAM (WRAM)
-            0x0000..=0x1FFF => unimplemented!(),
+            0x0000..=0x1FFF => MemoryAccess::Wram(usize::from(addr)),
 
             // HighRAM (WRAM)
-            0x2000..=0x7FFF => unimplemented!(),
+            0x2000..=0x7FFF => MemoryAccess::Wram(usize::from(addr)),
 
             // Extended RAM (WRAM)
-            0x8000..=0xFFFF => unimplemented!(),
+            0x8000..=0xFFFF => MemoryAccess::Wram(usize::from(addr)),
         }
     }
 
     fn get_bank_7f(&self, addr: u16) -> MemoryAccess {
         match addr {
             // Extended RAM (WRAM)
-            0x0000..=0xFFFF => unimplemented!(),
+            0x0000..=0xFFFF => MemoryAccess::Wram(0x10000 + usize::from(addr)),
         }
     }
 
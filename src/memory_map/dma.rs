@@ -0,0 +1,273 @@
+//! See https://wiki.superfamicom.org/dma-and-hdma
+
+use super::*;
+
+/// Master clock cycles per byte transferred.
+const CYCLES_PER_BYTE: u64 = 8;
+
+/// DMA and HDMA channels ($420B, $420C and $4300-$437F).
+#[derive(Default)]
+pub(crate) struct Dma {
+    channels: [DmaChannel; 8],
+
+    /// HDMAEN: channels that run HDMA each frame
+    hdma_enable: u8,
+
+    /// Channels that have not yet reached the end of their HDMA table this frame
+    hdma_active: u8,
+}
+
+#[derive(Default, Copy, Clone)]
+struct DmaChannel {
+    /// DMAPx: direction, HDMA indirect, address step and transfer mode
+    parameters: u8,
+
+    /// BBADx: the B-bus address, $21xx
+    b_address: u8,
+
+    /// A1TxL/H and A1Bx: the A-bus address, also the start of the HDMA table
+    a_address: u16,
+    a_bank: u8,
+
+    /// DASxL/H: the byte count, or the HDMA indirect address
+    count: u16,
+
+    /// DASBx: the HDMA indirect bank
+    indirect_bank: u8,
+
+    /// A2AxL/H: the current position in the HDMA table
+    table_address: u16,
+
+    /// NLTRx: HDMA repeat flag and line counter
+    line_counter: u8,
+
+    /// $43xB and $43xF: unused, but readable and writable
+    unused: u8,
+
+    /// An HDMA transfer happens on the next line
+    do_transfer: bool,
+}
+
+save_state! { Dma { channels, hdma_enable, hdma_active } }
+
+save_state! {
+    DmaChannel {
+        parameters, b_address, a_address, a_bank, count, indirect_bank, table_address,
+        line_counter, unused, do_transfer
+    }
+}
+
+impl DmaChannel {
+    fn b_to_a(&self) -> bool {
+        self.parameters & 0x80 != 0
+    }
+
+    fn indirect(&self) -> bool {
+        self.parameters & 0x40 != 0
+    }
+
+    /// The offsets from the B-bus address of each byte in one unit of the transfer mode.
+    fn pattern(&self) -> &'static [u8] {
+        match self.parameters & 0x07 {
+            0 => &[0],
+            1 => &[0, 1],
+            2 | 6 => &[0, 0],
+            3 | 7 => &[0, 0, 1, 1],
+            4 => &[0, 1, 2, 3],
+            _ => &[0, 1, 0, 1],
+        }
+    }
+
+    /// Step the A-bus address after a byte of general purpose DMA.
+    fn step_a_address(&mut self) {
+        match self.parameters & 0x18 {
+            0x00 => self.a_address = self.a_address.wrapping_add(1),
+            0x10 => self.a_address = self.a_address.wrapping_sub(1),
+            _ => {}
+        }
+    }
+}
+
+impl Dma {
+    pub fn read_port(&self, addr: u16) -> u8 {
+        if addr < 0x4300 {
+            // HDMAEN reads back, MDMAEN is write-only
+            return if addr == 0x420C { self.hdma_enable } else { 0 };
+        }
+
+        let channel = &self.channels[usize::from((addr >> 4) & 0x07)];
+        match addr & 0x0f {
+            0x0 => channel.parameters,
+            0x1 => channel.b_address,
+            0x2 => channel.a_address as u8,
+            0x3 => (channel.a_address >> 8) as u8,
+            0x4 => channel.a_bank,
+            0x5 => channel.count as u8,
+            0x6 => (channel.count >> 8) as u8,
+            0x7 => channel.indirect_bank,
+            0x8 => channel.table_address as u8,
+            0x9 => (channel.table_address >> 8) as u8,
+            0xA => channel.line_counter,
+            _ => channel.unused,
+        }
+    }
+
+    /// Write to a DMA register. Writing MDMAEN is handled by the memory map since it starts a
+    /// transfer.
+    pub fn write_port(&mut self, addr: u16, value: u8) {
+        if addr == 0x420C {
+            self.hdma_enable = value;
+            return;
+        }
+
+        let channel = &mut self.channels[usize::from((addr >> 4) & 0x07)];
+        let set_low = |word: u16| (word & 0xff00) | u16::from(value);
+        let set_high = |word: u16| (word & 0x00ff) | u16::from(value) << 8;
+
+        match addr & 0x0f {
+            0x0 => channel.parameters = value,
+            0x1 => channel.b_address = value,
+            0x2 => channel.a_address = set_low(channel.a_address),
+            0x3 => channel.a_address = set_high(channel.a_address),
+            0x4 => channel.a_bank = value,
+            0x5 => channel.count = set_low(channel.count),
+            0x6 => channel.count = set_high(channel.count),
+            0x7 => channel.indirect_bank = value,
+            0x8 => channel.table_address = set_low(channel.table_address),
+            0x9 => channel.table_address = set_high(channel.table_address),
+            0xA => channel.line_counter = value,
+            _ => channel.unused = value,
+        }
+    }
+}
+
+impl<'a> MemoryMap<'a> {
+    /// Run general purpose DMA on the channels in MDMAEN, in order. The CPU is halted until all
+    /// transfers are complete.
+    pub(super) fn run_dma(&mut self, enabled: u8) {
+        for index in 0..8 {
+            if enabled & (1 << index) == 0 {
+                continue;
+            }
+
+            self.cycles += CYCLES_PER_BYTE;
+
+            let pattern = self.dma.channels[index].pattern();
+            let mut unit = 0;
+            loop {
+                let channel = self.dma.channels[index];
+                let b_address = channel.b_address.wrapping_add(pattern[unit % pattern.len()]);
+                self.transfer(&channel, channel.a_bank, channel.a_address, b_address);
+
+                let channel = &mut self.dma.channels[index];
+                channel.step_a_address();
+                channel.count = channel.count.wrapping_sub(1);
+                unit += 1;
+
+                if channel.count == 0 {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Move a byte between the A and B buses, in the direction of the channel.
+    fn transfer(&mut self, channel: &DmaChannel, bank: u8, addr: u16, b_address: u8) {
+        let b_address = 0x2100 | u16::from(b_address);
+
+        let (source, destination) = if channel.b_to_a() {
+            ((0, b_address), (bank, addr))
+        } else {
+            ((bank, addr), (0, b_address))
+        };
+
+        let value = self.read_bus(source.0, source.1);
+        self.watches.check(Access::Read, source.0, source.1, value);
+        self.log_rom(source.0, source.1, CodeDataLog::DMA);
+        self.watches.check(Access::Write, destination.0, destination.1, value);
+        self.write_bus(destination.0, destination.1, value);
+
+        self.cycles += CYCLES_PER_BYTE;
+    }
+
+    /// Read the next byte of a channel's HDMA table.
+    fn read_hdma_table(&mut self, index: usize) -> u8 {
+        let channel = &mut self.dma.channels[index];
+        let (bank, addr) = (channel.a_bank, channel.table_address);
+        channel.table_address = channel.table_address.wrapping_add(1);
+
+        self.cycles += CYCLES_PER_BYTE;
+        let value = self.read_bus(bank, addr);
+        self.watches.check(Access::Read, bank, addr, value);
+        self.log_rom(bank, addr, CodeDataLog::DMA);
+        value
+    }
+
+    /// Load the next entry of a channel's HDMA table. The channel stops if the entry is empty.
+    fn load_hdma_entry(&mut self, index: usize) {
+        let line_counter = self.read_hdma_table(index);
+        self.dma.channels[index].line_counter = line_counter;
+
+        if line_counter == 0 {
+            self.dma.hdma_active &= !(1 << index);
+            return;
+        }
+
+        if self.dma.channels[index].indirect() {
+            let low = self.read_hdma_table(index);
+            let high = self.read_hdma_table(index);
+            self.dma.channels[index].count = u16::from_le_bytes([low, high]);
+        }
+
+        self.dma.channels[index].do_transfer = true;
+    }
+
+    /// Restart the HDMA tables, at the start of each frame.
+    pub(crate) fn init_hdma(&mut self) {
+        self.dma.hdma_active = self.dma.hdma_enable;
+
+        for index in 0..8 {
+            if self.dma.hdma_active & (1 << index) != 0 {
+                let channel = &mut self.dma.channels[index];
+                channel.table_address = channel.a_address;
+                self.load_hdma_entry(index);
+            }
+        }
+    }
+
+    /// Run one line of HDMA, at the start of H-blank on every visible line.
+    pub(crate) fn run_hdma(&mut self) {
+        for index in 0..8 {
+            if self.dma.hdma_active & (1 << index) == 0 {
+                continue;
+            }
+
+            let channel = self.dma.channels[index];
+            if channel.do_transfer {
+                for &offset in channel.pattern() {
+                    let b_address = channel.b_address.wrapping_add(offset);
+
+                    let channel = &mut self.dma.channels[index];
+                    let (bank, addr) = if channel.indirect() {
+                        channel.count = channel.count.wrapping_add(1);
+                        (channel.indirect_bank, channel.count.wrapping_sub(1))
+                    } else {
+                        channel.table_address = channel.table_address.wrapping_add(1);
+                        (channel.a_bank, channel.table_address.wrapping_sub(1))
+                    };
+
+                    let channel = *channel;
+                    self.transfer(&channel, bank, addr, b_address);
+                }
+            }
+
+            let channel = &mut self.dma.channels[index];
+            channel.line_counter = channel.line_counter.wrapping_sub(1);
+            channel.do_transfer = channel.line_counter & 0x80 != 0;
+
+            if channel.line_counter & 0x7f == 0 {
+                self.load_hdma_entry(index);
+            }
+        }
+    }
+}
@@ -17,21 +17,22 @@ macro_rules! get_access {
     }
 }
 
-macro_rules! get_mut_access {
+macro_rules! set_access {
     {
         matching_value = $value:expr,
         store = $store:expr,
+        data = $data:expr,
         hardware_registers = [$($reg:ident),*] 
         $($tt:tt)*
     } => {
         match $value { 
-            $(MemoryAccess::$reg => get_mut_access!($reg, $store) ),*
+            $(MemoryAccess::$reg => set_access!($reg, $store, $data) ),*
                 $($tt)*
         }
     };
 
-    ( $reg:ident, $store:expr ) => {
-        RegisterMap::<hardware_registers::$reg>::get_mut($store)
+    ( $reg:ident, $store:expr, $data:expr ) => {
+        *RegisterMap::<hardware_registers::$reg>::get_mut($store) = $data
     }
 }
 
@@ -41,31 +42,32 @@ macro_rules! impl_memory_access {
             hardware_registers = [ $($get_reg:ident),* ]
             $($get_tt:tt)*
         }
-        get_mut($mut_self:ident) {
-            hardware_registers = [ $($mut_reg:ident),* ]
-            $($mut_tt:tt)*
+        set($set_self:ident, $set_value:ident) {
+            hardware_registers = [ $($set_reg:ident),* ]
+            $($set_tt:tt)*
         }
     } => {
         impl<'a> MemoryMap<'a> {
-            fn access_byte(&self, access: MemoryAccess) -> u8 {
+            fn access_byte(&mut self, access: MemoryAccess) -> u8 {
                 let $get_self = self;
                 use MemoryAccess::*;
                 get_access! {
                     matching_value = access,
-                    store = &self.hardware_registers,
+                    store = &$get_self.hardware_registers,
                     hardware_registers = [ $($get_reg),* ],
                     $($get_tt)*
                 }
             }
 
-            fn access_byte_mut(&mut self, access: MemoryAccess) -> &mut u8 {
-                let $mut_self = self;
+            fn write_byte(&mut self, access: MemoryAccess, $set_value: u8) {
+                let $set_self = self;
                 use MemoryAccess::*;
-                get_mut_access! {
+                set_access! {
                     matching_value = access,
-                    store = &mut $mut_self.hardware_registers,
-                    hardware_registers = [ $($mut_reg),* ],
-                    $($mut_tt)*
+                    store = &mut $set_self.hardware_registers,
+                    data = $set_value,
+                    hardware_registers = [ $($set_reg),* ],
+                    $($set_tt)*
                 }
             }
         }
@@ -94,7 +96,7 @@ macro_rules! define_memory_access {
         hardware_registers = [ $($addr:expr => $reg:ident ($name:ident)),* ]
         other { $($tt:tt)* }
         get($get_self:ident) { $($get_tt:tt)* }
-        get_mut($mut_self:ident) { $($mut_tt:tt)* }
+        set($set_self:ident, $set_value:ident) { $($set_tt:tt)* }
     } => {
         enum MemoryAccess {
             $($reg,)*
@@ -106,9 +108,9 @@ macro_rules! define_memory_access {
                 hardware_registers = [ $($reg),* ]
                 $($get_tt)*
             }
-            get_mut($mut_self) {
+            set($set_self, $set_value) {
                 hardware_registers = [ $($reg),* ]
-                $($mut_tt)*
+                $($set_tt)*
             }
         }
 
@@ -0,0 +1,163 @@
+//! See https://wiki.superfamicom.org/registers#mathematics
+
+/// Master clock cycles per step of the multiplier and divider. They advance once per CPU cycle,
+/// counted here as a slow memory access.
+const CYCLES_PER_STEP: u64 = 8;
+
+/// Steps until a product is ready.
+const MULTIPLY_STEPS: u8 = 8;
+
+/// Steps until a quotient is ready.
+const DIVIDE_STEPS: u8 = 16;
+
+/// The 5A22's unsigned multiplier and divider ($4202-$4206 and $4214-$4217). Both work one bit
+/// per step, so results read too early are only partially computed.
+#[derive(Default)]
+pub(crate) struct Math {
+    /// WRMPYA: the multiplicand
+    multiplicand: u8,
+
+    /// WRDIVL/H: the dividend
+    dividend: u16,
+
+    /// RDDIVL/H: the quotient, also the remaining multiplier bits while multiplying
+    quotient: u16,
+
+    /// RDMPYL/H: the product or the remainder
+    product: u16,
+
+    /// The multiplicand or divisor, shifted one bit each step
+    shift: u32,
+
+    /// Steps left in the running multiplication or division
+    multiply_steps: u8,
+    divide_steps: u8,
+
+    /// The master clock cycle the unit has been run up to
+    synced: u64,
+}
+
+save_state! {
+    Math {
+        multiplicand, dividend, quotient, product, shift, multiply_steps, divide_steps, synced
+    }
+}
+
+impl Math {
+    /// Run the unit up to a point in time.
+    fn catch_up(&mut self, cycle: u64) {
+        let steps = cycle.saturating_sub(self.synced) / CYCLES_PER_STEP;
+        self.synced += steps * CYCLES_PER_STEP;
+
+        for _ in 0..steps {
+            if self.multiply_steps > 0 {
+                self.multiply_steps -= 1;
+                if self.quotient & 0x01 != 0 {
+                    self.product = self.product.wrapping_add(self.shift as u16);
+                }
+                self.quotient >>= 1;
+                self.shift <<= 1;
+            } else if self.divide_steps > 0 {
+                self.divide_steps -= 1;
+                self.quotient <<= 1;
+                self.shift >>= 1;
+                if u32::from(self.product) >= self.shift {
+                    self.product -= self.shift as u16;
+                    self.quotient |= 1;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn busy(&self) -> bool {
+        self.multiply_steps > 0 || self.divide_steps > 0
+    }
+
+    pub fn read_port(&mut self, addr: u16, cycle: u64) -> u8 {
+        self.catch_up(cycle);
+        match addr {
+            0x4214 => self.quotient as u8,
+            0x4215 => (self.quotient >> 8) as u8,
+            0x4216 => self.product as u8,
+            0x4217 => (self.product >> 8) as u8,
+
+            // Write-only registers
+            _ => 0,
+        }
+    }
+
+    pub fn write_port(&mut self, addr: u16, value: u8, cycle: u64) {
+        self.catch_up(cycle);
+        self.synced = cycle;
+
+        match addr {
+            // WRMPYA
+            0x4202 => self.multiplicand = value,
+
+            // WRMPYB, starts the multiplication
+            0x4203 => {
+                // The multiplier sits in the high byte of RDDIV, and ends up there once done
+                self.quotient = u16::from(value) << 8 | u16::from(self.multiplicand);
+                if !self.busy() {
+                    self.product = 0;
+                    self.shift = u32::from(value);
+                    self.multiply_steps = MULTIPLY_STEPS;
+                }
+            }
+
+            // WRDIVL/H
+            0x4204 => self.dividend = (self.dividend & 0xff00) | u16::from(value),
+            0x4205 => self.dividend = (self.dividend & 0x00ff) | u16::from(value) << 8,
+
+            // WRDIVB, starts the division. Dividing by zero gives $FFFF, remainder the dividend
+            0x4206 if !self.busy() => {
+                self.product = self.dividend;
+                self.shift = u32::from(value) << 16;
+                self.divide_steps = DIVIDE_STEPS;
+            }
+
+            // Read-only registers, or WRDIVB while busy
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_word(math: &mut Math, addr: u16, cycle: u64) -> u16 {
+        u16::from_le_bytes([math.read_port(addr, cycle), math.read_port(addr + 1, cycle)])
+    }
+
+    #[test]
+    fn multiply() {
+        let mut math = Math::default();
+        math.write_port(0x4202, 0xff, 0);
+        math.write_port(0x4203, 0xfe, 0);
+
+        // Half of the multiplicand's bits have been added in
+        assert_eq!(read_word(&mut math, 0x4216, 4 * CYCLES_PER_STEP), 0x0f * 0xfe);
+        assert_eq!(read_word(&mut math, 0x4216, 8 * CYCLES_PER_STEP), 0xff * 0xfe);
+        assert_eq!(read_word(&mut math, 0x4214, 8 * CYCLES_PER_STEP), 0x00fe);
+    }
+
+    #[test]
+    fn divide() {
+        let mut math = Math::default();
+        math.write_port(0x4204, 0x39, 100);
+        math.write_port(0x4205, 0x30, 100);
+        math.write_port(0x4206, 0x07, 100);
+
+        let done = 100 + 16 * CYCLES_PER_STEP;
+        assert_ne!(read_word(&mut math, 0x4214, done - CYCLES_PER_STEP), 0x3039 / 7);
+        assert_eq!(read_word(&mut math, 0x4214, done), 0x3039 / 7);
+        assert_eq!(read_word(&mut math, 0x4216, done), 0x3039 % 7);
+
+        math.write_port(0x4206, 0x00, done);
+        assert_eq!(read_word(&mut math, 0x4214, done + 200), 0xffff);
+        assert_eq!(read_word(&mut math, 0x4216, done + 200), 0x3039);
+    }
+}
@@ -31,10 +31,14 @@ macro_rules! define_registers {
             ),*
         }
 
+        save_state! { $struct { $($name),* } }
+
         $(
             #[derive(Default)] 
             pub struct $reg(pub u8);
 
+            save_state! { $reg { 0 } }
+
             impl_register_map!( $struct { type: $reg, name: $name });
         )*
     }
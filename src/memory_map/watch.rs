@@ -0,0 +1,94 @@
+//! Watchpoints on the accesses the CPU and DMA make to memory.
+
+use std::ops::RangeInclusive;
+
+/// A kind of memory access.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+
+    /// The CPU fetching an opcode
+    Execute,
+}
+
+/// A test on the value read, written or executed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Condition {
+    Any,
+    Equal(u8),
+    NotEqual(u8),
+    Less(u8),
+    Greater(u8),
+}
+
+impl Condition {
+    fn matches(self, value: u8) -> bool {
+        match self {
+            Condition::Any => true,
+            Condition::Equal(expected) => value == expected,
+            Condition::NotEqual(expected) => value != expected,
+            Condition::Less(limit) => value < limit,
+            Condition::Greater(limit) => value > limit,
+        }
+    }
+}
+
+/// Triggers on accesses of some kinds to a range of 24-bit addresses, when the value meets a
+/// condition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watchpoint {
+    pub addresses: RangeInclusive<u32>,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+    pub condition: Condition,
+}
+
+impl Watchpoint {
+    fn matches(&self, access: Access, address: u32, value: u8) -> bool {
+        let kind = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        };
+        kind && self.addresses.contains(&address) && self.condition.matches(value)
+    }
+}
+
+/// The access that triggered a watchpoint.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WatchHit {
+    /// The index of the watchpoint
+    pub index: usize,
+    pub access: Access,
+    pub address: u32,
+    pub value: u8,
+}
+
+/// The watchpoints set, and the first one triggered since they were last checked.
+#[derive(Default)]
+pub(crate) struct Watches {
+    pub points: Vec<Watchpoint>,
+    pub hit: Option<WatchHit>,
+}
+
+impl Watches {
+    pub fn check(&mut self, access: Access, bank: u8, addr: u16, value: u8) {
+        if self.points.is_empty() || self.hit.is_some() {
+            return;
+        }
+
+        let address = u32::from(bank) << 16 | u32::from(addr);
+        let index = self
+            .points
+            .iter()
+            .position(|point| point.matches(access, address, value));
+        self.hit = index.map(|index| WatchHit {
+            index,
+            access,
+            address,
+            value,
+        });
+    }
+}
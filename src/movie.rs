@@ -0,0 +1,242 @@
+//! Input movies: the buttons held on both pads every frame, replayed from power on or from a
+//! save state.
+//!
+//! A movie file begins with a signature, the format version, the CRC-32 of the ROM and the
+//! version of the emulator that recorded it. A flag and an optional save state give the starting
+//! point, and the buttons of both ports follow, two little endian words per frame.
+
+use crate::input::Buttons;
+use crate::state::StateError;
+use std::fmt;
+
+const SIGNATURE: &[u8; 8] = b"SNESMOVI";
+
+/// Bump whenever the layout of the file changes.
+const VERSION: u16 = 1;
+
+/// Why a movie couldn't be loaded or played.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MovieError {
+    /// The file doesn't begin with the movie signature
+    InvalidSignature,
+
+    /// The file was written in an incompatible format
+    UnsupportedVersion(u16),
+
+    /// The movie was recorded with a different ROM
+    WrongRom,
+
+    /// The file ends before the movie does
+    TooShort,
+
+    /// The embedded save state couldn't be loaded
+    State(StateError),
+
+    /// Something other than a standard pad is plugged into a port, whose input a movie can't hold
+    UnsupportedController(usize),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::InvalidSignature => write!(f, "not a movie file"),
+            MovieError::UnsupportedVersion(version) => {
+                write!(f, "unsupported movie version {}", version)
+            }
+            MovieError::WrongRom => write!(f, "the movie was recorded with a different ROM"),
+            MovieError::TooShort => write!(f, "the movie file is truncated"),
+            MovieError::State(error) => write!(f, "the movie's save state is invalid: {}", error),
+            MovieError::UnsupportedController(port) => {
+                write!(f, "port {} doesn't hold a standard pad", port)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<StateError> for MovieError {
+    fn from(error: StateError) -> MovieError {
+        match error {
+            StateError::WrongRom => MovieError::WrongRom,
+            error => MovieError::State(error),
+        }
+    }
+}
+
+/// A recording of the buttons held on the pads in both ports, frame by frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    rom_hash: u32,
+    emulator_version: String,
+
+    /// The save state the movie starts from, or `None` to start from power on
+    start: Option<Vec<u8>>,
+
+    frames: Vec<[Buttons; 2]>,
+}
+
+impl Movie {
+    pub(crate) fn new(rom_hash: u32, start: Option<Vec<u8>>) -> Movie {
+        Movie {
+            rom_hash,
+            emulator_version: env!("CARGO_PKG_VERSION").to_string(),
+            start,
+            frames: Vec::new(),
+        }
+    }
+
+    pub(crate) fn rom_hash(&self) -> u32 {
+        self.rom_hash
+    }
+
+    pub(crate) fn start(&self) -> Option<&[u8]> {
+        self.start.as_deref()
+    }
+
+    pub(crate) fn push(&mut self, buttons: [Buttons; 2]) {
+        self.frames.push(buttons);
+    }
+
+    /// Keep only the first `frames` frames.
+    pub(crate) fn truncate(&mut self, frames: usize) {
+        self.frames.truncate(frames);
+    }
+
+    /// The version of the emulator the movie was recorded with.
+    pub fn emulator_version(&self) -> &str {
+        &self.emulator_version
+    }
+
+    /// Does the movie start from power on, rather than a save state?
+    pub fn from_power_on(&self) -> bool {
+        self.start.is_none()
+    }
+
+    /// The buttons held on each port, frame by frame.
+    pub fn frames(&self) -> &[[Buttons; 2]] {
+        &self.frames
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut file = Vec::new();
+        file.extend_from_slice(SIGNATURE);
+        file.extend_from_slice(&VERSION.to_le_bytes());
+        file.extend_from_slice(&self.rom_hash.to_le_bytes());
+
+        let version = self.emulator_version.as_bytes();
+        file.push(version.len() as u8);
+        file.extend_from_slice(version);
+
+        match &self.start {
+            Some(state) => {
+                file.push(1);
+                file.extend_from_slice(&(state.len() as u32).to_le_bytes());
+                file.extend_from_slice(state);
+            }
+            None => file.push(0),
+        }
+
+        file.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        for ports in &self.frames {
+            for buttons in ports {
+                file.extend_from_slice(&buttons.0.to_le_bytes());
+            }
+        }
+        file
+    }
+
+    pub fn from_bytes(mut file: &[u8]) -> Result<Movie, MovieError> {
+        if !file.starts_with(SIGNATURE) {
+            return Err(MovieError::InvalidSignature);
+        }
+        file = &file[SIGNATURE.len()..];
+
+        let version = take(&mut file, 2)?;
+        let version = u16::from_le_bytes([version[0], version[1]]);
+        if version != VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+
+        let rom_hash = read_u32(&mut file)?;
+
+        let length = usize::from(take(&mut file, 1)?[0]);
+        let emulator_version = String::from_utf8_lossy(take(&mut file, length)?).into_owned();
+
+        let start = if take(&mut file, 1)?[0] != 0 {
+            let length = read_u32(&mut file)? as usize;
+            Some(take(&mut file, length)?.to_vec())
+        } else {
+            None
+        };
+
+        let length = read_u32(&mut file)? as usize;
+        let frames = take(&mut file, 4 * length)?
+            .chunks_exact(4)
+            .map(|frame| {
+                [
+                    Buttons(u16::from_le_bytes([frame[0], frame[1]])),
+                    Buttons(u16::from_le_bytes([frame[2], frame[3]])),
+                ]
+            })
+            .collect();
+
+        Ok(Movie {
+            rom_hash,
+            emulator_version,
+            start,
+            frames,
+        })
+    }
+}
+
+fn take<'a>(file: &mut &'a [u8], length: usize) -> Result<&'a [u8], MovieError> {
+    if file.len() < length {
+        return Err(MovieError::TooShort);
+    }
+
+    let (bytes, rest) = file.split_at(length);
+    *file = rest;
+    Ok(bytes)
+}
+
+fn read_u32(file: &mut &[u8]) -> Result<u32, MovieError> {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(take(file, 4)?);
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// What is being done with a movie.
+pub(crate) enum MovieMode {
+    /// Adding the buttons held every frame
+    Recording(Movie),
+
+    /// Setting the buttons from the movie every frame, the next one being `frame`
+    Playing { movie: Movie, frame: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_round_trip() {
+        let mut movie = Movie::new(0x1234_5678, Some(vec![1, 2, 3]));
+        movie.push([Buttons::A, Buttons::NONE]);
+        movie.push([Buttons::B | Buttons::UP, Buttons::START]);
+
+        let file = movie.to_bytes();
+        assert_eq!(Movie::from_bytes(&file), Ok(movie.clone()));
+        assert_eq!(
+            Movie::from_bytes(&file[..file.len() - 1]),
+            Err(MovieError::TooShort)
+        );
+        assert_eq!(
+            Movie::from_bytes(b"SNESSAVE"),
+            Err(MovieError::InvalidSignature)
+        );
+
+        let movie = Movie::new(0, None);
+        assert!(Movie::from_bytes(&movie.to_bytes()).unwrap().from_power_on());
+    }
+}
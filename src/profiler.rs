@@ -0,0 +1,338 @@
+//! A profiler for emulated code, attributing master clock cycles to the routines on the emulated
+//! call stack.
+//!
+//! Routines are entered by JSR, JSL and interrupts, and left by RTS, RTL and RTI. Each routine is
+//! known by the address it was entered at. Cycles spent in an instruction go to the routine
+//! executing it, so a call belongs to the caller and a return to the callee.
+
+use crate::symbols::Symbols;
+use std::collections::HashMap;
+
+/// A routine being executed.
+struct Frame {
+    routine: u32,
+
+    /// The cycles profiled when it was entered
+    entered: u64,
+}
+
+/// Calls and cycles of a routine, or of the calls from one routine to another.
+#[derive(Default, Copy, Clone)]
+struct Totals {
+    calls: u64,
+
+    /// Cycles spent in the routine itself
+    own: u64,
+
+    /// Cycles spent in the routine and the routines it called
+    total: u64,
+}
+
+/// Cycles spent by every routine, and by every call stack, since profiling began.
+pub struct Profiler {
+    stack: Vec<Frame>,
+
+    /// The routines on the stack, outermost first
+    path: Vec<u32>,
+
+    cycles: u64,
+    routines: HashMap<u32, Totals>,
+
+    /// Calls and cycles by caller and callee
+    calls: HashMap<(u32, u32), Totals>,
+
+    /// Cycles spent with each call stack on top, outermost routine first
+    stacks: HashMap<Vec<u32>, u64>,
+}
+
+impl Profiler {
+    /// Start profiling, inside a routine at `address`.
+    pub(crate) fn new(address: u32) -> Profiler {
+        let mut routines = HashMap::new();
+        routines.insert(address, Totals::default());
+
+        Profiler {
+            stack: vec![Frame {
+                routine: address,
+                entered: 0,
+            }],
+            path: vec![address],
+            cycles: 0,
+            routines,
+            calls: HashMap::new(),
+            stacks: HashMap::new(),
+        }
+    }
+
+    /// Account for a step of the CPU that took `cycles` and left the program counter at `pc`. The
+    /// step either executed `opcode` or, when it is `None`, entered an interrupt handler.
+    pub(crate) fn record(&mut self, opcode: Option<u8>, cycles: u64, pc: u32) {
+        match opcode {
+            None => {
+                self.enter(pc);
+                self.spend(cycles);
+            }
+            // BRK and COP, whose handlers end with RTI, then JSR, JSL and JSR (addr,X)
+            Some(0x00) | Some(0x02) | Some(0x20) | Some(0x22) | Some(0xfc) => {
+                self.spend(cycles);
+                self.enter(pc);
+            }
+            // RTS, RTL and RTI
+            Some(0x60) | Some(0x6b) | Some(0x40) => {
+                self.spend(cycles);
+                self.leave();
+            }
+            Some(_) => self.spend(cycles),
+        }
+    }
+
+    fn spend(&mut self, cycles: u64) {
+        self.cycles += cycles;
+
+        let routine = self.stack.last().unwrap().routine;
+        self.routines.entry(routine).or_default().own += cycles;
+
+        // Only allocate for call stacks not seen before
+        match self.stacks.get_mut(self.path.as_slice()) {
+            Some(total) => *total += cycles,
+            None => {
+                self.stacks.insert(self.path.clone(), cycles);
+            }
+        }
+    }
+
+    fn enter(&mut self, routine: u32) {
+        let caller = self.stack.last().unwrap().routine;
+        self.routines.entry(routine).or_default().calls += 1;
+        self.calls.entry((caller, routine)).or_default().calls += 1;
+
+        self.stack.push(Frame {
+            routine,
+            entered: self.cycles,
+        });
+        self.path.push(routine);
+    }
+
+    /// Return from the routine on top of the stack. Returns from where profiling began are
+    /// ignored, as there is no caller known to return to.
+    fn leave(&mut self) {
+        if self.stack.len() == 1 {
+            return;
+        }
+
+        let frame = self.stack.pop().unwrap();
+        self.path.pop();
+        let caller = self.stack.last().unwrap().routine;
+        let cycles = self.cycles - frame.entered;
+
+        // Recursive calls are counted in the outermost call
+        if self
+            .stack
+            .iter()
+            .all(|outer| outer.routine != frame.routine)
+        {
+            self.routines.entry(frame.routine).or_default().total += cycles;
+        }
+        self.calls.entry((caller, frame.routine)).or_default().total += cycles;
+    }
+
+    /// The totals of every routine, counting the cycles of the calls that haven't returned yet.
+    fn routine_totals(&self) -> Vec<(u32, Totals)> {
+        let mut routines = self.routines.clone();
+        for (depth, frame) in self.stack.iter().enumerate() {
+            if self.stack[..depth]
+                .iter()
+                .all(|outer| outer.routine != frame.routine)
+            {
+                routines.get_mut(&frame.routine).unwrap().total += self.cycles - frame.entered;
+            }
+        }
+
+        let mut routines: Vec<(u32, Totals)> = routines.into_iter().collect();
+        routines.sort_by_key(|&(address, totals)| (std::cmp::Reverse(totals.total), address));
+        routines
+    }
+
+    /// A table of every routine with its calls and cycles, most expensive first.
+    pub fn flat_report(&self, symbols: &Symbols) -> String {
+        let mut routines = self.routine_totals();
+        routines.sort_by_key(|&(address, totals)| (std::cmp::Reverse(totals.own), address));
+
+        let mut report = String::from(" Own %  Own cycles  Total cycles    Calls  Routine\n");
+        for (address, totals) in routines {
+            report += &format!(
+                "{:>5.1}% {:>11} {:>13} {:>8}  {}\n",
+                percent(totals.own, self.cycles),
+                totals.own,
+                totals.total,
+                totals.calls,
+                name(address, symbols)
+            );
+        }
+        report
+    }
+
+    /// Every routine, most expensive first, with the routines that called it and that it called.
+    pub fn call_graph_report(&self, symbols: &Symbols) -> String {
+        let mut report = String::new();
+        for (address, totals) in self.routine_totals() {
+            report += &format!(
+                "{}: {} cycles ({:.1}%), {} own, {} calls\n",
+                name(address, symbols),
+                totals.total,
+                percent(totals.total, self.cycles),
+                totals.own,
+                totals.calls
+            );
+
+            let mut callers: Vec<_> = self
+                .calls
+                .iter()
+                .filter(|((_, callee), _)| *callee == address)
+                .collect();
+            callers.sort_by_key(|&(&(caller, _), _)| caller);
+            for ((caller, _), call) in callers {
+                report += &format!(
+                    "    called by {} {} times\n",
+                    name(*caller, symbols),
+                    call.calls
+                );
+            }
+
+            let mut callees: Vec<_> = self
+                .calls
+                .iter()
+                .filter(|((caller, _), _)| *caller == address)
+                .collect();
+            callees.sort_by_key(|&(&(_, callee), call)| (std::cmp::Reverse(call.total), callee));
+            for ((_, callee), call) in callees {
+                report += &format!(
+                    "    calls {} {} times, {} cycles\n",
+                    name(*callee, symbols),
+                    call.calls,
+                    call.total
+                );
+            }
+        }
+        report
+    }
+
+    /// The cycles spent in each call stack, in the folded format read by flamegraph.pl and
+    /// inferno: the routines from the outermost in, separated by semicolons, then the cycles.
+    pub fn folded_stacks(&self, symbols: &Symbols) -> String {
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(stack, cycles)| {
+                let names: Vec<String> = stack
+                    .iter()
+                    .map(|&address| name(address, symbols))
+                    .collect();
+                format!("{} {}", names.join(";"), cycles)
+            })
+            .collect();
+        lines.sort();
+
+        let mut folded = lines.join("\n");
+        folded.push('\n');
+        folded
+    }
+}
+
+/// A routine's label, or its address if it has none.
+fn name(address: u32, symbols: &Symbols) -> String {
+    match symbols.label(address) {
+        Some(label) => label.replace([';', ' '], "_"),
+        None => format!("{:02X}:{:04X}", address >> 16, address & 0xffff),
+    }
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    100.0 * part as f64 / whole.max(1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::build_rom;
+    use crate::Snes;
+
+    #[test]
+    fn call_stack() {
+        const JSR: Option<u8> = Some(0x20);
+        const RTS: Option<u8> = Some(0x60);
+        const RTI: Option<u8> = Some(0x40);
+        const NOP: Option<u8> = Some(0xea);
+
+        let mut profiler = Profiler::new(0x008000);
+        profiler.record(NOP, 10, 0x008001);
+        profiler.record(JSR, 20, 0x009000);
+        profiler.record(NOP, 30, 0x009001);
+        profiler.record(None, 40, 0x00a000);
+        profiler.record(RTI, 50, 0x009001);
+        profiler.record(RTS, 60, 0x008004);
+        profiler.record(RTS, 70, 0x008005);
+
+        let mut symbols = Symbols::new();
+        symbols.insert("main", 0x008000);
+        symbols.insert("nmi", 0x00a000);
+
+        assert_eq!(
+            profiler.folded_stacks(&symbols),
+            "main 100\nmain;00:9000 90\nmain;00:9000;nmi 90\n"
+        );
+
+        let flat = profiler.flat_report(&symbols);
+        let lines: Vec<&str> = flat.lines().collect();
+        assert_eq!(lines[1], " 35.7%         100           280        0  main");
+        assert_eq!(
+            lines[2],
+            " 32.1%          90           180        1  00:9000"
+        );
+        assert_eq!(lines[3], " 32.1%          90            90        1  nmi");
+
+        let graph = profiler.call_graph_report(&symbols);
+        assert!(graph.contains("00:9000: 180 cycles (64.3%), 90 own, 1 calls\n"));
+        assert!(graph.contains("    called by main 1 times\n    calls nmi 1 times, 90 cycles\n"));
+    }
+
+    #[test]
+    fn calls_on_the_cpu() {
+        // jsr $9100; jsl $009200; loop: jmp loop
+        let main = [0x20, 0x00, 0x91, 0x22, 0x00, 0x92, 0x00, 0x4c, 0x07, 0x80];
+        // lda #$01; rts
+        let short = [0xa9, 0x01, 0x60];
+        // jsr $9100; rtl
+        let long = [0x20, 0x00, 0x91, 0x6b];
+        let rom = build_rom(&[(0x8000, &main), (0x9100, &short), (0x9200, &long)]);
+        let mut snes = Snes::new(&rom);
+        snes.core.registers.stack_pointer = 0x01ff;
+
+        snes.enable_profiler();
+        for _ in 0..8 {
+            snes.step();
+        }
+        assert_eq!(snes.core.registers.program_address(), 0x008007);
+
+        let mut symbols = Symbols::new();
+        symbols.insert("main", 0x008000);
+        symbols.insert("short", 0x009100);
+        symbols.insert("long", 0x009200);
+
+        let profiler = snes.profiler().unwrap();
+        assert_eq!(profiler.stack.len(), 1, "back in main");
+        let folded = profiler.folded_stacks(&symbols);
+        let stacks: Vec<&str> = folded
+            .lines()
+            .map(|line| line.rsplit_once(' ').unwrap().0)
+            .collect();
+        assert_eq!(
+            stacks,
+            ["main", "main;long", "main;long;short", "main;short"]
+        );
+        assert!(profiler
+            .call_graph_report(&symbols)
+            .lines()
+            .any(|line| line.starts_with("short:") && line.ends_with(", 2 calls")));
+    }
+}
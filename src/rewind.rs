@@ -0,0 +1,241 @@
+//! Rewinding, by keeping a save state for every frame.
+//!
+//! States are grouped behind keyframes. A keyframe is stored on its own, and the states after it
+//! as deltas against it. Both are encoded as runs of bytes copied from the base, which is all
+//! zeros for keyframes, and runs of literal bytes. Little changes between frames, so deltas are
+//! small.
+
+use std::collections::VecDeque;
+
+/// Frames between keyframes.
+const KEYFRAME_INTERVAL: usize = 60;
+
+/// Literal runs end once this many bytes in a row match the base.
+const MIN_COPY: usize = 4;
+
+/// A keyframe and the deltas against it.
+struct Group {
+    keyframe: Vec<u8>,
+    deltas: Vec<Vec<u8>>,
+}
+
+impl Group {
+    fn size(&self) -> usize {
+        self.keyframe.len() + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+
+    fn len(&self) -> usize {
+        1 + self.deltas.len()
+    }
+}
+
+/// A history of save states, one per frame, kept within a memory budget.
+pub(crate) struct Rewind {
+    groups: VecDeque<Group>,
+
+    /// The newest keyframe, decoded
+    base: Vec<u8>,
+
+    /// Bytes taken by the encoded states, and the most they may take
+    size: usize,
+    budget: usize,
+}
+
+impl Rewind {
+    pub fn new(budget: usize) -> Rewind {
+        Rewind {
+            groups: VecDeque::new(),
+            base: Vec::new(),
+            size: 0,
+            budget,
+        }
+    }
+
+    /// The number of states kept.
+    pub fn len(&self) -> usize {
+        self.groups.iter().map(Group::len).sum()
+    }
+
+    /// Bytes taken by the states kept.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Add the newest state, dropping the oldest ones if over budget.
+    pub fn push(&mut self, state: &[u8]) {
+        match self.groups.back_mut() {
+            Some(group) if group.len() < KEYFRAME_INTERVAL => {
+                let delta = encode(&self.base, state);
+                self.size += delta.len();
+                group.deltas.push(delta);
+            }
+            _ => {
+                let keyframe = encode(&[], state);
+                self.size += keyframe.len();
+                self.groups.push_back(Group {
+                    keyframe,
+                    deltas: Vec::new(),
+                });
+                self.base = state.to_vec();
+            }
+        }
+
+        // The newest group is always kept, so that there is something to rewind to
+        while self.size > self.budget && self.groups.len() > 1 {
+            let group = self.groups.pop_front().unwrap();
+            self.size -= group.size();
+        }
+    }
+
+    /// Drop every state kept.
+    pub fn clear(&mut self) {
+        self.groups.clear();
+        self.base.clear();
+        self.size = 0;
+    }
+
+    /// Drop the newest `frames` states, and return the state that is now the newest. Stops at
+    /// the oldest state kept.
+    pub fn rewind(&mut self, frames: usize) -> Option<Vec<u8>> {
+        let frames = frames.min(self.len().checked_sub(1)?);
+
+        for _ in 0..frames {
+            let group = self.groups.back_mut().unwrap();
+            if let Some(delta) = group.deltas.pop() {
+                self.size -= delta.len();
+            } else {
+                self.size -= group.keyframe.len();
+                self.groups.pop_back();
+            }
+        }
+
+        let group = self.groups.back().unwrap();
+        if frames > 0 {
+            self.base = decode(&[], &group.keyframe);
+        }
+        Some(match group.deltas.last() {
+            Some(delta) => decode(&self.base, delta),
+            None => self.base.clone(),
+        })
+    }
+}
+
+fn write_number(output: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        output.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+fn read_number(input: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = input[0];
+        *input = &input[1..];
+        value |= usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+/// Encode `state` as the state's length followed by pairs of runs: bytes copied from `base`, then
+/// literal bytes. Past the end of `base`, the bytes copied are zeros.
+fn encode(base: &[u8], state: &[u8]) -> Vec<u8> {
+    let matches = |index: usize| state[index] == base.get(index).copied().unwrap_or(0);
+
+    let mut output = Vec::new();
+    write_number(&mut output, state.len());
+
+    let mut index = 0;
+    while index < state.len() {
+        let copy_start = index;
+        while index < state.len() && matches(index) {
+            index += 1;
+        }
+
+        let literal_start = index;
+        while index < state.len() {
+            let end = (index + MIN_COPY).min(state.len());
+            if (index..end).all(matches) {
+                break;
+            }
+            index += 1;
+        }
+
+        write_number(&mut output, literal_start - copy_start);
+        write_number(&mut output, index - literal_start);
+        output.extend_from_slice(&state[literal_start..index]);
+    }
+
+    output
+}
+
+fn decode(base: &[u8], mut encoded: &[u8]) -> Vec<u8> {
+    let length = read_number(&mut encoded);
+    let mut state = Vec::with_capacity(length);
+
+    while state.len() < length {
+        let start = state.len();
+        let end = start + read_number(&mut encoded);
+        if start < base.len() {
+            state.extend_from_slice(&base[start..end.min(base.len())]);
+        }
+        state.resize(end, 0);
+
+        let literal = read_number(&mut encoded);
+        state.extend_from_slice(&encoded[..literal]);
+        encoded = &encoded[literal..];
+    }
+
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        let base: Vec<u8> = (0..1000).map(|index| (index * 7) as u8).collect();
+
+        let mut state = base.clone();
+        state[10] = 0xff;
+        state[500..510].copy_from_slice(&[1; 10]);
+        state.extend_from_slice(&[0, 0, 9]);
+
+        let delta = encode(&base, &state);
+        assert!(delta.len() < 32);
+        assert_eq!(decode(&base, &delta), state);
+
+        // Shorter than the base, and against nothing at all
+        assert_eq!(decode(&base, &encode(&base, &state[..50])), &state[..50]);
+        assert_eq!(decode(&[], &encode(&[], &state)), state);
+    }
+
+    #[test]
+    fn budget() {
+        let state = |frame: usize| -> Vec<u8> {
+            let mut state = vec![0; 4096];
+            state[frame % 4096] = 1;
+            state[100..200].iter_mut().for_each(|byte| *byte = frame as u8);
+            state
+        };
+
+        let mut rewind = Rewind::new(32 * 1024);
+        for frame in 0..1000 {
+            rewind.push(&state(frame));
+        }
+        assert!(rewind.size <= 32 * 1024);
+        assert!(rewind.len() > 200 && rewind.len() < 1000);
+
+        let oldest = 1000 - rewind.len();
+        assert_eq!(rewind.rewind(1), Some(state(998)));
+        assert_eq!(rewind.rewind(100), Some(state(898)));
+        assert_eq!(rewind.rewind(10_000), Some(state(oldest)));
+        assert_eq!(rewind.len(), 1);
+    }
+}
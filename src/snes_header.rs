@@ -1,3 +1,4 @@
+use crate::timing::Region;
 
 #[derive(Debug)]
 pub struct SnesHeader<'a> {
@@ -11,6 +12,9 @@ pub struct SnesHeader<'a> {
     /// The logarithmic size of the SRAM in kB. #bytes = `1024 << rom_size`
     pub sram_size: u8,
 
+    /// The destination country, which determines the video standard
+    pub country: u8,
+
     pub creator_id: u8,
     pub version: u8,
     pub checksum_complement: u8,
@@ -65,7 +69,8 @@ impl<'a> SnesHeader<'a> {
             kind: RomKind::Rom,
             rom_size: bytes[23],
             sram_size: bytes[24],
-            creator_id: bytes[25],
+            country: bytes[25],
+            creator_id: bytes[26],
             version: bytes[27],
             checksum_complement: bytes[28],
             checksum: bytes[29],
@@ -75,6 +80,16 @@ impl<'a> SnesHeader<'a> {
     }
 }
 
+impl<'a> SnesHeader<'a> {
+    /// Europe, Scandinavia, China, Indonesia and Australia use PAL, the rest of the world NTSC.
+    pub fn region(&self) -> Region {
+        match self.country {
+            0x02..=0x0C | 0x11 => Region::Pal,
+            _ => Region::Ntsc,
+        }
+    }
+}
+
 impl RomMakeup {
     pub fn from_byte(byte: u8) -> RomMakeup {
         match byte {
@@ -0,0 +1,312 @@
+//! See https://wiki.superfamicom.org/spc700-reference
+
+mod spc700;
+use spc700::*;
+
+mod dsp;
+pub use dsp::SAMPLE_RATE;
+use dsp::*;
+
+mod timer;
+use timer::Timer;
+
+mod spc_file;
+pub use spc_file::{SpcError, SpcPlayer};
+
+/// Frequency of the SPC700's clock in Hz. The APU has its own oscillator, independent of the
+/// master clock.
+pub const SOUND_CLOCK: u64 = 1_024_000;
+
+const ARAM_SIZE: usize = 64 * 1024;
+
+/// The boot ROM, mapped at $FFC0-$FFFF until disabled through CONTROL. It waits for the main CPU
+/// to upload a program through the communication ports, then jumps to it.
+const IPL_ROM: [u8; 64] = [
+    0xcd, 0xef, 0xbd, 0xe8, 0x00, 0xc6, 0x1d, 0xd0, 0xfc, 0x8f, 0xaa, 0xf4, 0x8f, 0xbb, 0xf5, 0x78,
+    0xcc, 0xf4, 0xd0, 0xfb, 0x2f, 0x19, 0xeb, 0xf4, 0xd0, 0xfc, 0x7e, 0xf4, 0xd0, 0x0b, 0xe4, 0xf5,
+    0xcb, 0xf4, 0xd7, 0x00, 0xfc, 0xd0, 0xf3, 0xab, 0x01, 0x10, 0xef, 0x7e, 0xf4, 0x10, 0xeb, 0xba,
+    0xf6, 0xda, 0x00, 0xba, 0xf4, 0xc4, 0xf4, 0xdd, 0x5d, 0xd0, 0xdb, 0x1f, 0x00, 0x00, 0xc0, 0xff,
+];
+
+/// Sound Controller Chip: 8-bit Sony SPC700
+pub struct Sound {
+    processor: Spc700,
+    memory: SoundRam,
+
+    /// The master clock cycle the APU has been run up to
+    synced: u64,
+
+    /// Master clock cycles not yet spent running the SPC700, scaled by `SOUND_CLOCK`. Negative
+    /// when the last instruction ran past the point caught up to.
+    cycle_debt: i64,
+
+    /// SPC700 cycles since the DSP last generated a sample
+    dsp_cycles: u32,
+}
+
+/// Sound RAM: 64 KB (ARAM), together with the registers mapped into it at $F0-$FF.
+pub(crate) struct SoundRam {
+    data: [u8; ARAM_SIZE],
+
+    /// CONTROL: IPL ROM enable and timer enables
+    control: u8,
+
+    /// DSPADDR and the DSP whose registers it selects
+    dsp_address: u8,
+    dsp: Dsp,
+
+    /// CPUIO0-3 as written by the main CPU, read by the SPC700
+    input: [u8; 4],
+
+    /// CPUIO0-3 as written by the SPC700, read by the main CPU
+    output: [u8; 4],
+
+    /// Timers 0-2, set up through CONTROL and T0TARGET-T2TARGET, read through T0OUT-T2OUT
+    timers: [Timer; 3],
+}
+
+save_state! { Sound { processor, memory, synced, cycle_debt, dsp_cycles } }
+
+save_state! { SoundRam { data, control, dsp_address, dsp, input, output, timers } }
+
+impl SoundRam {
+    fn new() -> SoundRam {
+        SoundRam {
+            data: [0; ARAM_SIZE],
+            control: 0x80,
+            dsp_address: 0,
+            dsp: Dsp::new(),
+            input: [0; 4],
+            output: [0; 4],
+            timers: [Timer::new(0), Timer::new(1), Timer::new(2)],
+        }
+    }
+
+    fn ipl_enabled(&self) -> bool {
+        self.control & 0x80 != 0
+    }
+
+    pub fn get_byte(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x00F2 => self.dsp_address,
+            0x00F3 => self.dsp.read(self.dsp_address),
+            0x00F4..=0x00F7 => self.input[usize::from(addr - 0x00F4)],
+
+            // Counters are cleared when read
+            0x00FD..=0x00FF => self.timers[usize::from(addr - 0x00FD)].read_counter(),
+
+            // Write-only registers
+            0x00F0 | 0x00F1 | 0x00FA..=0x00FC => 0,
+
+            0xFFC0..=0xFFFF if self.ipl_enabled() => IPL_ROM[usize::from(addr - 0xFFC0)],
+
+            _ => self.data[usize::from(addr)],
+        }
+    }
+
+    /// Writes to the registers also reach the RAM underneath, as do writes to the IPL ROM.
+    pub fn set_byte(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x00F1 => self.write_control(value),
+            0x00F2 => self.dsp_address = value,
+            // The upper half of the DSP address space is a read-only mirror
+            0x00F3 if self.dsp_address < 0x80 => {
+                self.dsp.write(self.dsp_address, value);
+            }
+            0x00F4..=0x00F7 => self.output[usize::from(addr - 0x00F4)] = value,
+            0x00FA..=0x00FC => self.timers[usize::from(addr - 0x00FA)].target = value,
+            _ => {}
+        }
+
+        self.data[usize::from(addr)] = value;
+    }
+
+    fn write_control(&mut self, value: u8) {
+        if value & 0x10 != 0 {
+            self.input[0] = 0;
+            self.input[1] = 0;
+        }
+        if value & 0x20 != 0 {
+            self.input[2] = 0;
+            self.input[3] = 0;
+        }
+
+        for (index, timer) in self.timers.iter_mut().enumerate() {
+            timer.set_enabled(value & (1 << index) != 0);
+        }
+
+        self.control = value;
+    }
+
+    /// Read a little endian word. Used for vectors, which are never in the register area.
+    pub fn get_word(&mut self, addr: u16) -> u16 {
+        let low = self.get_byte(addr);
+        let high = self.get_byte(addr.wrapping_add(1));
+        u16::from_le_bytes([low, high])
+    }
+}
+
+impl Sound {
+    pub(crate) fn new() -> Sound {
+        let mut sound = Sound {
+            processor: Spc700::new(),
+            memory: SoundRam::new(),
+            synced: 0,
+            cycle_debt: 0,
+            dsp_cycles: 0,
+        };
+
+        sound.processor.reset(&mut sound.memory);
+        sound
+    }
+
+    /// Read one of the communication ports at $2140-$2143, mirrored through $217F.
+    pub(crate) fn read_port(&self, addr: u16) -> u8 {
+        self.memory.output[usize::from(addr & 0x03)]
+    }
+
+    /// Write one of the communication ports at $2140-$2143, mirrored through $217F.
+    pub(crate) fn write_port(&mut self, addr: u16, value: u8) {
+        self.memory.input[usize::from(addr & 0x03)] = value;
+    }
+
+    /// Run the APU up to a point in time, given in cycles of a master clock with the given
+    /// frequency. The main CPU catches the APU up before it touches the ports, so both sides see
+    /// each other's accesses in the order they happen.
+    pub(crate) fn catch_up(&mut self, cycle: u64, master_clock: u32) {
+        if cycle > self.synced {
+            self.run(cycle - self.synced, master_clock);
+            self.synced = cycle;
+        }
+    }
+
+    /// Run the SPC700 for as long as `cycles` cycles of a master clock with the given frequency.
+    /// Instructions are never split, so the APU may run slightly ahead, which is paid back on the
+    /// next run.
+    fn run(&mut self, cycles: u64, master_clock: u32) {
+        let master_clock = i64::from(master_clock);
+        self.cycle_debt += (cycles * SOUND_CLOCK) as i64;
+
+        while self.cycle_debt > 0 {
+            let spent = self.processor.step(&mut self.memory);
+            self.cycle_debt -= i64::from(spent) * master_clock;
+
+            for timer in &mut self.memory.timers {
+                timer.run(spent);
+            }
+
+            self.dsp_cycles += spent;
+            while self.dsp_cycles >= CYCLES_PER_SAMPLE {
+                self.dsp_cycles -= CYCLES_PER_SAMPLE;
+                self.memory.dsp.sample(&mut self.memory.data);
+            }
+        }
+    }
+
+    /// Take the samples generated since the last call, interleaved left and right, up to the
+    /// last second of them.
+    pub(crate) fn take_samples(&mut self) -> Vec<i16> {
+        self.memory.dsp.take_samples(usize::MAX).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MASTER_CLOCK: u32 = 21_477_272;
+
+    /// Run until port 0 reads back a value, as the main CPU does while talking to the IPL ROM.
+    fn wait_for(sound: &mut Sound, value: u8) {
+        for _ in 0..10_000 {
+            if sound.read_port(0x2140) == value {
+                return;
+            }
+            sound.run(64, MASTER_CLOCK);
+        }
+        panic!("port 0 never read {:02x}", value);
+    }
+
+    /// Upload a program through the IPL ROM's protocol and start it.
+    fn upload(sound: &mut Sound, addr: u16, program: &[u8]) {
+        wait_for(sound, 0xAA);
+        sound.run(1000, MASTER_CLOCK);
+        assert_eq!(sound.read_port(0x2141), 0xBB);
+
+        let [low, high] = addr.to_le_bytes();
+        sound.write_port(0x2141, 0x01);
+        sound.write_port(0x2142, low);
+        sound.write_port(0x2143, high);
+        sound.write_port(0x2140, 0xCC);
+        wait_for(sound, 0xCC);
+
+        for (index, &byte) in program.iter().enumerate() {
+            sound.write_port(0x2141, byte);
+            sound.write_port(0x2140, index as u8);
+            wait_for(sound, index as u8);
+        }
+
+        sound.write_port(0x2141, 0x00);
+        sound.write_port(0x2142, low);
+        sound.write_port(0x2143, high);
+        sound.write_port(0x2140, program.len() as u8 + 1);
+        wait_for(sound, program.len() as u8 + 1);
+    }
+
+    #[test]
+    fn ipl_upload() {
+        let mut sound = Sound::new();
+
+        // mov a, #$12; mov y, #$34; mul ya; movw $f5, ya; mov $f4, #$5a; bra -2
+        let program = [
+            0xe8, 0x12, 0x8d, 0x34, 0xcf, 0xda, 0xf5, 0x8f, 0x5a, 0xf4, 0x2f, 0xfe,
+        ];
+        upload(&mut sound, 0x0200, &program);
+        wait_for(&mut sound, 0x5a);
+
+        let product = u16::from_le_bytes([sound.read_port(0x2141), sound.read_port(0x2142)]);
+        assert_eq!(product, 0x12 * 0x34);
+
+        // Ports are separate in each direction
+        sound.write_port(0x2141, 0x99);
+        assert_ne!(sound.read_port(0x2141), 0x99);
+        assert_eq!(sound.memory.get_byte(0x00F5), 0x99);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut sound = Sound::new();
+        let program = [
+            // mov a, #$f0; clrc; adc a, #$20 -> $10, carry
+            0xe8, 0xf0, 0x60, 0x88, 0x20, 0xc4, 0x10,
+            // mov a, #$00; mov y, #$01; mov x, #$03; div ya, x -> $55 r1
+            0xe8, 0x00, 0x8d, 0x01, 0xcd, 0x03, 0x9e, 0xc4, 0x11, 0xcb, 0x12,
+            // mov a, #$15; setc; sbc a, #$07 ; das a -> $08
+            0xe8, 0x15, 0x80, 0xa8, 0x07, 0xbe, 0xc4, 0x13, // stop
+            0xff,
+        ];
+        upload(&mut sound, 0x0300, &program);
+        sound.run(10_000, MASTER_CLOCK);
+
+        assert_eq!(sound.memory.get_byte(0x0010), 0x10);
+        assert_eq!(sound.memory.get_byte(0x0011), 0x55);
+        assert_eq!(sound.memory.get_byte(0x0012), 0x01);
+        assert_eq!(sound.memory.get_byte(0x0013), 0x08);
+    }
+
+    #[test]
+    fn every_opcode_decodes() {
+        let mut sound = Sound::new();
+        for opcode in 0..=0xff {
+            sound.processor.reset(&mut sound.memory);
+            sound.processor.registers.program_counter = 0x1000;
+            sound.processor.registers.index_x = 1;
+            for (offset, &byte) in [opcode, 0x20, 0x30, 0x40].iter().enumerate() {
+                sound.memory.set_byte(0x1000 + offset as u16, byte);
+            }
+
+            let cycles = sound.processor.step(&mut sound.memory);
+            assert!(cycles >= 2, "opcode {:02x} took {} cycles", opcode, cycles);
+        }
+    }
+}
@@ -0,0 +1,794 @@
+//! See https://wiki.superfamicom.org/spc700-reference#dsp
+//!
+//! The sample generation follows the order of operations of the hardware closely enough to match
+//! it sample for sample, but not within a sample.
+
+use crate::state::*;
+use std::collections::VecDeque;
+
+/// Output rate of the DSP in Hz, one stereo sample every 32 SPC700 cycles.
+pub const SAMPLE_RATE: u32 = 32_000;
+
+/// SPC700 cycles per sample.
+pub(super) const CYCLES_PER_SAMPLE: u32 = 32;
+
+/// Samples kept until they are taken, one second in both channels. Older ones are dropped, so
+/// that nothing piles up when nobody takes them.
+const MAX_SAMPLES: usize = 2 * SAMPLE_RATE as usize;
+
+// Voice registers, at $x0-$x9 for voice x
+const VOLUME_LEFT: usize = 0x0;
+const VOLUME_RIGHT: usize = 0x1;
+const PITCH_LOW: usize = 0x2;
+const PITCH_HIGH: usize = 0x3;
+const SOURCE: usize = 0x4;
+const ADSR1: usize = 0x5;
+const ADSR2: usize = 0x6;
+const GAIN: usize = 0x7;
+const ENVX: usize = 0x8;
+const OUTX: usize = 0x9;
+
+// Global registers
+const MAIN_VOLUME_LEFT: usize = 0x0C;
+const MAIN_VOLUME_RIGHT: usize = 0x1C;
+const ECHO_VOLUME_LEFT: usize = 0x2C;
+const ECHO_VOLUME_RIGHT: usize = 0x3C;
+const KEY_ON: usize = 0x4C;
+const KEY_OFF: usize = 0x5C;
+const FLAGS: usize = 0x6C;
+const END: usize = 0x7C;
+const ECHO_FEEDBACK: usize = 0x0D;
+const PITCH_MODULATION: usize = 0x2D;
+const NOISE_ENABLE: usize = 0x3D;
+const ECHO_ENABLE: usize = 0x4D;
+const DIRECTORY: usize = 0x5D;
+const ECHO_START: usize = 0x6D;
+const ECHO_DELAY: usize = 0x7D;
+
+/// FIR filter coefficients C0-C7, at $0F-$7F.
+const FIR: usize = 0x0F;
+
+/// The rate counter counts down through this range, events of each rate fire when it reaches a
+/// multiple of the rate's period.
+const COUNTER_RANGE: u16 = 2048 * 5 * 3;
+
+/// Periods of the 32 envelope and noise rates, in samples. Rate 0 never fires.
+const COUNTER_PERIODS: [u16; 32] = [
+    COUNTER_RANGE + 1,
+    2048,
+    1536,
+    1280,
+    1024,
+    768,
+    640,
+    512,
+    384,
+    320,
+    256,
+    192,
+    160,
+    128,
+    96,
+    80,
+    64,
+    48,
+    40,
+    32,
+    24,
+    20,
+    16,
+    12,
+    10,
+    8,
+    6,
+    5,
+    4,
+    3,
+    2,
+    1,
+];
+
+/// Phase of each rate relative to the counter.
+const COUNTER_OFFSETS: [u16; 32] = [
+    1, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040,
+    536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 0, 0,
+];
+
+/// The hardware's Gaussian interpolation table, one half of a symmetric curve.
+#[rustfmt::skip]
+const GAUSS: [i16; 512] = [
+    0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,
+    1,   1,   1,   1,   1,   1,   1,   1,   1,   1,   1,   2,   2,   2,   2,   2,
+    2,   2,   3,   3,   3,   3,   3,   4,   4,   4,   4,   4,   5,   5,   5,   5,
+    6,   6,   6,   6,   7,   7,   7,   8,   8,   8,   9,   9,   9,  10,  10,  10,
+    11,  11,  11,  12,  12,  13,  13,  14,  14,  15,  15,  15,  16,  16,  17,  17,
+    18,  19,  19,  20,  20,  21,  21,  22,  23,  23,  24,  24,  25,  26,  27,  27,
+    28,  29,  29,  30,  31,  32,  32,  33,  34,  35,  36,  36,  37,  38,  39,  40,
+    41,  42,  43,  44,  45,  46,  47,  48,  49,  50,  51,  52,  53,  54,  55,  56,
+    58,  59,  60,  61,  62,  64,  65,  66,  67,  69,  70,  71,  73,  74,  76,  77,
+    78,  80,  81,  83,  84,  86,  87,  89,  90,  92,  94,  95,  97,  99, 100, 102,
+    104, 106, 107, 109, 111, 113, 115, 117, 118, 120, 122, 124, 126, 128, 130, 132,
+    134, 137, 139, 141, 143, 145, 147, 150, 152, 154, 156, 159, 161, 163, 166, 168,
+    171, 173, 175, 178, 180, 183, 186, 188, 191, 193, 196, 199, 201, 204, 207, 210,
+    212, 215, 218, 221, 224, 227, 230, 233, 236, 239, 242, 245, 248, 251, 254, 257,
+    260, 263, 267, 270, 273, 276, 280, 283, 286, 290, 293, 297, 300, 304, 307, 311,
+    314, 318, 321, 325, 328, 332, 336, 339, 343, 347, 351, 354, 358, 362, 366, 370,
+    374, 378, 381, 385, 389, 393, 397, 401, 405, 410, 414, 418, 422, 426, 430, 434,
+    439, 443, 447, 451, 456, 460, 464, 469, 473, 477, 482, 486, 491, 495, 499, 504,
+    508, 513, 517, 522, 527, 531, 536, 540, 545, 550, 554, 559, 563, 568, 573, 577,
+    582, 587, 592, 596, 601, 606, 611, 615, 620, 625, 630, 635, 640, 644, 649, 654,
+    659, 664, 669, 674, 678, 683, 688, 693, 698, 703, 708, 713, 718, 723, 728, 732,
+    737, 742, 747, 752, 757, 762, 767, 772, 777, 782, 787, 792, 797, 802, 806, 811,
+    816, 821, 826, 831, 836, 841, 846, 851, 855, 860, 865, 870, 875, 880, 884, 889,
+    894, 899, 904, 908, 913, 918, 923, 927, 932, 937, 941, 946, 951, 955, 960, 965,
+    969, 974, 978, 983, 988, 992, 997,1001,1005,1010,1014,1019,1023,1027,1032,1036,
+    1040,1045,1049,1053,1057,1061,1066,1070,1074,1078,1082,1086,1090,1094,1098,1102,
+    1106,1109,1113,1117,1121,1125,1128,1132,1136,1139,1143,1146,1150,1153,1157,1160,
+    1164,1167,1170,1174,1177,1180,1183,1186,1190,1193,1196,1199,1202,1205,1207,1210,
+    1213,1216,1219,1221,1224,1227,1229,1232,1234,1237,1239,1241,1244,1246,1248,1251,
+    1253,1255,1257,1259,1261,1263,1265,1267,1269,1270,1272,1274,1275,1277,1279,1280,
+    1282,1283,1284,1286,1287,1288,1290,1291,1292,1293,1294,1295,1296,1297,1297,1298,
+    1299,1300,1300,1301,1302,1302,1303,1303,1303,1304,1304,1304,1304,1304,1305,1305,
+];
+
+/// Samples of decoded BRR kept by each voice, three groups of four.
+const BUFFER_SIZE: usize = 12;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum EnvelopeMode {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+#[derive(Copy, Clone)]
+struct Voice {
+    /// Decoded samples, as a ring buffer
+    buffer: [i16; BUFFER_SIZE],
+    buffer_position: usize,
+
+    /// Position between samples, 12 bits of fraction
+    interpolation_position: u16,
+
+    /// The BRR block being decoded, and the offset of the next data byte in it
+    brr_address: u16,
+    brr_offset: u16,
+
+    /// Samples left before a keyed on voice starts playing
+    key_on_delay: u8,
+
+    envelope_mode: EnvelopeMode,
+    envelope: u16,
+
+    /// The envelope as computed, before the rate decides whether it is used
+    hidden_envelope: i32,
+
+    /// Output of the last sample, the input of the next voice's pitch modulation
+    output: i32,
+}
+
+impl Default for Voice {
+    fn default() -> Voice {
+        Voice {
+            buffer: [0; BUFFER_SIZE],
+            buffer_position: 0,
+            interpolation_position: 0,
+            brr_address: 0,
+            brr_offset: 1,
+            key_on_delay: 0,
+            envelope_mode: EnvelopeMode::Release,
+            envelope: 0,
+            hidden_envelope: 0,
+            output: 0,
+        }
+    }
+}
+
+/// Sony S-DSP, mixes the eight voices of the sound module
+pub(super) struct Dsp {
+    registers: [u8; 128],
+    voices: [Voice; 8],
+
+    /// KON written since voices were last keyed on
+    key_on: u8,
+
+    /// Voices are keyed on and off every other sample
+    poll: bool,
+
+    counter: u16,
+    noise: u16,
+
+    /// The last eight samples read from the echo buffer, per channel
+    echo_history: [[i32; 2]; 8],
+    echo_history_position: usize,
+    echo_offset: u16,
+    echo_length: u16,
+
+    /// Interleaved left and right samples not yet taken, the most recent `MAX_SAMPLES`
+    samples: VecDeque<i16>,
+}
+
+// The samples not yet taken are left out
+save_state! {
+    Dsp {
+        registers, voices, key_on, poll, counter, noise, echo_history, echo_history_position,
+        echo_offset, echo_length
+    }
+}
+
+save_state! {
+    Voice {
+        buffer, buffer_position, interpolation_position, brr_address, brr_offset, key_on_delay,
+        envelope_mode, envelope, hidden_envelope, output
+    }
+}
+
+impl SaveState for EnvelopeMode {
+    fn save(&self, state: &mut Vec<u8>) {
+        (*self as u8).save(state);
+    }
+
+    fn load(&mut self, state: &mut &[u8]) -> Result<(), StateError> {
+        let mut mode = 0u8;
+        mode.load(state)?;
+        *self = match mode {
+            0 => EnvelopeMode::Attack,
+            1 => EnvelopeMode::Decay,
+            2 => EnvelopeMode::Sustain,
+            3 => EnvelopeMode::Release,
+            _ => return Err(StateError::Corrupted),
+        };
+        Ok(())
+    }
+}
+
+fn clamp16(value: i32) -> i32 {
+    value.clamp(i32::from(i16::MIN), i32::from(i16::MAX))
+}
+
+impl Dsp {
+    pub fn new() -> Dsp {
+        let mut registers = [0; 128];
+        // Muted and echo writes disabled, as after a soft reset
+        registers[FLAGS] = 0xE0;
+
+        Dsp {
+            registers,
+            voices: [Voice::default(); 8],
+            key_on: 0,
+            poll: false,
+            counter: 0,
+            noise: 0x4000,
+            echo_history: [[0; 2]; 8],
+            echo_history_position: 0,
+            echo_offset: 0,
+            echo_length: 0,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Take up to `max` of the oldest samples not taken yet.
+    pub fn take_samples(&mut self, max: usize) -> impl Iterator<Item = i16> + '_ {
+        let count = max.min(self.samples.len());
+        self.samples.drain(..count)
+    }
+
+    pub fn read(&self, addr: u8) -> u8 {
+        self.registers[usize::from(addr & 0x7f)]
+    }
+
+    pub fn registers(&self) -> &[u8; 128] {
+        &self.registers
+    }
+
+    /// Restore the registers from a snapshot. Voices that were keyed on start again from the
+    /// beginning of their samples.
+    pub fn load(&mut self, registers: &[u8; 128]) {
+        self.registers = *registers;
+        self.key_on = registers[KEY_ON] & !registers[KEY_OFF];
+        self.voices = [Voice::default(); 8];
+    }
+
+    pub fn write(&mut self, addr: u8, value: u8) {
+        let addr = usize::from(addr);
+        match addr {
+            KEY_ON => self.key_on |= value,
+
+            // Writing ENDX clears it
+            END => {
+                self.registers[END] = 0;
+                return;
+            }
+            _ => {}
+        }
+
+        self.registers[addr] = value;
+    }
+
+    fn voice_register(&self, voice: usize, register: usize) -> u8 {
+        self.registers[voice << 4 | register]
+    }
+
+    /// Does an event of the given rate happen on this sample?
+    fn rate_fires(&self, rate: usize) -> bool {
+        (self.counter + COUNTER_OFFSETS[rate]).is_multiple_of(COUNTER_PERIODS[rate])
+    }
+
+    /// Generate one stereo sample, reading samples from and writing echo to ARAM.
+    pub fn sample(&mut self, ram: &mut [u8]) {
+        self.counter = self.counter.checked_sub(1).unwrap_or(COUNTER_RANGE - 1);
+
+        let flags = self.registers[FLAGS];
+        if self.rate_fires(usize::from(flags & 0x1f)) {
+            let feedback = (self.noise << 13) ^ (self.noise << 14);
+            self.noise = (feedback & 0x4000) ^ (self.noise >> 1);
+        }
+
+        self.poll = !self.poll;
+        if self.poll {
+            self.poll_keys(ram);
+        }
+
+        let mut main = [0; 2];
+        let mut echo = [0; 2];
+        let mut previous_output = 0;
+
+        for index in 0..8 {
+            let output = self.run_voice(index, previous_output, ram);
+            previous_output = output;
+
+            let mask = 1 << index;
+            for (channel, register) in [VOLUME_LEFT, VOLUME_RIGHT].iter().enumerate() {
+                let volume = i32::from(self.voice_register(index, *register) as i8);
+                let amplitude = (output * volume) >> 7;
+
+                main[channel] = clamp16(main[channel] + amplitude);
+                if self.registers[ECHO_ENABLE] & mask != 0 {
+                    echo[channel] = clamp16(echo[channel] + amplitude);
+                }
+            }
+        }
+
+        let echo_input = self.run_echo(echo, ram);
+
+        let volumes = [
+            (MAIN_VOLUME_LEFT, ECHO_VOLUME_LEFT),
+            (MAIN_VOLUME_RIGHT, ECHO_VOLUME_RIGHT),
+        ];
+        // Drop the oldest sample in both channels to make room
+        if self.samples.len() >= MAX_SAMPLES {
+            self.samples.drain(..2);
+        }
+
+        for (channel, (main_volume, echo_volume)) in volumes.iter().enumerate() {
+            let main_volume = i32::from(self.registers[*main_volume] as i8);
+            let echo_volume = i32::from(self.registers[*echo_volume] as i8);
+
+            let value = clamp16(
+                ((main[channel] * main_volume) >> 7) + ((echo_input[channel] * echo_volume) >> 7),
+            );
+
+            // FLG bit 6 mutes the output
+            let value = if flags & 0x40 != 0 { 0 } else { value };
+            self.samples.push_back(value as i16);
+        }
+    }
+
+    /// Key voices on and off, and handle a soft reset.
+    fn poll_keys(&mut self, ram: &[u8]) {
+        let key_on = self.key_on;
+        let key_off = self.registers[KEY_OFF];
+        let reset = self.registers[FLAGS] & 0x80 != 0;
+        self.key_on = 0;
+
+        for index in 0..8 {
+            let mask = 1 << index;
+            let voice = &mut self.voices[index];
+
+            if reset || key_off & mask != 0 {
+                voice.envelope_mode = EnvelopeMode::Release;
+            }
+            if reset {
+                voice.envelope = 0;
+            }
+
+            if key_on & mask != 0 {
+                voice.key_on_delay = 5;
+                voice.envelope_mode = EnvelopeMode::Attack;
+                self.registers[END] &= !mask;
+
+                let source = self.registers[index << 4 | SOURCE];
+                let start = self.directory_entry(ram, source, 0);
+                self.voices[index].brr_address = start;
+            }
+        }
+    }
+
+    /// Read the start (offset 0) or loop (offset 2) address of a sample from the directory.
+    fn directory_entry(&self, ram: &[u8], source: u8, offset: u16) -> u16 {
+        let addr = u16::from(self.registers[DIRECTORY]) << 8;
+        let addr = addr.wrapping_add(u16::from(source) * 4 + offset);
+        let high = addr.wrapping_add(1);
+        u16::from_le_bytes([ram[usize::from(addr)], ram[usize::from(high)]])
+    }
+
+    /// Run a voice for one sample, returning its output before volume.
+    fn run_voice(&mut self, index: usize, previous_output: i32, ram: &mut [u8]) -> i32 {
+        let mask = 1 << index;
+        let pitch_register = u16::from_le_bytes([
+            self.voice_register(index, PITCH_LOW),
+            self.voice_register(index, PITCH_HIGH),
+        ]);
+        let mut pitch = i32::from(pitch_register & 0x3fff);
+        if index > 0 && self.registers[PITCH_MODULATION] & mask != 0 {
+            pitch += ((previous_output >> 5) * pitch) >> 10;
+        }
+
+        let mut voice = self.voices[index];
+
+        // A keyed on voice fills its buffer with three groups of samples, then starts
+        let delaying = voice.key_on_delay > 0;
+        if delaying {
+            if voice.key_on_delay == 5 {
+                voice.brr_offset = 1;
+                voice.buffer_position = 0;
+                voice.buffer = [0; BUFFER_SIZE];
+            }
+
+            voice.envelope = 0;
+            voice.hidden_envelope = 0;
+            voice.key_on_delay -= 1;
+            voice.interpolation_position = if voice.key_on_delay & 3 != 0 {
+                0x4000
+            } else {
+                0
+            };
+            pitch = 0;
+        }
+
+        let sample = if self.registers[NOISE_ENABLE] & mask != 0 {
+            i32::from((self.noise << 1) as i16)
+        } else {
+            interpolate(&voice)
+        };
+
+        let output = ((sample * i32::from(voice.envelope)) >> 11) & !1;
+        voice.output = output;
+
+        if !delaying {
+            self.run_envelope(index, &mut voice);
+        }
+
+        if voice.interpolation_position >= 0x4000 {
+            self.decode_brr(index, &mut voice, ram);
+        }
+
+        let position = i32::from(voice.interpolation_position & 0x3fff) + pitch;
+        voice.interpolation_position = position.min(0x7fff) as u16;
+
+        self.registers[index << 4 | ENVX] = (voice.envelope >> 4) as u8;
+        self.registers[index << 4 | OUTX] = (output >> 8) as u8;
+        self.voices[index] = voice;
+        output
+    }
+
+    /// Decode the next four samples of BRR into a voice's buffer, moving to the next block or
+    /// looping at the end of one.
+    fn decode_brr(&mut self, index: usize, voice: &mut Voice, ram: &[u8]) {
+        let read = |addr: u16| ram[usize::from(addr)];
+        let header = read(voice.brr_address);
+        let data = u16::from_be_bytes([
+            read(voice.brr_address.wrapping_add(voice.brr_offset)),
+            read(voice.brr_address.wrapping_add(voice.brr_offset + 1)),
+        ]);
+
+        let shift = header >> 4;
+        let filter = header & 0x0c;
+
+        for nibble in 0..4 {
+            // Sign extend the nibble, then scale it by the shift
+            let value = i32::from((data << (4 * nibble)) as i16 >> 12);
+            let mut value = if shift <= 12 {
+                (value << shift) >> 1
+            } else if value < 0 {
+                -2048
+            } else {
+                0
+            };
+
+            let position = voice.buffer_position;
+            let previous = |back: usize| {
+                i32::from(voice.buffer[(position + BUFFER_SIZE - back) % BUFFER_SIZE])
+            };
+            let p1 = previous(1);
+            let p2 = previous(2) >> 1;
+
+            match filter {
+                0x04 => {
+                    value += p1 >> 1;
+                    value += (-p1) >> 5;
+                }
+                0x08 => {
+                    value += p1 - p2;
+                    value += p2 >> 4;
+                    value += (p1 * -3) >> 6;
+                }
+                0x0c => {
+                    value += p1 - p2;
+                    value += (p1 * -13) >> 7;
+                    value += (p2 * 3) >> 4;
+                }
+                _ => {}
+            }
+
+            // Samples are 15 bits, stored doubled
+            voice.buffer[position] = (clamp16(value) << 1) as i16;
+            voice.buffer_position = (position + 1) % BUFFER_SIZE;
+        }
+
+        voice.brr_offset += 2;
+        if voice.brr_offset < 9 {
+            return;
+        }
+
+        // End of the block
+        voice.brr_offset = 1;
+        if header & 0x01 != 0 {
+            self.registers[END] |= 1 << index;
+
+            if header & 0x02 != 0 {
+                let source = self.voice_register(index, SOURCE);
+                voice.brr_address = self.directory_entry(ram, source, 2);
+            } else {
+                voice.envelope_mode = EnvelopeMode::Release;
+                voice.envelope = 0;
+            }
+        } else {
+            voice.brr_address = voice.brr_address.wrapping_add(9);
+        }
+    }
+
+    /// Step a voice's ADSR or GAIN envelope.
+    fn run_envelope(&self, index: usize, voice: &mut Voice) {
+        let mut envelope = i32::from(voice.envelope);
+
+        if voice.envelope_mode == EnvelopeMode::Release {
+            voice.envelope = (envelope - 8).max(0) as u16;
+            return;
+        }
+
+        let adsr1 = self.voice_register(index, ADSR1);
+        let mut data = self.voice_register(index, ADSR2);
+        let rate;
+
+        if adsr1 & 0x80 != 0 {
+            if voice.envelope_mode == EnvelopeMode::Attack {
+                let attack = usize::from(adsr1 & 0x0f) * 2 + 1;
+                envelope += if attack < 31 { 0x20 } else { 0x400 };
+                rate = attack;
+            } else {
+                envelope -= 1;
+                envelope -= envelope >> 8;
+                rate = if voice.envelope_mode == EnvelopeMode::Decay {
+                    usize::from((adsr1 >> 3) & 0x0e) + 0x10
+                } else {
+                    usize::from(data & 0x1f)
+                };
+            }
+        } else {
+            data = self.voice_register(index, GAIN);
+            let mode = data >> 5;
+
+            if mode < 4 {
+                // Direct
+                envelope = i32::from(data) * 0x10;
+                rate = 31;
+            } else {
+                rate = usize::from(data & 0x1f);
+                match mode {
+                    // Linear decrease
+                    4 => envelope -= 0x20,
+
+                    // Exponential decrease
+                    5 => {
+                        envelope -= 1;
+                        envelope -= envelope >> 8;
+                    }
+
+                    // Linear increase, slower above 3/4 in bent mode
+                    _ => {
+                        envelope += 0x20;
+                        if mode == 7 && voice.hidden_envelope >= 0x600 {
+                            envelope += 0x8 - 0x20;
+                        }
+                    }
+                }
+            }
+        }
+
+        // The sustain level is compared with ADSR2, or GAIN in GAIN mode
+        if envelope >> 8 == i32::from(data >> 5) && voice.envelope_mode == EnvelopeMode::Decay {
+            voice.envelope_mode = EnvelopeMode::Sustain;
+        }
+
+        voice.hidden_envelope = envelope;
+
+        if !(0..=0x7ff).contains(&envelope) {
+            envelope = envelope.clamp(0, 0x7ff);
+            if voice.envelope_mode == EnvelopeMode::Attack {
+                voice.envelope_mode = EnvelopeMode::Decay;
+            }
+        }
+
+        if self.rate_fires(rate) {
+            voice.envelope = envelope as u16;
+        }
+    }
+
+    /// Read the echo buffer through the FIR filter and write the new echo with feedback,
+    /// returning the filtered echo.
+    fn run_echo(&mut self, echo: [i32; 2], ram: &mut [u8]) -> [i32; 2] {
+        if self.echo_offset == 0 {
+            self.echo_length = u16::from(self.registers[ECHO_DELAY] & 0x0f) * 0x800;
+        }
+
+        let start = u16::from(self.registers[ECHO_START]) << 8;
+        let addr = start.wrapping_add(self.echo_offset);
+        let byte = |offset: u16| usize::from(addr.wrapping_add(offset));
+
+        self.echo_history_position = (self.echo_history_position + 1) % 8;
+        for channel in 0..2 {
+            let offset = 2 * channel as u16;
+            let value = i16::from_le_bytes([ram[byte(offset)], ram[byte(offset + 1)]]);
+            self.echo_history[self.echo_history_position][channel] = i32::from(value) >> 1;
+        }
+
+        let flags = self.registers[FLAGS];
+        let feedback = i32::from(self.registers[ECHO_FEEDBACK] as i8);
+        let mut output = [0; 2];
+
+        for channel in 0..2 {
+            // C0 applies to the oldest sample, C7 to the newest
+            let tap = |tap: usize| {
+                let position = (self.echo_history_position + 1 + tap) % 8;
+                let coefficient = i32::from(self.registers[tap << 4 | FIR] as i8);
+                (self.echo_history[position][channel] * coefficient) >> 6
+            };
+
+            // The sum of the first seven taps wraps, only the last one clamps
+            let sum = (0..7).map(tap).sum::<i32>();
+            let filtered = clamp16(i32::from(sum as i16) + tap(7)) & !1;
+            output[channel] = filtered;
+
+            let value = clamp16(echo[channel] + (((filtered * feedback) >> 7) as i16 as i32)) & !1;
+
+            // FLG bit 5 disables writes to the echo buffer
+            if flags & 0x20 == 0 {
+                let [low, high] = (value as i16).to_le_bytes();
+                let offset = 2 * channel as u16;
+                ram[byte(offset)] = low;
+                ram[byte(offset + 1)] = high;
+            }
+        }
+
+        self.echo_offset += 4;
+        if self.echo_offset >= self.echo_length {
+            self.echo_offset = 0;
+        }
+
+        output
+    }
+}
+
+/// Gaussian interpolation between the four samples around a voice's position.
+fn interpolate(voice: &Voice) -> i32 {
+    let offset = usize::from((voice.interpolation_position >> 4) & 0xff);
+    let start = voice.buffer_position + usize::from(voice.interpolation_position >> 12);
+    let sample = |index: usize| i32::from(voice.buffer[(start + index) % BUFFER_SIZE]);
+
+    let mut output = (i32::from(GAUSS[255 - offset]) * sample(0)) >> 11;
+    output += (i32::from(GAUSS[511 - offset]) * sample(1)) >> 11;
+    output += (i32::from(GAUSS[256 + offset]) * sample(2)) >> 11;
+    let output = i32::from(output as i16);
+    let output = output + ((i32::from(GAUSS[offset]) * sample(3)) >> 11);
+
+    clamp16(output) & !1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A DSP playing a looping sample of a constant value on voice 0, with full volume.
+    fn dsp_with_voice(ram: &mut [u8]) -> Dsp {
+        // Directory at $0200, sample 0 starts and loops at $0300
+        ram[0x0200..0x0204].copy_from_slice(&[0x00, 0x03, 0x00, 0x03]);
+
+        // Shift 11, no filter, end and loop, every sample 7
+        ram[0x0300] = 0xB3;
+        for byte in &mut ram[0x0301..0x0309] {
+            *byte = 0x77;
+        }
+
+        let mut dsp = Dsp::new();
+        for &(addr, value) in &[
+            (DIRECTORY, 0x02),
+            (VOLUME_LEFT, 0x7f),
+            (VOLUME_RIGHT, 0x40),
+            (PITCH_HIGH, 0x10),
+            (GAIN, 0x7f),
+            (MAIN_VOLUME_LEFT, 0x7f),
+            (MAIN_VOLUME_RIGHT, 0x7f),
+            (FLAGS, 0x20),
+            (KEY_ON, 0x01),
+        ] {
+            dsp.write(addr as u8, value);
+        }
+        dsp
+    }
+
+    #[test]
+    fn voice_output() {
+        let mut ram = vec![0; 0x10000];
+        let mut dsp = dsp_with_voice(&mut ram);
+
+        for _ in 0..64 {
+            dsp.sample(&mut ram);
+        }
+
+        // 14336 from BRR, through the envelope and both volumes
+        let (left, right) = (dsp.samples[126], dsp.samples[127]);
+        assert!((13900..14100).contains(&left), "left {}", left);
+        assert!((6900..7100).contains(&right), "right {}", right);
+
+        assert_eq!(dsp.read(ENVX as u8), 0x7f);
+        assert_eq!(
+            dsp.read(END as u8),
+            0x01,
+            "the sample reached its end and looped"
+        );
+    }
+
+    #[test]
+    fn samples_kept() {
+        let mut ram = vec![0; 0x10000];
+        let mut dsp = dsp_with_voice(&mut ram);
+
+        for _ in 0..MAX_SAMPLES / 2 + 10 {
+            dsp.sample(&mut ram);
+        }
+        assert_eq!(dsp.samples.len(), MAX_SAMPLES, "the oldest are dropped");
+        assert_eq!(dsp.take_samples(3).count(), 3);
+        assert_eq!(dsp.take_samples(usize::MAX).count(), MAX_SAMPLES - 3);
+    }
+
+    #[test]
+    fn echo() {
+        let mut ram = vec![0; 0x10000];
+        let mut dsp = dsp_with_voice(&mut ram);
+
+        // Only the echo is heard, 2 KB or 512 samples later
+        for &(addr, value) in &[
+            (MAIN_VOLUME_LEFT, 0x00),
+            (MAIN_VOLUME_RIGHT, 0x00),
+            (ECHO_VOLUME_LEFT, 0x7f),
+            (ECHO_VOLUME_RIGHT, 0x7f),
+            (ECHO_ENABLE, 0x01),
+            (ECHO_START, 0x80),
+            (ECHO_DELAY, 0x01),
+            (FIR + 0x70, 0x7f),
+            (FLAGS, 0x00),
+        ] {
+            dsp.write(addr as u8, value);
+        }
+
+        for _ in 0..600 {
+            dsp.sample(&mut ram);
+        }
+
+        let heard = |sample: usize| dsp.samples[2 * sample];
+        assert_eq!(heard(500), 0);
+        assert!(heard(599) > 6000, "echo {}", heard(599));
+    }
+}
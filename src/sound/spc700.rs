@@ -0,0 +1,872 @@
+use super::SoundRam;
+
+/// Cycles taken by each opcode. Conditional branches take two more when the branch is taken.
+#[rustfmt::skip]
+const CYCLES: [u8; 256] = [
+//  0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 6, 5, 4, 5, 4, 6, 8, // 0
+    2, 8, 4, 5, 4, 5, 5, 6, 5, 5, 6, 5, 2, 2, 4, 6, // 1
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 6, 5, 4, 5, 4, 5, 2, // 2
+    2, 8, 4, 5, 4, 5, 5, 6, 5, 5, 6, 5, 2, 2, 3, 8, // 3
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 6, 4, 4, 5, 4, 6, 6, // 4
+    2, 8, 4, 5, 4, 5, 5, 6, 5, 5, 4, 5, 2, 2, 4, 3, // 5
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 6, 4, 4, 5, 4, 5, 5, // 6
+    2, 8, 4, 5, 4, 5, 5, 6, 5, 5, 5, 5, 2, 2, 3, 6, // 7
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 6, 5, 4, 5, 2, 4, 5, // 8
+    2, 8, 4, 5, 4, 5, 5, 6, 5, 5, 5, 5, 2, 2, 12, 5, // 9
+    3, 8, 4, 5, 3, 4, 3, 6, 2, 6, 4, 4, 5, 2, 4, 4, // A
+    2, 8, 4, 5, 4, 5, 5, 6, 5, 5, 5, 5, 2, 2, 3, 4, // B
+    3, 8, 4, 5, 4, 5, 4, 7, 2, 5, 6, 4, 5, 2, 4, 9, // C
+    2, 8, 4, 5, 5, 6, 6, 7, 4, 5, 5, 5, 2, 2, 6, 3, // D
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 4, 5, 3, 4, 3, 4, 3, // E
+    2, 8, 4, 5, 4, 5, 5, 6, 3, 4, 5, 4, 2, 2, 4, 3, // F
+];
+
+// Program status word flags
+const CARRY: u8 = 0x01;
+const ZERO: u8 = 0x02;
+const INTERRUPT: u8 = 0x04;
+const HALF_CARRY: u8 = 0x08;
+const BREAK: u8 = 0x10;
+const DIRECT_PAGE: u8 = 0x20;
+const OVERFLOW: u8 = 0x40;
+const NEGATIVE: u8 = 0x80;
+
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub struct Spc700Registers {
+    pub accumulator: u8,
+    pub index_x: u8,
+    pub index_y: u8,
+
+    /// Offset into page 1
+    pub stack_pointer: u8,
+    pub program_counter: u16,
+
+    /// PSW: NVPBHIZC
+    pub status: u8,
+}
+
+/// Sony SPC700, the CPU of the sound module
+pub struct Spc700 {
+    pub registers: Spc700Registers,
+
+    /// Halted by SLEEP or STOP, which only a reset recovers from
+    stopped: bool,
+}
+
+save_state! {
+    Spc700Registers { accumulator, index_x, index_y, stack_pointer, program_counter, status }
+}
+
+save_state! { Spc700 { registers, stopped } }
+
+/// How an instruction finds its operand.
+#[derive(Debug, Copy, Clone)]
+enum Mode {
+    /// d
+    Direct,
+    /// d+X
+    DirectX,
+    /// d+Y
+    DirectY,
+    /// !a
+    Absolute,
+    /// !a+X
+    AbsoluteX,
+    /// !a+Y
+    AbsoluteY,
+    /// (X)
+    IndirectX,
+    /// (Y)
+    IndirectY,
+    /// [d+X]
+    DirectXIndirect,
+    /// [d]+Y
+    DirectIndirectY,
+    /// #i
+    Immediate,
+}
+
+/// Where an operand lives, once its address has been fetched.
+#[derive(Debug, Copy, Clone)]
+enum Operand {
+    Accumulator,
+    IndexX,
+    IndexY,
+    Immediate(u8),
+    Memory(u16),
+}
+
+impl Spc700 {
+    pub fn new() -> Spc700 {
+        Spc700 {
+            registers: Spc700Registers::default(),
+            stopped: false,
+        }
+    }
+
+    pub fn reset(&mut self, memory: &mut SoundRam) {
+        self.registers = Spc700Registers {
+            stack_pointer: 0xef,
+            program_counter: memory.get_word(0xFFFE),
+            status: ZERO,
+            ..Spc700Registers::default()
+        };
+        self.stopped = false;
+    }
+
+    /// Execute one instruction, returning the number of cycles it took.
+    pub fn step(&mut self, memory: &mut SoundRam) -> u32 {
+        if self.stopped {
+            return 2;
+        }
+
+        let opcode = self.fetch(memory);
+        let extra = self.execute(opcode, memory);
+        u32::from(CYCLES[usize::from(opcode)]) + extra
+    }
+
+    // ===== //
+    // Flags //
+    // ===== //
+
+    fn flag(&self, flag: u8) -> bool {
+        self.registers.status & flag != 0
+    }
+
+    fn set_flag(&mut self, flag: u8, state: bool) {
+        if state {
+            self.registers.status |= flag;
+        } else {
+            self.registers.status &= !flag;
+        }
+    }
+
+    fn set_negative_zero(&mut self, value: u8) {
+        self.set_flag(NEGATIVE, value & 0x80 != 0);
+        self.set_flag(ZERO, value == 0);
+    }
+
+    fn set_negative_zero_word(&mut self, value: u16) {
+        self.set_flag(NEGATIVE, value & 0x8000 != 0);
+        self.set_flag(ZERO, value == 0);
+    }
+
+    // ========== //
+    // Addressing //
+    // ========== //
+
+    fn fetch(&mut self, memory: &mut SoundRam) -> u8 {
+        let value = memory.get_byte(self.registers.program_counter);
+        self.registers.program_counter = self.registers.program_counter.wrapping_add(1);
+        value
+    }
+
+    fn fetch_word(&mut self, memory: &mut SoundRam) -> u16 {
+        let low = self.fetch(memory);
+        let high = self.fetch(memory);
+        u16::from_le_bytes([low, high])
+    }
+
+    /// The address of an offset into the direct page, $00xx or $01xx depending on the P flag.
+    fn direct_page(&self, offset: u8) -> u16 {
+        let page = if self.flag(DIRECT_PAGE) { 0x0100 } else { 0 };
+        page | u16::from(offset)
+    }
+
+    /// Read a word from the direct page. The high byte wraps around within the page.
+    fn direct_word(&self, memory: &mut SoundRam, offset: u8) -> u16 {
+        let low = memory.get_byte(self.direct_page(offset));
+        let high = memory.get_byte(self.direct_page(offset.wrapping_add(1)));
+        u16::from_le_bytes([low, high])
+    }
+
+    fn set_direct_word(&self, memory: &mut SoundRam, offset: u8, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        memory.set_byte(self.direct_page(offset), low);
+        memory.set_byte(self.direct_page(offset.wrapping_add(1)), high);
+    }
+
+    /// Fetch the operand of an instruction.
+    fn operand(&mut self, memory: &mut SoundRam, mode: Mode) -> Operand {
+        let registers = self.registers;
+
+        let addr = match mode {
+            Mode::Immediate => return Operand::Immediate(self.fetch(memory)),
+            Mode::Direct => {
+                let offset = self.fetch(memory);
+                self.direct_page(offset)
+            }
+            Mode::DirectX => {
+                let offset = self.fetch(memory);
+                self.direct_page(offset.wrapping_add(registers.index_x))
+            }
+            Mode::DirectY => {
+                let offset = self.fetch(memory);
+                self.direct_page(offset.wrapping_add(registers.index_y))
+            }
+            Mode::Absolute => self.fetch_word(memory),
+            Mode::AbsoluteX => self
+                .fetch_word(memory)
+                .wrapping_add(u16::from(registers.index_x)),
+            Mode::AbsoluteY => self
+                .fetch_word(memory)
+                .wrapping_add(u16::from(registers.index_y)),
+            Mode::IndirectX => self.direct_page(registers.index_x),
+            Mode::IndirectY => self.direct_page(registers.index_y),
+            Mode::DirectXIndirect => {
+                let offset = self.fetch(memory);
+                self.direct_word(memory, offset.wrapping_add(registers.index_x))
+            }
+            Mode::DirectIndirectY => {
+                let offset = self.fetch(memory);
+                self.direct_word(memory, offset)
+                    .wrapping_add(u16::from(registers.index_y))
+            }
+        };
+
+        Operand::Memory(addr)
+    }
+
+    /// Fetch the operand of a single bit instruction, m.b: a 13 bit address and a bit number.
+    fn bit_operand(&mut self, memory: &mut SoundRam) -> (u16, u8) {
+        let operand = self.fetch_word(memory);
+        (operand & 0x1fff, (operand >> 13) as u8)
+    }
+
+    fn read(&self, memory: &mut SoundRam, operand: Operand) -> u8 {
+        match operand {
+            Operand::Accumulator => self.registers.accumulator,
+            Operand::IndexX => self.registers.index_x,
+            Operand::IndexY => self.registers.index_y,
+            Operand::Immediate(value) => value,
+            Operand::Memory(addr) => memory.get_byte(addr),
+        }
+    }
+
+    fn write(&mut self, memory: &mut SoundRam, operand: Operand, value: u8) {
+        match operand {
+            Operand::Accumulator => self.registers.accumulator = value,
+            Operand::IndexX => self.registers.index_x = value,
+            Operand::IndexY => self.registers.index_y = value,
+            Operand::Immediate(_) => panic!("Attempted to write to an immediate operand"),
+            Operand::Memory(addr) => memory.set_byte(addr, value),
+        }
+    }
+
+    // ===== //
+    // Stack //
+    // ===== //
+
+    fn push(&mut self, memory: &mut SoundRam, value: u8) {
+        memory.set_byte(0x0100 | u16::from(self.registers.stack_pointer), value);
+        self.registers.stack_pointer = self.registers.stack_pointer.wrapping_sub(1);
+    }
+
+    fn pull(&mut self, memory: &mut SoundRam) -> u8 {
+        self.registers.stack_pointer = self.registers.stack_pointer.wrapping_add(1);
+        memory.get_byte(0x0100 | u16::from(self.registers.stack_pointer))
+    }
+
+    fn push_program_counter(&mut self, memory: &mut SoundRam) {
+        let [low, high] = self.registers.program_counter.to_le_bytes();
+        self.push(memory, high);
+        self.push(memory, low);
+    }
+
+    fn pull_program_counter(&mut self, memory: &mut SoundRam) {
+        let low = self.pull(memory);
+        let high = self.pull(memory);
+        self.registers.program_counter = u16::from_le_bytes([low, high]);
+    }
+
+    // ========== //
+    // Arithmetic //
+    // ========== //
+
+    fn add(&mut self, a: u8, b: u8) -> u8 {
+        let result = u16::from(a) + u16::from(b) + u16::from(self.flag(CARRY));
+        let value = result as u8;
+
+        self.set_flag(CARRY, result > 0xff);
+        self.set_flag(HALF_CARRY, (a ^ b ^ value) & 0x10 != 0);
+        self.set_flag(OVERFLOW, !(a ^ b) & (a ^ value) & 0x80 != 0);
+        self.set_negative_zero(value);
+        value
+    }
+
+    fn subtract(&mut self, a: u8, b: u8) -> u8 {
+        self.add(a, !b)
+    }
+
+    fn compare(&mut self, a: u8, b: u8) {
+        self.set_flag(CARRY, a >= b);
+        self.set_negative_zero(a.wrapping_sub(b));
+    }
+
+    fn add_word(&mut self, a: u16, b: u16) -> u16 {
+        self.set_flag(CARRY, false);
+        let low = self.add(a as u8, b as u8);
+        let high = self.add((a >> 8) as u8, (b >> 8) as u8);
+        let value = u16::from_le_bytes([low, high]);
+        self.set_flag(ZERO, value == 0);
+        value
+    }
+
+    fn subtract_word(&mut self, a: u16, b: u16) -> u16 {
+        self.set_flag(CARRY, true);
+        let low = self.subtract(a as u8, b as u8);
+        let high = self.subtract((a >> 8) as u8, (b >> 8) as u8);
+        let value = u16::from_le_bytes([low, high]);
+        self.set_flag(ZERO, value == 0);
+        value
+    }
+
+    fn accumulator_word(&self) -> u16 {
+        u16::from_le_bytes([self.registers.accumulator, self.registers.index_y])
+    }
+
+    fn set_accumulator_word(&mut self, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.registers.accumulator = low;
+        self.registers.index_y = high;
+    }
+
+    /// Branch by a signed offset if the condition holds, returning the extra cycles taken.
+    fn branch(&mut self, memory: &mut SoundRam, condition: bool) -> u32 {
+        let offset = self.fetch(memory) as i8;
+        if condition {
+            let counter = &mut self.registers.program_counter;
+            *counter = counter.wrapping_add(offset as u16);
+            2
+        } else {
+            0
+        }
+    }
+
+    // ====================== //
+    // Implement instructions //
+    // ====================== //
+
+    /// Execute an opcode, returning the cycles taken on top of its base cycles.
+    fn execute(&mut self, opcode: u8, memory: &mut SoundRam) -> u32 {
+        log::trace!(
+            "SPC700 {:04x}: {:02x}",
+            self.registers.program_counter.wrapping_sub(1),
+            opcode
+        );
+
+        let row = opcode >> 4;
+        let column = opcode & 0x0f;
+
+        use Mode::*;
+        match opcode {
+            // OR, AND, EOR, CMP, ADC and SBC share their addressing modes
+            _ if row < 0xC && (0x4..=0x9).contains(&column) => self.arithmetic(memory, opcode),
+
+            // ASL, ROL, LSR, ROR, DEC and INC
+            _ if row < 0xC && (column == 0xB || column == 0xC) => self.shift(memory, opcode),
+
+            // TCALL n
+            _ if column == 0x1 => {
+                self.push_program_counter(memory);
+                let vector = 0xFFDE - 2 * u16::from(row);
+                self.registers.program_counter = memory.get_word(vector);
+            }
+
+            // SET1 d.b and CLR1 d.b
+            _ if column == 0x2 => {
+                let operand = self.operand(memory, Direct);
+                let mask = 1 << (row >> 1);
+                let value = self.read(memory, operand);
+                let value = if row & 1 == 0 {
+                    value | mask
+                } else {
+                    value & !mask
+                };
+                self.write(memory, operand, value);
+            }
+
+            // BBS d.b, r and BBC d.b, r
+            _ if column == 0x3 => {
+                let operand = self.operand(memory, Direct);
+                let set = self.read(memory, operand) & (1 << (row >> 1)) != 0;
+                return self.branch(memory, set == (row & 1 == 0));
+            }
+
+            0x00 => {}
+
+            // Branches
+            0x10 => return self.branch(memory, !self.flag(NEGATIVE)),
+            0x30 => return self.branch(memory, self.flag(NEGATIVE)),
+            0x50 => return self.branch(memory, !self.flag(OVERFLOW)),
+            0x70 => return self.branch(memory, self.flag(OVERFLOW)),
+            0x90 => return self.branch(memory, !self.flag(CARRY)),
+            0xB0 => return self.branch(memory, self.flag(CARRY)),
+            0xD0 => return self.branch(memory, !self.flag(ZERO)),
+            0xF0 => return self.branch(memory, self.flag(ZERO)),
+            0x2F => return self.branch(memory, true),
+
+            // CBNE d, r and CBNE d+X, r
+            0x2E | 0xDE => {
+                let operand = self.operand(memory, if opcode == 0x2E { Direct } else { DirectX });
+                let value = self.read(memory, operand);
+                return self.branch(memory, self.registers.accumulator != value);
+            }
+
+            // DBNZ d, r
+            0x6E => {
+                let operand = self.operand(memory, Direct);
+                let value = self.read(memory, operand).wrapping_sub(1);
+                self.write(memory, operand, value);
+                return self.branch(memory, value != 0);
+            }
+
+            // DBNZ Y, r
+            0xFE => {
+                self.registers.index_y = self.registers.index_y.wrapping_sub(1);
+                return self.branch(memory, self.registers.index_y != 0);
+            }
+
+            // Flags
+            0x20 => self.set_flag(DIRECT_PAGE, false),
+            0x40 => self.set_flag(DIRECT_PAGE, true),
+            0x60 => self.set_flag(CARRY, false),
+            0x80 => self.set_flag(CARRY, true),
+            0xA0 => self.set_flag(INTERRUPT, true),
+            0xC0 => self.set_flag(INTERRUPT, false),
+            0xE0 => {
+                self.set_flag(OVERFLOW, false);
+                self.set_flag(HALF_CARRY, false);
+            }
+            0xED => self.set_flag(CARRY, !self.flag(CARRY)),
+
+            // MOV to memory
+            0xC4 => self.store(memory, Operand::Accumulator, Direct),
+            0xD4 => self.store(memory, Operand::Accumulator, DirectX),
+            0xC5 => self.store(memory, Operand::Accumulator, Absolute),
+            0xD5 => self.store(memory, Operand::Accumulator, AbsoluteX),
+            0xC6 => self.store(memory, Operand::Accumulator, IndirectX),
+            0xD6 => self.store(memory, Operand::Accumulator, AbsoluteY),
+            0xC7 => self.store(memory, Operand::Accumulator, DirectXIndirect),
+            0xD7 => self.store(memory, Operand::Accumulator, DirectIndirectY),
+            0xD8 => self.store(memory, Operand::IndexX, Direct),
+            0xD9 => self.store(memory, Operand::IndexX, DirectY),
+            0xC9 => self.store(memory, Operand::IndexX, Absolute),
+            0xCB => self.store(memory, Operand::IndexY, Direct),
+            0xDB => self.store(memory, Operand::IndexY, DirectX),
+            0xCC => self.store(memory, Operand::IndexY, Absolute),
+
+            // MOV to registers
+            0xE4 => self.load(memory, Operand::Accumulator, Direct),
+            0xF4 => self.load(memory, Operand::Accumulator, DirectX),
+            0xE5 => self.load(memory, Operand::Accumulator, Absolute),
+            0xF5 => self.load(memory, Operand::Accumulator, AbsoluteX),
+            0xE6 => self.load(memory, Operand::Accumulator, IndirectX),
+            0xF6 => self.load(memory, Operand::Accumulator, AbsoluteY),
+            0xE7 => self.load(memory, Operand::Accumulator, DirectXIndirect),
+            0xF7 => self.load(memory, Operand::Accumulator, DirectIndirectY),
+            0xE8 => self.load(memory, Operand::Accumulator, Immediate),
+            0xF8 => self.load(memory, Operand::IndexX, Direct),
+            0xF9 => self.load(memory, Operand::IndexX, DirectY),
+            0xE9 => self.load(memory, Operand::IndexX, Absolute),
+            0xCD => self.load(memory, Operand::IndexX, Immediate),
+            0xEB => self.load(memory, Operand::IndexY, Direct),
+            0xFB => self.load(memory, Operand::IndexY, DirectX),
+            0xEC => self.load(memory, Operand::IndexY, Absolute),
+            0x8D => self.load(memory, Operand::IndexY, Immediate),
+
+            // MOV between registers
+            0x5D => self.transfer(Operand::IndexX, self.registers.accumulator),
+            0x7D => self.transfer(Operand::Accumulator, self.registers.index_x),
+            0xDD => self.transfer(Operand::Accumulator, self.registers.index_y),
+            0xFD => self.transfer(Operand::IndexY, self.registers.accumulator),
+            0x9D => self.transfer(Operand::IndexX, self.registers.stack_pointer),
+            0xBD => self.registers.stack_pointer = self.registers.index_x,
+
+            // MOV dd, ds
+            0xFA => {
+                let source = self.operand(memory, Direct);
+                let value = self.read(memory, source);
+                let target = self.operand(memory, Direct);
+                self.write(memory, target, value);
+            }
+
+            // MOV d, #i
+            0x8F => {
+                let value = self.fetch(memory);
+                let target = self.operand(memory, Direct);
+                self.write(memory, target, value);
+            }
+
+            // MOV (X)+, A
+            0xAF => {
+                self.store(memory, Operand::Accumulator, IndirectX);
+                self.registers.index_x = self.registers.index_x.wrapping_add(1);
+            }
+
+            // MOV A, (X)+
+            0xBF => {
+                self.load(memory, Operand::Accumulator, IndirectX);
+                self.registers.index_x = self.registers.index_x.wrapping_add(1);
+            }
+
+            // CMP X and CMP Y
+            0xC8 => self.compare_register(memory, self.registers.index_x, Immediate),
+            0x1E => self.compare_register(memory, self.registers.index_x, Absolute),
+            0x3E => self.compare_register(memory, self.registers.index_x, Direct),
+            0xAD => self.compare_register(memory, self.registers.index_y, Immediate),
+            0x5E => self.compare_register(memory, self.registers.index_y, Absolute),
+            0x7E => self.compare_register(memory, self.registers.index_y, Direct),
+
+            // INC and DEC on index registers
+            0x1D => self.transfer(Operand::IndexX, self.registers.index_x.wrapping_sub(1)),
+            0x3D => self.transfer(Operand::IndexX, self.registers.index_x.wrapping_add(1)),
+            0xDC => self.transfer(Operand::IndexY, self.registers.index_y.wrapping_sub(1)),
+            0xFC => self.transfer(Operand::IndexY, self.registers.index_y.wrapping_add(1)),
+
+            // Single bit operations on the carry
+            0x0A | 0x2A | 0x4A | 0x6A | 0x8A | 0xAA => {
+                let (addr, bit) = self.bit_operand(memory);
+                let set = memory.get_byte(addr) & (1 << bit) != 0;
+                let carry = self.flag(CARRY);
+                let carry = match opcode {
+                    0x0A => carry | set,
+                    0x2A => carry | !set,
+                    0x4A => carry & set,
+                    0x6A => carry & !set,
+                    0x8A => carry ^ set,
+                    _ => set,
+                };
+                self.set_flag(CARRY, carry);
+            }
+
+            // MOV1 m.b, C
+            0xCA => {
+                let (addr, bit) = self.bit_operand(memory);
+                let value = memory.get_byte(addr) & !(1 << bit);
+                memory.set_byte(addr, value | (self.flag(CARRY) as u8) << bit);
+            }
+
+            // NOT1 m.b
+            0xEA => {
+                let (addr, bit) = self.bit_operand(memory);
+                let value = memory.get_byte(addr) ^ (1 << bit);
+                memory.set_byte(addr, value);
+            }
+
+            // TSET1 !a and TCLR1 !a
+            0x0E | 0x4E => {
+                let addr = self.fetch_word(memory);
+                let value = memory.get_byte(addr);
+                let accumulator = self.registers.accumulator;
+                self.set_negative_zero(accumulator.wrapping_sub(value));
+
+                let value = if opcode == 0x0E {
+                    value | accumulator
+                } else {
+                    value & !accumulator
+                };
+                memory.set_byte(addr, value);
+            }
+
+            // DECW d and INCW d
+            0x1A | 0x3A => {
+                let offset = self.fetch(memory);
+                let value = self.direct_word(memory, offset);
+                let value = if opcode == 0x1A {
+                    value.wrapping_sub(1)
+                } else {
+                    value.wrapping_add(1)
+                };
+                self.set_direct_word(memory, offset, value);
+                self.set_negative_zero_word(value);
+            }
+
+            // CMPW YA, d
+            0x5A => {
+                let offset = self.fetch(memory);
+                let value = self.direct_word(memory, offset);
+                let accumulator = self.accumulator_word();
+                self.set_flag(CARRY, accumulator >= value);
+                self.set_negative_zero_word(accumulator.wrapping_sub(value));
+            }
+
+            // ADDW YA, d
+            0x7A => {
+                let offset = self.fetch(memory);
+                let value = self.direct_word(memory, offset);
+                let result = self.add_word(self.accumulator_word(), value);
+                self.set_accumulator_word(result);
+            }
+
+            // SUBW YA, d
+            0x9A => {
+                let offset = self.fetch(memory);
+                let value = self.direct_word(memory, offset);
+                let result = self.subtract_word(self.accumulator_word(), value);
+                self.set_accumulator_word(result);
+            }
+
+            // MOVW YA, d
+            0xBA => {
+                let offset = self.fetch(memory);
+                let value = self.direct_word(memory, offset);
+                self.set_accumulator_word(value);
+                self.set_negative_zero_word(value);
+            }
+
+            // MOVW d, YA
+            0xDA => {
+                let offset = self.fetch(memory);
+                self.set_direct_word(memory, offset, self.accumulator_word());
+            }
+
+            // MUL YA
+            0xCF => {
+                let result =
+                    u16::from(self.registers.index_y) * u16::from(self.registers.accumulator);
+                self.set_accumulator_word(result);
+                self.set_negative_zero(self.registers.index_y);
+            }
+
+            // DIV YA, X
+            0x9E => self.divide(),
+
+            // XCN A
+            0x9F => {
+                let value = self.registers.accumulator.rotate_left(4);
+                self.transfer(Operand::Accumulator, value);
+            }
+
+            // DAA A
+            0xDF => {
+                let mut value = self.registers.accumulator;
+                if self.flag(CARRY) || value > 0x99 {
+                    value = value.wrapping_add(0x60);
+                    self.set_flag(CARRY, true);
+                }
+                if self.flag(HALF_CARRY) || value & 0x0f > 0x09 {
+                    value = value.wrapping_add(0x06);
+                }
+                self.transfer(Operand::Accumulator, value);
+            }
+
+            // DAS A
+            0xBE => {
+                let mut value = self.registers.accumulator;
+                if !self.flag(CARRY) || value > 0x99 {
+                    value = value.wrapping_sub(0x60);
+                    self.set_flag(CARRY, false);
+                }
+                if !self.flag(HALF_CARRY) || value & 0x0f > 0x09 {
+                    value = value.wrapping_sub(0x06);
+                }
+                self.transfer(Operand::Accumulator, value);
+            }
+
+            // PUSH and POP
+            0x0D => self.push(memory, self.registers.status),
+            0x2D => self.push(memory, self.registers.accumulator),
+            0x4D => self.push(memory, self.registers.index_x),
+            0x6D => self.push(memory, self.registers.index_y),
+            0x8E => self.registers.status = self.pull(memory),
+            0xAE => self.registers.accumulator = self.pull(memory),
+            0xCE => self.registers.index_x = self.pull(memory),
+            0xEE => self.registers.index_y = self.pull(memory),
+
+            // JMP !a
+            0x5F => self.registers.program_counter = self.fetch_word(memory),
+
+            // JMP [!a+X]
+            0x1F => {
+                let addr = self.fetch_word(memory);
+                let addr = addr.wrapping_add(u16::from(self.registers.index_x));
+                self.registers.program_counter = memory.get_word(addr);
+            }
+
+            // CALL !a
+            0x3F => {
+                let addr = self.fetch_word(memory);
+                self.push_program_counter(memory);
+                self.registers.program_counter = addr;
+            }
+
+            // PCALL u
+            0x4F => {
+                let offset = self.fetch(memory);
+                self.push_program_counter(memory);
+                self.registers.program_counter = 0xFF00 | u16::from(offset);
+            }
+
+            // BRK
+            0x0F => {
+                self.push_program_counter(memory);
+                self.push(memory, self.registers.status);
+                self.set_flag(BREAK, true);
+                self.set_flag(INTERRUPT, false);
+                self.registers.program_counter = memory.get_word(0xFFDE);
+            }
+
+            // RET
+            0x6F => self.pull_program_counter(memory),
+
+            // RETI
+            0x7F => {
+                self.registers.status = self.pull(memory);
+                self.pull_program_counter(memory);
+            }
+
+            // SLEEP and STOP
+            0xEF | 0xFF => {
+                log::debug!("SPC700 stopped at {:04x}", self.registers.program_counter);
+                self.stopped = true;
+            }
+
+            _ => unreachable!("SPC700 opcode not decoded: {:02x}", opcode),
+        }
+
+        0
+    }
+
+    /// OR, AND, EOR, CMP, ADC and SBC, selected by the top three bits of the opcode. Each has
+    /// twelve forms in columns 4 to 9 of the opcode table.
+    fn arithmetic(&mut self, memory: &mut SoundRam, opcode: u8) {
+        use Mode::*;
+        let odd = opcode & 0x10 != 0;
+
+        let (target, source) = match (opcode & 0x0f, odd) {
+            (0x4, false) => (Operand::Accumulator, self.operand(memory, Direct)),
+            (0x4, true) => (Operand::Accumulator, self.operand(memory, DirectX)),
+            (0x5, false) => (Operand::Accumulator, self.operand(memory, Absolute)),
+            (0x5, true) => (Operand::Accumulator, self.operand(memory, AbsoluteX)),
+            (0x6, false) => (Operand::Accumulator, self.operand(memory, IndirectX)),
+            (0x6, true) => (Operand::Accumulator, self.operand(memory, AbsoluteY)),
+            (0x7, false) => (Operand::Accumulator, self.operand(memory, DirectXIndirect)),
+            (0x7, true) => (Operand::Accumulator, self.operand(memory, DirectIndirectY)),
+            (0x8, false) => (Operand::Accumulator, self.operand(memory, Immediate)),
+
+            // The source operand comes first in the instruction
+            (0x8, true) | (0x9, false) => {
+                let source = self.operand(memory, if odd { Immediate } else { Direct });
+                (self.operand(memory, Direct), source)
+            }
+
+            _ => (
+                self.operand(memory, IndirectX),
+                self.operand(memory, IndirectY),
+            ),
+        };
+
+        let a = self.read(memory, target);
+        let b = self.read(memory, source);
+
+        let value = match opcode >> 5 {
+            0 => a | b,
+            1 => a & b,
+            2 => a ^ b,
+            3 => return self.compare(a, b),
+            4 => self.add(a, b),
+            _ => self.subtract(a, b),
+        };
+
+        self.set_negative_zero(value);
+        self.write(memory, target, value);
+    }
+
+    /// ASL, ROL, LSR, ROR, DEC and INC, selected by the top three bits of the opcode, on a direct
+    /// page operand in column B and on an absolute operand or the accumulator in column C.
+    fn shift(&mut self, memory: &mut SoundRam, opcode: u8) {
+        let odd = opcode & 0x10 != 0;
+
+        let operand = match (opcode & 0x0f, odd) {
+            (0xB, false) => self.operand(memory, Mode::Direct),
+            (0xB, true) => self.operand(memory, Mode::DirectX),
+            (_, false) => self.operand(memory, Mode::Absolute),
+            (_, true) => Operand::Accumulator,
+        };
+
+        let value = self.read(memory, operand);
+        let carry = self.flag(CARRY) as u8;
+
+        let value = match opcode >> 5 {
+            0 => {
+                self.set_flag(CARRY, value & 0x80 != 0);
+                value << 1
+            }
+            1 => {
+                self.set_flag(CARRY, value & 0x80 != 0);
+                value << 1 | carry
+            }
+            2 => {
+                self.set_flag(CARRY, value & 0x01 != 0);
+                value >> 1
+            }
+            3 => {
+                self.set_flag(CARRY, value & 0x01 != 0);
+                value >> 1 | carry << 7
+            }
+            4 => value.wrapping_sub(1),
+            _ => value.wrapping_add(1),
+        };
+
+        self.set_negative_zero(value);
+        self.write(memory, operand, value);
+    }
+
+    /// MOV from memory or an immediate to a register.
+    fn load(&mut self, memory: &mut SoundRam, register: Operand, mode: Mode) {
+        let operand = self.operand(memory, mode);
+        let value = self.read(memory, operand);
+        self.transfer(register, value);
+    }
+
+    /// MOV from a register to memory, which doesn't change any flags.
+    fn store(&mut self, memory: &mut SoundRam, register: Operand, mode: Mode) {
+        let value = self.read(memory, register);
+        let operand = self.operand(memory, mode);
+        self.write(memory, operand, value);
+    }
+
+    /// Set a register, along with the N and Z flags.
+    fn transfer(&mut self, register: Operand, value: u8) {
+        match register {
+            Operand::Accumulator => self.registers.accumulator = value,
+            Operand::IndexX => self.registers.index_x = value,
+            Operand::IndexY => self.registers.index_y = value,
+            _ => unreachable!("transfer to {:?}", register),
+        }
+        self.set_negative_zero(value);
+    }
+
+    fn compare_register(&mut self, memory: &mut SoundRam, value: u8, mode: Mode) {
+        let operand = self.operand(memory, mode);
+        let other = self.read(memory, operand);
+        self.compare(value, other);
+    }
+
+    /// DIV YA, X. Quotients that don't fit in 8 bits give the same odd results as the hardware,
+    /// which divides with a 9 bit shift register.
+    fn divide(&mut self) {
+        let dividend = u32::from(self.accumulator_word());
+        let divisor = u32::from(self.registers.index_x);
+        let high = u32::from(self.registers.index_y);
+
+        self.set_flag(HALF_CARRY, high & 0x0f >= divisor & 0x0f);
+        self.set_flag(OVERFLOW, high >= divisor);
+
+        let (quotient, remainder) = if high < divisor << 1 {
+            (dividend / divisor, dividend % divisor)
+        } else {
+            let dividend = dividend - (divisor << 9);
+            (
+                255 - dividend / (256 - divisor),
+                divisor + dividend % (256 - divisor),
+            )
+        };
+
+        self.registers.accumulator = quotient as u8;
+        self.registers.index_y = remainder as u8;
+        self.set_negative_zero(self.registers.accumulator);
+    }
+}
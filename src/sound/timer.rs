@@ -0,0 +1,118 @@
+/// SPC700 cycles per tick of timers 0 and 1, which run at 8 kHz.
+const SLOW_PERIOD: u32 = 128;
+
+/// SPC700 cycles per tick of timer 2, which runs at 64 kHz.
+const FAST_PERIOD: u32 = 16;
+
+/// One of the three SPC700 timers. Each tick increments a stage counter, and every time it
+/// reaches the target the 4-bit output counter is incremented.
+#[derive(Copy, Clone)]
+pub(super) struct Timer {
+    /// SPC700 cycles per tick
+    period: u32,
+
+    /// SPC700 cycles since the last tick
+    cycles: u32,
+
+    pub enabled: bool,
+
+    /// TnTARGET: 0 counts 256 ticks
+    pub target: u8,
+    stage: u8,
+
+    /// TnOUT, cleared when read
+    counter: u8,
+}
+
+save_state! { Timer { period, cycles, enabled, target, stage, counter } }
+
+impl Timer {
+    pub fn new(index: usize) -> Timer {
+        Timer {
+            period: if index == 2 { FAST_PERIOD } else { SLOW_PERIOD },
+            cycles: 0,
+            enabled: false,
+            target: 0,
+            stage: 0,
+            counter: 0,
+        }
+    }
+
+    /// Enable or disable the timer. Enabling it resets both counters.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled && !self.enabled {
+            self.stage = 0;
+            self.counter = 0;
+        }
+        self.enabled = enabled;
+    }
+
+    /// The output counter, without clearing it.
+    pub fn counter(&self) -> u8 {
+        self.counter
+    }
+
+    pub fn set_counter(&mut self, value: u8) {
+        self.counter = value & 0x0f;
+    }
+
+    pub fn read_counter(&mut self) -> u8 {
+        let value = self.counter;
+        self.counter = 0;
+        value
+    }
+
+    /// Advance by a number of SPC700 cycles. The dividers keep running while the timer is
+    /// disabled.
+    pub fn run(&mut self, cycles: u32) {
+        self.cycles += cycles;
+        while self.cycles >= self.period {
+            self.cycles -= self.period;
+            if !self.enabled {
+                continue;
+            }
+
+            self.stage = self.stage.wrapping_add(1);
+            if self.stage == self.target {
+                self.stage = 0;
+                self.counter = (self.counter + 1) & 0x0f;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_to_target() {
+        let mut timer = Timer::new(0);
+        timer.target = 4;
+        timer.run(SLOW_PERIOD * 8);
+        assert_eq!(timer.read_counter(), 0, "disabled timers don't count");
+
+        timer.set_enabled(true);
+        timer.run(SLOW_PERIOD * 4 * 3 + SLOW_PERIOD * 3);
+        assert_eq!(timer.read_counter(), 3);
+        assert_eq!(timer.read_counter(), 0, "the counter is cleared when read");
+
+        // The stage counter kept its three ticks
+        timer.run(SLOW_PERIOD);
+        assert_eq!(timer.read_counter(), 1);
+
+        // The output counter is 4 bits
+        timer.run(SLOW_PERIOD * 4 * 17);
+        assert_eq!(timer.read_counter(), 1);
+    }
+
+    #[test]
+    fn fast_timer_and_target_zero() {
+        let mut timer = Timer::new(2);
+        timer.set_enabled(true);
+        timer.run(FAST_PERIOD * 255);
+        assert_eq!(timer.read_counter(), 0);
+        timer.run(FAST_PERIOD);
+        assert_eq!(timer.read_counter(), 1, "a target of 0 counts 256 ticks");
+    }
+}
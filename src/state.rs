@@ -0,0 +1,294 @@
+//! Save states: the whole machine serialized into a versioned blob.
+//!
+//! The blob begins with a signature, the format version and the CRC-32 of the ROM it was made
+//! with. The state of each component follows, field by field in the order they are listed in
+//! `save_state!`, little endian.
+
+use std::fmt;
+
+const SIGNATURE: &[u8; 8] = b"SNESSAVE";
+
+/// Bump whenever a field is added, removed or reordered.
+const VERSION: u16 = 4;
+
+/// Why a save state couldn't be loaded.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StateError {
+    /// The blob doesn't begin with the save state signature
+    InvalidSignature,
+
+    /// The state was made by an incompatible version of the emulator
+    UnsupportedVersion(u16),
+
+    /// The state was made with a different ROM
+    WrongRom,
+
+    /// The blob ends before the state does
+    TooShort,
+
+    /// A value in the state is out of range
+    Corrupted,
+
+    /// A movie is being recorded, which a state loaded partway through would desync
+    Recording,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::InvalidSignature => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::WrongRom => write!(f, "the save state was made with a different ROM"),
+            StateError::TooShort => write!(f, "the save state is truncated"),
+            StateError::Corrupted => write!(f, "the save state is corrupted"),
+            StateError::Recording => write!(f, "a movie is being recorded"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// A part of the machine that can be saved and restored.
+pub(crate) trait SaveState {
+    fn save(&self, state: &mut Vec<u8>);
+
+    /// Restore from the beginning of `state`, advancing past what was read.
+    fn load(&mut self, state: &mut &[u8]) -> Result<(), StateError>;
+}
+
+/// Implement `SaveState` for a struct by saving the listed fields in order.
+macro_rules! save_state {
+    { $type:ty { $($field:tt),* } } => {
+        impl crate::state::SaveState for $type {
+            fn save(&self, state: &mut Vec<u8>) {
+                $( crate::state::SaveState::save(&self.$field, state); )*
+            }
+
+            fn load(&mut self, state: &mut &[u8]) -> Result<(), crate::state::StateError> {
+                $( crate::state::SaveState::load(&mut self.$field, state)?; )*
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Take the next `length` bytes of a state.
+fn take<'a>(state: &mut &'a [u8], length: usize) -> Result<&'a [u8], StateError> {
+    if state.len() < length {
+        return Err(StateError::TooShort);
+    }
+
+    let (bytes, rest) = state.split_at(length);
+    *state = rest;
+    Ok(bytes)
+}
+
+macro_rules! impl_save_state_int {
+    ( $($type:ty),* ) => {
+        $(
+            impl SaveState for $type {
+                fn save(&self, state: &mut Vec<u8>) {
+                    state.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn load(&mut self, state: &mut &[u8]) -> Result<(), StateError> {
+                    let mut bytes = [0; std::mem::size_of::<$type>()];
+                    bytes.copy_from_slice(take(state, std::mem::size_of::<$type>())?);
+                    *self = <$type>::from_le_bytes(bytes);
+                    Ok(())
+                }
+            }
+        )*
+    }
+}
+
+impl_save_state_int!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl SaveState for bool {
+    fn save(&self, state: &mut Vec<u8>) {
+        state.push(*self as u8);
+    }
+
+    fn load(&mut self, state: &mut &[u8]) -> Result<(), StateError> {
+        *self = match take(state, 1)?[0] {
+            0 => false,
+            1 => true,
+            _ => return Err(StateError::Corrupted),
+        };
+        Ok(())
+    }
+}
+
+/// Sizes and indices are saved as 64 bits, whatever the host.
+impl SaveState for usize {
+    fn save(&self, state: &mut Vec<u8>) {
+        (*self as u64).save(state);
+    }
+
+    fn load(&mut self, state: &mut &[u8]) -> Result<(), StateError> {
+        let mut value = 0u64;
+        value.load(state)?;
+        *self = value as usize;
+        Ok(())
+    }
+}
+
+impl<T: SaveState, const N: usize> SaveState for [T; N] {
+    fn save(&self, state: &mut Vec<u8>) {
+        for element in self {
+            element.save(state);
+        }
+    }
+
+    fn load(&mut self, state: &mut &[u8]) -> Result<(), StateError> {
+        for element in self {
+            element.load(state)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: SaveState + Default> SaveState for Vec<T> {
+    fn save(&self, state: &mut Vec<u8>) {
+        self.len().save(state);
+        for element in self {
+            element.save(state);
+        }
+    }
+
+    fn load(&mut self, state: &mut &[u8]) -> Result<(), StateError> {
+        let mut length = 0usize;
+        length.load(state)?;
+        // Every element takes at least a byte
+        if length > state.len() {
+            return Err(StateError::TooShort);
+        }
+
+        self.clear();
+        self.resize_with(length, T::default);
+        for element in self {
+            element.load(state)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: SaveState + Default> SaveState for Option<T> {
+    fn save(&self, state: &mut Vec<u8>) {
+        self.is_some().save(state);
+        if let Some(value) = self {
+            value.save(state);
+        }
+    }
+
+    fn load(&mut self, state: &mut &[u8]) -> Result<(), StateError> {
+        let mut some = false;
+        some.load(state)?;
+        *self = if some {
+            let mut value = T::default();
+            value.load(state)?;
+            Some(value)
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
+/// Write the header of a save state for a ROM.
+pub(crate) fn write_header(state: &mut Vec<u8>, rom_hash: u32) {
+    state.extend_from_slice(SIGNATURE);
+    VERSION.save(state);
+    rom_hash.save(state);
+}
+
+/// Check the header of a save state, leaving `state` at the beginning of the body.
+pub(crate) fn read_header(state: &mut &[u8], rom_hash: u32) -> Result<(), StateError> {
+    if !state.starts_with(SIGNATURE) {
+        return Err(StateError::InvalidSignature);
+    }
+    *state = &state[SIGNATURE.len()..];
+
+    let mut version = 0u16;
+    version.load(state)?;
+    if version != VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+
+    let mut hash = 0u32;
+    hash.load(state)?;
+    if hash != rom_hash {
+        return Err(StateError::WrongRom);
+    }
+    Ok(())
+}
+
+/// The CRC-32 of some data, as used by zip and most ROM databases.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (index, entry) in table.iter_mut().enumerate() {
+        *entry = (0..8).fold(index as u32, |crc, _| {
+            if crc & 1 != 0 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            }
+        });
+    }
+
+    !data.iter().fold(!0, |crc, &byte| {
+        table[usize::from(crc as u8 ^ byte)] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default, Debug, PartialEq)]
+    struct Example {
+        flag: bool,
+        word: u16,
+        table: [i32; 3],
+        list: Vec<u8>,
+        latch: Option<u8>,
+    }
+
+    save_state! { Example { flag, word, table, list, latch } }
+
+    #[test]
+    fn round_trip() {
+        let example = Example {
+            flag: true,
+            word: 0x1234,
+            table: [-1, 2, -3],
+            list: vec![4, 5],
+            latch: Some(6),
+        };
+
+        let mut state = Vec::new();
+        example.save(&mut state);
+
+        let mut loaded = Example::default();
+        let mut reader = &state[..];
+        loaded.load(&mut reader).unwrap();
+        assert_eq!(loaded, example);
+        assert!(reader.is_empty());
+
+        let mut reader = &state[..state.len() - 1];
+        assert_eq!(loaded.load(&mut reader), Err(StateError::TooShort));
+    }
+
+    #[test]
+    fn header() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let mut state = Vec::new();
+        write_header(&mut state, 0xdead_beef);
+        assert_eq!(read_header(&mut &state[..], 0xdead_beef), Ok(()));
+        assert_eq!(read_header(&mut &state[..], 0), Err(StateError::WrongRom));
+        assert_eq!(read_header(&mut &b"SNES"[..], 0), Err(StateError::InvalidSignature));
+    }
+}
@@ -0,0 +1,120 @@
+//! Names given to addresses, for debugging.
+//!
+//! Symbols are read from the files assemblers write next to the ROM:
+//!
+//! - WLA-DX (`wlalink -S`) and bass (`-sym`) list labels as `bank:address name` under `[labels]`.
+//!   The bank is taken as the CPU bank, which holds for LoROM images.
+//! - ca65 (`ld65 -Ln`) writes VICE label files, with lines like `al 008000 .name`.
+
+use std::collections::{BTreeMap, HashMap};
+
+/// A table of labels and the 24-bit addresses they stand for.
+#[derive(Default)]
+pub struct Symbols {
+    labels: BTreeMap<u32, String>,
+    addresses: HashMap<String, u32>,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    /// Read the labels in a WLA-DX, bass or ca65 symbol file. Constants, comments and lines that
+    /// can't be read are skipped.
+    pub fn parse(text: &str) -> Symbols {
+        let mut symbols = Symbols::new();
+
+        // Files from older versions of WLA-DX have no sections
+        let mut in_labels = true;
+
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.starts_with('[') {
+                in_labels = line == "[labels]";
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let label = match fields.as_slice() {
+                ["al", address, name] => u32::from_str_radix(address, 16)
+                    .ok()
+                    .map(|address| (address, name.trim_start_matches('.'))),
+                [address, name] if in_labels => address
+                    .split_once(':')
+                    .and_then(|(bank, address)| {
+                        let bank = u32::from_str_radix(bank, 16).ok()?;
+                        let address = u32::from_str_radix(address, 16).ok()?;
+                        Some((bank << 16 | address) & 0xffffff)
+                    })
+                    .map(|address| (address, *name)),
+                _ => None,
+            };
+
+            if let Some((address, name)) = label {
+                symbols.insert(name, address);
+            }
+        }
+
+        symbols
+    }
+
+    /// Name an address. The first name given to an address is the one it is shown with.
+    pub fn insert(&mut self, name: &str, address: u32) {
+        self.labels
+            .entry(address)
+            .or_insert_with(|| name.to_string());
+        self.addresses.insert(name.to_string(), address);
+    }
+
+    /// The address of a label.
+    pub fn address(&self, name: &str) -> Option<u32> {
+        self.addresses.get(name).copied()
+    }
+
+    /// The label at an address, if there is one.
+    pub fn label(&self, address: u32) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let wla = "\
+; wla symbolic information file
+[information]
+version 3
+
+[labels]
+0000:8000 Main
+00:8005 Main@loop ; a child label
+7e:0010 frame_counter
+
+[definitions]
+00000010 SPRITES
+";
+        let symbols = Symbols::parse(wla);
+        assert_eq!(symbols.len(), 3);
+        assert_eq!(symbols.address("Main"), Some(0x008000));
+        assert_eq!(symbols.label(0x008005), Some("Main@loop"));
+        assert_eq!(symbols.address("frame_counter"), Some(0x7e0010));
+        assert_eq!(symbols.address("SPRITES"), None);
+
+        let vice = "al 008000 .reset\nal 7E0010 .frame_counter\n";
+        let symbols = Symbols::parse(vice);
+        assert_eq!(symbols.label(0x008000), Some("reset"));
+        assert_eq!(symbols.address("frame_counter"), Some(0x7e0010));
+    }
+}
@@ -0,0 +1,388 @@
+//! See https://wiki.superfamicom.org/timing
+
+use crate::state::*;
+use crate::video::Video;
+
+/// Master clock cycles per scanline, 340 dots of 4 cycles each (ignoring the two long dots).
+pub const CYCLES_PER_LINE: u32 = 1364;
+
+/// Master clock cycles per dot.
+const CYCLES_PER_DOT: u32 = 4;
+
+/// H-blank begins at dot 274.
+const HBLANK_START: u32 = 274 * CYCLES_PER_DOT;
+
+/// Master clock cycles the automatic controller read keeps HVBJOY's busy flag set for.
+const AUTO_JOYPAD_CYCLES: u32 = 4224;
+
+/// The video standard the system runs in. PAL consoles have a slower master clock and draw more
+/// scanlines, giving 50 frames per second instead of 60.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Region {
+    Ntsc,
+    Pal,
+}
+
+impl SaveState for Region {
+    fn save(&self, state: &mut Vec<u8>) {
+        (*self == Region::Pal).save(state);
+    }
+
+    fn load(&mut self, state: &mut &[u8]) -> Result<(), StateError> {
+        let mut pal = false;
+        pal.load(state)?;
+        *self = if pal { Region::Pal } else { Region::Ntsc };
+        Ok(())
+    }
+}
+
+impl Region {
+    /// Master clock frequency in Hz.
+    pub fn master_clock(self) -> u32 {
+        match self {
+            Region::Ntsc => 21_477_272,
+            Region::Pal => 21_281_370,
+        }
+    }
+
+    /// Scanlines per frame, one more in the first field of an interlaced frame.
+    pub fn lines_per_frame(self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal => 312,
+        }
+    }
+
+    /// Frames per second of a progressive picture.
+    pub fn frame_rate(self) -> f64 {
+        let cycles_per_frame = CYCLES_PER_LINE * u32::from(self.lines_per_frame());
+        f64::from(self.master_clock()) / f64::from(cycles_per_frame)
+    }
+}
+
+/// The position of the beam, and the interrupts and registers that depend on it.
+pub struct Timing {
+    pub(crate) region: Region,
+
+    /// Master clock cycles since the scanline began
+    h_cycle: u32,
+    v_counter: u16,
+
+    /// Toggled every frame, selects the lines drawn when interlaced
+    field: bool,
+
+    /// The number of frames started since power on
+    frame: u64,
+
+    /// NMITIMEN: NMI, H/V IRQ and auto joypad enable
+    interrupt_enable: u8,
+
+    /// HTIME and VTIME: position of the H/V IRQ
+    h_time: u16,
+    v_time: u16,
+
+    /// RDNMI: set when V-blank begins
+    nmi_flag: bool,
+
+    /// An NMI has been raised but not yet serviced by the CPU
+    nmi_pending: bool,
+
+    /// TIMEUP: set when the H/V IRQ fires, keeps the IRQ line asserted until read
+    irq_flag: bool,
+
+    /// OPHCT and OPVCT, latched through SLHV
+    latched_h: u16,
+    latched_v: u16,
+    latch_flag: bool,
+    latched_h_high: bool,
+    latched_v_high: bool,
+}
+
+save_state! {
+    Timing {
+        region, h_cycle, v_counter, field, frame, interrupt_enable, h_time, v_time, nmi_flag,
+        nmi_pending, irq_flag, latched_h, latched_v, latch_flag, latched_h_high, latched_v_high
+    }
+}
+
+/// Something the rest of the system has to respond to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum Event {
+    /// H-blank began
+    HBlank,
+
+    /// V-blank began
+    VBlank,
+
+    /// V-blank ended and a new frame began
+    Frame,
+}
+
+impl Timing {
+    pub(crate) fn new() -> Timing {
+        Timing {
+            region: Region::Ntsc,
+            h_cycle: 0,
+            v_counter: 0,
+            field: false,
+            frame: 0,
+            interrupt_enable: 0,
+            h_time: 0x1ff,
+            v_time: 0x1ff,
+            nmi_flag: false,
+            nmi_pending: false,
+            irq_flag: false,
+            latched_h: 0,
+            latched_v: 0,
+            latch_flag: false,
+            latched_h_high: false,
+            latched_v_high: false,
+        }
+    }
+
+    /// The horizontal counter, in dots.
+    pub fn h_counter(&self) -> u16 {
+        (self.h_cycle / CYCLES_PER_DOT) as u16
+    }
+
+    pub fn v_counter(&self) -> u16 {
+        self.v_counter
+    }
+
+    pub fn field(&self) -> bool {
+        self.field
+    }
+
+    /// The number of frames started since power on.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    fn lines_per_frame(&self, video: &Video) -> u16 {
+        let lines = self.region.lines_per_frame();
+        if video.interlace() && !self.field {
+            lines + 1
+        } else {
+            lines
+        }
+    }
+
+    fn in_vblank(&self, video: &Video) -> bool {
+        self.v_counter > video.visible_lines()
+    }
+
+    fn in_hblank(&self) -> bool {
+        self.h_cycle >= HBLANK_START || self.h_cycle < CYCLES_PER_DOT
+    }
+
+    /// Whether the controllers are read automatically at the start of V-blank.
+    pub(crate) fn auto_joypad(&self) -> bool {
+        self.interrupt_enable & 0x01 != 0
+    }
+
+    /// Whether the automatic controller read is still going on. It takes about three scanlines
+    /// from the start of V-blank.
+    fn auto_joypad_busy(&self, video: &Video) -> bool {
+        if !self.auto_joypad() || !self.in_vblank(video) {
+            return false;
+        }
+
+        let lines = u32::from(self.v_counter - video.visible_lines() - 1);
+        lines * CYCLES_PER_LINE + self.h_cycle < AUTO_JOYPAD_CYCLES
+    }
+
+    // ========== //
+    // Interrupts //
+    // ========== //
+
+    /// Take a pending NMI, if there is one.
+    pub(crate) fn take_nmi(&mut self) -> bool {
+        let pending = self.nmi_pending;
+        self.nmi_pending = false;
+        pending
+    }
+
+    /// Is the IRQ line asserted?
+    pub(crate) fn irq(&self) -> bool {
+        self.irq_flag
+    }
+
+    /// The point on the current line, in master cycles, where the H/V IRQ fires.
+    fn irq_position(&self) -> Option<u32> {
+        let h_position = u32::from(self.h_time) * CYCLES_PER_DOT;
+        let on_line = self.v_counter == self.v_time;
+
+        match (self.interrupt_enable >> 4) & 0x03 {
+            1 => Some(h_position),
+            2 if on_line => Some(0),
+            3 if on_line => Some(h_position),
+            _ => None,
+        }
+    }
+
+    fn check_irq(&mut self) {
+        if self.irq_position() == Some(self.h_cycle) {
+            self.irq_flag = true;
+        }
+    }
+
+    // ================== //
+    // Advancing the beam //
+    // ================== //
+
+    /// Advance the beam by at most `cycles` master cycles, stopping at the next event. Returns
+    /// the number of cycles that were advanced and the event that was reached, if any.
+    pub(crate) fn step(&mut self, cycles: u32, video: &Video) -> (u32, Option<Event>) {
+        let mut boundary = CYCLES_PER_LINE;
+        if self.h_cycle < HBLANK_START {
+            boundary = boundary.min(HBLANK_START);
+        }
+        if let Some(position) = self.irq_position() {
+            if self.h_cycle < position {
+                boundary = boundary.min(position);
+            }
+        }
+
+        let elapsed = cycles.min(boundary - self.h_cycle);
+        self.h_cycle += elapsed;
+
+        if self.h_cycle == CYCLES_PER_LINE {
+            return (elapsed, self.start_line(video));
+        }
+
+        self.check_irq();
+
+        if self.h_cycle == HBLANK_START {
+            (elapsed, Some(Event::HBlank))
+        } else {
+            (elapsed, None)
+        }
+    }
+
+    fn start_line(&mut self, video: &Video) -> Option<Event> {
+        self.h_cycle = 0;
+        self.v_counter += 1;
+
+        let event = if self.v_counter >= self.lines_per_frame(video) {
+            self.v_counter = 0;
+            self.field = !self.field;
+            self.frame += 1;
+            self.nmi_flag = false;
+            Some(Event::Frame)
+        } else if self.v_counter == video.visible_lines() + 1 {
+            self.nmi_flag = true;
+            if self.interrupt_enable & 0x80 != 0 {
+                self.nmi_pending = true;
+            }
+            Some(Event::VBlank)
+        } else {
+            None
+        };
+
+        self.check_irq();
+        event
+    }
+
+    // ===== //
+    // Ports //
+    // ===== //
+
+    /// Latch the H/V counters into OPHCT and OPVCT, through SLHV, WRIO or a light gun.
+    pub(crate) fn latch_counters(&mut self, h: u16, v: u16) {
+        self.latched_h = h;
+        self.latched_v = v;
+        self.latch_flag = true;
+    }
+
+    /// Read one of the timing registers. Some of them are PPU ports at $213x.
+    pub(crate) fn read_port(&mut self, addr: u16, video: &Video) -> u8 {
+        match addr {
+            // SLHV
+            0x2137 => {
+                self.latch_counters(self.h_counter(), self.v_counter);
+                0
+            }
+
+            // OPHCT
+            0x213C => {
+                let value = read_counter(self.latched_h, self.latched_h_high);
+                self.latched_h_high = !self.latched_h_high;
+                value
+            }
+
+            // OPVCT
+            0x213D => {
+                let value = read_counter(self.latched_v, self.latched_v_high);
+                self.latched_v_high = !self.latched_v_high;
+                value
+            }
+
+            // STAT78
+            0x213F => {
+                let pal = self.region == Region::Pal;
+                let value =
+                    (self.field as u8) << 7 | (self.latch_flag as u8) << 6 | (pal as u8) << 4 | 0x03;
+                self.latch_flag = false;
+                self.latched_h_high = false;
+                self.latched_v_high = false;
+                value
+            }
+
+            // RDNMI
+            0x4210 => {
+                let value = (self.nmi_flag as u8) << 7 | 0x02;
+                self.nmi_flag = false;
+                value
+            }
+
+            // TIMEUP
+            0x4211 => {
+                let value = (self.irq_flag as u8) << 7;
+                self.irq_flag = false;
+                value
+            }
+
+            // HVBJOY
+            0x4212 => {
+                (self.in_vblank(video) as u8) << 7
+                    | (self.in_hblank() as u8) << 6
+                    | self.auto_joypad_busy(video) as u8
+            }
+
+            // Write-only registers
+            _ => 0,
+        }
+    }
+
+    pub(crate) fn write_port(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4200 => {
+                // Enabling NMI during V-blank raises it immediately
+                if self.interrupt_enable & 0x80 == 0 && value & 0x80 != 0 && self.nmi_flag {
+                    self.nmi_pending = true;
+                }
+                if value & 0x30 == 0 {
+                    self.irq_flag = false;
+                }
+                self.interrupt_enable = value;
+            }
+
+            0x4207 => self.h_time = (self.h_time & 0x100) | u16::from(value),
+            0x4208 => self.h_time = (self.h_time & 0x0ff) | u16::from(value & 0x01) << 8,
+            0x4209 => self.v_time = (self.v_time & 0x100) | u16::from(value),
+            0x420A => self.v_time = (self.v_time & 0x0ff) | u16::from(value & 0x01) << 8,
+
+            // Read-only registers
+            _ => {}
+        }
+    }
+}
+
+/// The latched counters are read twice, first the low byte then the high bit.
+fn read_counter(value: u16, high: bool) -> u8 {
+    if high {
+        (value >> 8) as u8 & 0x01
+    } else {
+        value as u8
+    }
+}
@@ -0,0 +1,110 @@
+//! Trace logs: a line for every instruction executed, with the registers as they were before it.
+
+use crate::cpu::disassembler::{disassemble, instruction_size};
+use crate::*;
+use std::io::Write;
+
+impl<'a> Snes<'a> {
+    /// Write the instruction about to be executed to the trace log, if one is being written.
+    pub(crate) fn trace_instruction(&mut self) {
+        let output = match &mut self.trace {
+            Some(output) => output,
+            None => return,
+        };
+
+        let registers = &self.core.registers;
+        let address = registers.program_address();
+        let (m, x) = registers.register_sizes();
+
+        // Only the instruction's own bytes are read, the ones after it may be I/O or unmapped
+        let memory = &self.memory;
+        let peek = |offset: u16| {
+            let addr = (address as u16).wrapping_add(offset);
+            memory.peek((address >> 16) as u8, addr).unwrap_or(0)
+        };
+        let size = instruction_size(peek(0), m, x);
+        let bytes = [0, 1, 2, 3].map(|offset| {
+            if offset < size {
+                peek(offset.into())
+            } else {
+                0
+            }
+        });
+
+        let (text, size) = disassemble(bytes, address, m, x, &self.symbols);
+        let hex: Vec<String> = bytes[..usize::from(size)]
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+
+        let mut line = String::new();
+        if let Some(label) = self.symbols.label(address) {
+            line = format!("{}:\n", label);
+        }
+        line += &format!(
+            "{:02X}:{:04X}  {:<12} {:<20} {}",
+            address >> 16,
+            address & 0xffff,
+            hex.join(" "),
+            text,
+            registers
+        );
+
+        if let Err(error) = writeln!(output, "{}", line) {
+            warn!("Stopped writing the trace log: {}", error);
+            self.trace = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{build_rom, SharedWriter};
+
+    #[test]
+    fn trace_log() {
+        // lda #$80; loop: jmp loop
+        let rom = build_rom(&[(0x8000, &[0xa9, 0x80, 0x4c, 0x02, 0x80])]);
+        let mut snes = Snes::new(&rom);
+
+        let mut symbols = Symbols::new();
+        symbols.insert("loop", 0x008002);
+        snes.set_symbols(symbols);
+
+        let log = SharedWriter::default();
+        snes.set_trace(Some(Box::new(log.clone())));
+        snes.step();
+        snes.step();
+        snes.set_trace(None);
+        snes.step();
+
+        let log = String::from_utf8(log.contents()).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("00:8000  A9 80        LDA #$80             A=0000"));
+        assert_eq!(lines[1], "loop:");
+        assert!(lines[2].starts_with("00:8002  4C 02 80     JMP loop             A=0080"));
+    }
+
+    #[test]
+    fn end_of_ram() {
+        // jmp $1ffe, where lda #$80 is the last instruction before the unmapped $2000
+        let rom = build_rom(&[(0x8000, &[0x4c, 0xfe, 0x1f])]);
+        let mut snes = Snes::new(&rom);
+        snes.memory.set_byte(0x7e, 0x1ffe, 0xa9);
+        snes.memory.set_byte(0x7e, 0x1fff, 0x80);
+
+        let log = SharedWriter::default();
+        snes.set_trace(Some(Box::new(log.clone())));
+        snes.step();
+        snes.step();
+
+        let log = String::from_utf8(log.contents()).unwrap();
+        assert!(log
+            .lines()
+            .nth(1)
+            .unwrap()
+            .starts_with("00:1FFE  A9 80        LDA #$80"));
+    }
+}
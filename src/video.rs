@@ -0,0 +1,824 @@
+//! See https://wiki.superfamicom.org/backgrounds and https://wiki.superfamicom.org/sprites
+
+mod background;
+use background::*;
+
+mod sprites;
+use sprites::*;
+
+mod window;
+use window::*;
+
+mod compositor;
+use compositor::*;
+
+use crate::png::{write_png, write_png_rgba};
+use crate::state::{SaveState, StateError};
+use std::io::{self, Write};
+
+/// Width of the visible picture in pixels, doubled in hi-res modes
+pub const SCREEN_WIDTH: usize = 256;
+
+/// Height of the visible picture in scanlines, doubled when interlaced
+pub const SCREEN_HEIGHT: usize = 224;
+
+/// Height of the visible picture in scanlines with overscan enabled
+pub const OVERSCAN_HEIGHT: usize = 239;
+
+/// Layers are rendered in hi-res, two columns per pixel.
+const LINE_WIDTH: usize = 2 * SCREEN_WIDTH;
+
+const VRAM_WORDS: usize = 32 * 1024;
+const CGRAM_WORDS: usize = 256;
+
+/// Picture Processing Unit (ppu).
+pub struct Video {
+    memory: VideoRam,
+    cgram: ColorRam,
+    objects: Objects,
+    backgrounds: [Background; 4],
+    compositor: Compositor,
+
+    /// INIDISP: forced blank and master brightness
+    screen_display: u8,
+
+    /// BGMODE: background mode, BG3 priority and tile sizes
+    bg_mode: u8,
+
+    /// MOSAIC: mosaic size and the backgrounds it applies to
+    mosaic: u8,
+
+    /// SETINI: pseudo hi-res, overscan and interlace
+    screen_init: u8,
+
+    /// Toggled every frame, selects the lines drawn when interlaced
+    field: bool,
+
+    /// Shared latches for the write-twice scroll registers
+    bg_offset_latch: u8,
+    bg_h_offset_latch: u8,
+
+    /// VMAIN: address increment mode and address remapping
+    vram_increment: u8,
+    vram_address: u16,
+    vram_prefetch: u16,
+
+    cgram_address: u8,
+    cgram_latch: Option<u8>,
+    cgram_read_high: bool,
+
+    /// The frame being rendered, two rows per scanline and two columns per pixel
+    output: Vec<u16>,
+
+    /// A scanline in the frame being rendered was drawn in hi-res
+    output_hires: bool,
+
+    /// The last completed picture in BGR555, one `u16` per pixel
+    frame: Vec<u16>,
+    frame_width: usize,
+    frame_height: usize,
+}
+
+save_state! {
+    Video {
+        memory, cgram, objects, backgrounds, compositor, screen_display, bg_mode, mosaic,
+        screen_init, field, bg_offset_latch, bg_h_offset_latch, vram_increment, vram_address,
+        vram_prefetch, cgram_address, cgram_latch, cgram_read_high, output_hires
+    }
+}
+
+/// A complete picture, in BGR555 row by row.
+pub struct Frame<'a> {
+    /// 256, or 512 if any scanline was drawn in a hi-res mode
+    pub width: usize,
+
+    /// 224 or 239 depending on overscan, doubled when interlaced
+    pub height: usize,
+
+    pub pixels: &'a [u16],
+}
+
+/// A layout for the pixels of a `Framebuffer`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// 16 bits per pixel, little endian, with red in bits 10-14, green in 5-9 and blue in 0-4
+    Rgb555,
+
+    /// 4 bytes per pixel: red, green, blue and an opaque alpha
+    Rgba8888,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgb555 => 2,
+            PixelFormat::Rgba8888 => 4,
+        }
+    }
+}
+
+/// A copy of a picture in a common pixel format, row by row with no padding.
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub format: PixelFormat,
+    pub data: Vec<u8>,
+}
+
+impl Framebuffer {
+    /// Write the picture as a PNG file.
+    pub fn write_png<W: Write>(&self, writer: W) -> io::Result<()> {
+        match self.format {
+            PixelFormat::Rgba8888 => write_png_rgba(writer, self.width, self.height, &self.data),
+            PixelFormat::Rgb555 => {
+                let rgb: Vec<u8> = self
+                    .data
+                    .chunks(2)
+                    .flat_map(|pixel| {
+                        let color = u16::from_le_bytes([pixel[0], pixel[1]]);
+                        [
+                            widen(color >> 10 & 0x1f),
+                            widen(color >> 5 & 0x1f),
+                            widen(color & 0x1f),
+                        ]
+                    })
+                    .collect();
+                write_png(writer, self.width, self.height, &rgb)
+            }
+        }
+    }
+}
+
+impl Frame<'_> {
+    /// Copy the picture into a buffer of the given format.
+    pub fn to_framebuffer(&self, format: PixelFormat) -> Framebuffer {
+        let pixels = &self.pixels[..self.width * self.height];
+        let mut data = Vec::with_capacity(pixels.len() * format.bytes_per_pixel());
+        for &color in pixels {
+            // BGR555, with red in the low bits
+            let (red, green, blue) = (color & 0x1f, color >> 5 & 0x1f, color >> 10 & 0x1f);
+            match format {
+                PixelFormat::Rgb555 => {
+                    data.extend_from_slice(&(red << 10 | green << 5 | blue).to_le_bytes())
+                }
+                PixelFormat::Rgba8888 => {
+                    data.extend_from_slice(&[widen(red), widen(green), widen(blue), 0xff])
+                }
+            }
+        }
+
+        Framebuffer {
+            width: self.width,
+            height: self.height,
+            format,
+            data,
+        }
+    }
+}
+
+/// Widen a 5-bit channel to 8 bits, so that full intensity stays full.
+fn widen(channel: u16) -> u8 {
+    let channel = channel as u8;
+    channel << 3 | channel >> 2
+}
+
+/// Video RAM: 64 KB (VRAM), addressed in 16-bit words
+struct VideoRam {
+    data: [u16; VRAM_WORDS],
+}
+
+/// Color Generator RAM: 256 BGR555 palette entries (CGRAM)
+struct ColorRam {
+    data: [u16; CGRAM_WORDS],
+}
+
+save_state! { VideoRam { data } }
+save_state! { ColorRam { data } }
+
+/// A single pixel produced by a layer, before it is looked up in CGRAM.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct LayerPixel {
+    /// Index into CGRAM
+    color: u8,
+
+    /// Backgrounds use 0-1, sprites 0-3
+    priority: u8,
+
+    /// The palette the pixel was drawn with
+    palette: u8,
+}
+
+type LayerLine = [Option<LayerPixel>; LINE_WIDTH];
+
+/// The layers in the order of their bits in the screen designation and color math registers.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Layer {
+    Bg1 = 0,
+    Bg2 = 1,
+    Bg3 = 2,
+    Bg4 = 3,
+    Obj = 4,
+}
+
+impl VideoRam {
+    fn new() -> VideoRam {
+        VideoRam {
+            data: [0; VRAM_WORDS],
+        }
+    }
+
+    fn word(&self, addr: u16) -> u16 {
+        self.data[usize::from(addr) % VRAM_WORDS]
+    }
+
+    fn word_mut(&mut self, addr: u16) -> &mut u16 {
+        &mut self.data[usize::from(addr) % VRAM_WORDS]
+    }
+
+    /// Get the color index of a pixel within an 8x8 tile stored in planar format.
+    fn tile_pixel(&self, tile_address: u16, bits_per_pixel: u8, x: u16, y: u16) -> u8 {
+        let mut color = 0;
+
+        for pair in 0..u16::from(bits_per_pixel / 2) {
+            let planes = self.word(tile_address.wrapping_add(8 * pair + y));
+            let low = (planes >> (7 - x)) & 1;
+            let high = (planes >> (15 - x)) & 1;
+            color |= ((high << 1 | low) as u8) << (2 * pair);
+        }
+
+        color
+    }
+}
+
+impl Layer {
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl ColorRam {
+    fn new() -> ColorRam {
+        ColorRam {
+            data: [0; CGRAM_WORDS],
+        }
+    }
+}
+
+impl Video {
+    pub(crate) fn new() -> Video {
+        Video {
+            memory: VideoRam::new(),
+            cgram: ColorRam::new(),
+            objects: Objects::new(),
+            backgrounds: Default::default(),
+            compositor: Compositor::default(),
+            screen_display: 0x80,
+            bg_mode: 0,
+            mosaic: 0,
+            screen_init: 0,
+            field: false,
+            bg_offset_latch: 0,
+            bg_h_offset_latch: 0,
+            vram_increment: 0,
+            vram_address: 0,
+            vram_prefetch: 0,
+            cgram_address: 0,
+            cgram_latch: None,
+            cgram_read_high: false,
+            output: vec![0; LINE_WIDTH * 2 * OVERSCAN_HEIGHT],
+            output_hires: false,
+            frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_width: SCREEN_WIDTH,
+            frame_height: SCREEN_HEIGHT,
+        }
+    }
+
+    /// Save the last completed picture. It is kept apart from the rest of the PPU's state, so
+    /// that rewinding can leave it out. The scanlines being drawn aren't saved at all, as they
+    /// are drawn again before the next picture is completed.
+    pub(crate) fn save_picture(&self, state: &mut Vec<u8>) {
+        self.frame.save(state);
+        self.frame_width.save(state);
+        self.frame_height.save(state);
+    }
+
+    pub(crate) fn load_picture(&mut self, state: &mut &[u8]) -> Result<(), StateError> {
+        self.frame.load(state)?;
+        self.frame_width.load(state)?;
+        self.frame_height.load(state)
+    }
+
+    /// The last completed picture.
+    pub fn frame(&self) -> Frame<'_> {
+        Frame {
+            width: self.frame_width,
+            height: self.frame_height,
+            pixels: &self.frame,
+        }
+    }
+
+    /// Read from one of the PPU ports at $2100-$213F.
+    pub(crate) fn read_port(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x2138 => self.objects.read_data(),
+
+            0x2139 => {
+                let value = self.vram_prefetch as u8;
+                if self.vram_increment & 0x80 == 0 {
+                    self.increment_vram_address();
+                }
+                value
+            }
+            0x213A => {
+                let value = (self.vram_prefetch >> 8) as u8;
+                if self.vram_increment & 0x80 != 0 {
+                    self.increment_vram_address();
+                }
+                value
+            }
+
+            0x213B => {
+                let color = self.cgram.data[usize::from(self.cgram_address)];
+                let value = if self.cgram_read_high {
+                    self.cgram_address = self.cgram_address.wrapping_add(1);
+                    (color >> 8) as u8 & 0x7f
+                } else {
+                    color as u8
+                };
+                self.cgram_read_high = !self.cgram_read_high;
+                value
+            }
+
+            0x213E => self.objects.status(),
+
+            // Write-only registers
+            _ => 0,
+        }
+    }
+
+    /// Write to one of the PPU ports at $2100-$213F.
+    pub(crate) fn write_port(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x2100 => self.screen_display = value,
+
+            0x2101 => self.objects.select = value,
+            0x2102 => self.objects.write_address_low(value),
+            0x2103 => self.objects.write_address_high(value),
+            0x2104 => self.objects.write_data(value),
+
+            0x2105 => self.bg_mode = value,
+            0x2106 => self.mosaic = value,
+
+            0x2107..=0x210A => self.backgrounds[usize::from(addr - 0x2107)].screen = value,
+            0x210B => {
+                self.backgrounds[0].char_base = u16::from(value & 0x0f) << 12;
+                self.backgrounds[1].char_base = u16::from(value >> 4) << 12;
+            }
+            0x210C => {
+                self.backgrounds[2].char_base = u16::from(value & 0x0f) << 12;
+                self.backgrounds[3].char_base = u16::from(value >> 4) << 12;
+            }
+
+            0x210D..=0x2114 => {
+                let background = &mut self.backgrounds[usize::from(addr - 0x210D) / 2];
+                let current = u16::from(value) << 8;
+                let previous = u16::from(self.bg_offset_latch);
+
+                // Horizontal offsets are at odd addresses
+                if addr & 1 != 0 {
+                    let fine = u16::from(self.bg_h_offset_latch & 7);
+                    background.h_offset = (current | (previous & !7) | fine) & 0x3ff;
+                    self.bg_h_offset_latch = value;
+                } else {
+                    background.v_offset = (current | previous) & 0x3ff;
+                }
+
+                self.bg_offset_latch = value;
+            }
+
+            0x2115 => self.vram_increment = value,
+            0x2116 => {
+                self.vram_address = (self.vram_address & 0xff00) | u16::from(value);
+                self.prefetch_vram();
+            }
+            0x2117 => {
+                self.vram_address = (self.vram_address & 0x00ff) | u16::from(value) << 8;
+                self.prefetch_vram();
+            }
+            0x2118 => {
+                let word = self.memory.word_mut(self.remapped_vram_address());
+                *word = (*word & 0xff00) | u16::from(value);
+                if self.vram_increment & 0x80 == 0 {
+                    self.vram_address = self.vram_address.wrapping_add(self.vram_step());
+                }
+            }
+            0x2119 => {
+                let word = self.memory.word_mut(self.remapped_vram_address());
+                *word = (*word & 0x00ff) | u16::from(value) << 8;
+                if self.vram_increment & 0x80 != 0 {
+                    self.vram_address = self.vram_address.wrapping_add(self.vram_step());
+                }
+            }
+
+            0x2123..=0x2125 => self.compositor.windows.select[usize::from(addr - 0x2123)] = value,
+            0x2126 => self.compositor.windows.left[0] = value,
+            0x2127 => self.compositor.windows.right[0] = value,
+            0x2128 => self.compositor.windows.left[1] = value,
+            0x2129 => self.compositor.windows.right[1] = value,
+            0x212A => self.compositor.windows.logic[0] = value,
+            0x212B => self.compositor.windows.logic[1] = value,
+
+            0x212C => self.compositor.main_layers = value,
+            0x212D => self.compositor.sub_layers = value,
+            0x212E => self.compositor.main_window = value,
+            0x212F => self.compositor.sub_window = value,
+
+            0x2133 => self.screen_init = value,
+
+            0x2130 => self.compositor.color_window_select = value,
+            0x2131 => self.compositor.color_math = value,
+            0x2132 => self.compositor.write_fixed_color(value),
+
+            0x2121 => {
+                self.cgram_address = value;
+                self.cgram_latch = None;
+                self.cgram_read_high = false;
+            }
+            0x2122 => match self.cgram_latch.take() {
+                None => self.cgram_latch = Some(value),
+                Some(low) => {
+                    let color = u16::from_le_bytes([low, value & 0x7f]);
+                    self.cgram.data[usize::from(self.cgram_address)] = color;
+                    self.cgram_address = self.cgram_address.wrapping_add(1);
+                }
+            },
+
+            _ => log::trace!("Unhandled PPU write: ${:04x} = {:02x}", addr, value),
+        }
+    }
+
+    // ================ //
+    // VRAM port access //
+    // ================ //
+
+    fn vram_step(&self) -> u16 {
+        match self.vram_increment & 0x03 {
+            0 => 1,
+            1 => 32,
+            _ => 128,
+        }
+    }
+
+    /// Apply the address translation selected in VMAIN.
+    fn remapped_vram_address(&self) -> u16 {
+        let addr = self.vram_address;
+        match (self.vram_increment >> 2) & 0x03 {
+            0 => addr,
+            1 => (addr & 0xff00) | ((addr & 0x001f) << 3) | ((addr >> 5) & 7),
+            2 => (addr & 0xfe00) | ((addr & 0x003f) << 3) | ((addr >> 6) & 7),
+            _ => (addr & 0xfc00) | ((addr & 0x007f) << 3) | ((addr >> 7) & 7),
+        }
+    }
+
+    fn prefetch_vram(&mut self) {
+        self.vram_prefetch = self.memory.word(self.remapped_vram_address());
+    }
+
+    /// Reads return the prefetched word, then fetch the next one.
+    fn increment_vram_address(&mut self) {
+        self.prefetch_vram();
+        self.vram_address = self.vram_address.wrapping_add(self.vram_step());
+    }
+
+    // ========= //
+    // Rendering //
+    // ========= //
+
+    fn forced_blank(&self) -> bool {
+        self.screen_display & 0x80 != 0
+    }
+
+    pub(crate) fn interlace(&self) -> bool {
+        self.screen_init & 0x01 != 0
+    }
+
+    /// The number of scanlines drawn each frame.
+    pub(crate) fn visible_lines(&self) -> u16 {
+        if self.screen_init & 0x04 != 0 {
+            OVERSCAN_HEIGHT as u16
+        } else {
+            SCREEN_HEIGHT as u16
+        }
+    }
+
+    /// Called when the frame starts, at the end of V-blank. `field` selects the lines drawn when
+    /// interlaced.
+    pub(crate) fn start_frame(&mut self, field: bool) {
+        self.field = field;
+        self.output_hires = false;
+
+        if !self.forced_blank() {
+            self.objects.clear_status();
+        }
+    }
+
+    /// Called when V-blank begins, completes the frame.
+    pub(crate) fn start_vblank(&mut self) {
+        if !self.forced_blank() {
+            self.objects.reload_address();
+        }
+
+        let interlace = self.interlace();
+        let width = if self.output_hires {
+            LINE_WIDTH
+        } else {
+            SCREEN_WIDTH
+        };
+        let height = usize::from(self.visible_lines()) * if interlace { 2 } else { 1 };
+
+        self.frame.resize(width * height, 0);
+        for y in 0..height {
+            let row = if interlace { y } else { 2 * y };
+            for x in 0..width {
+                let column = if self.output_hires { x } else { 2 * x + 1 };
+                self.frame[y * width + x] = self.output[row * LINE_WIDTH + column];
+            }
+        }
+
+        self.frame_width = width;
+        self.frame_height = height;
+    }
+
+    /// Render a scanline into the frame. `line` is the value of the vertical counter, so the
+    /// first visible line is 1.
+    pub(crate) fn render_scanline(&mut self, line: u16) {
+        if line == 0 || line > self.visible_lines() {
+            return;
+        }
+
+        // Each scanline covers two rows of the output, interlaced frames draw one of them
+        let interlace = self.interlace();
+        let first_row = 2 * usize::from(line - 1);
+        let rows = if interlace {
+            let row = first_row + self.field as usize;
+            row..row + 1
+        } else {
+            first_row..first_row + 2
+        };
+
+        if self.forced_blank() {
+            for row in rows {
+                let row = row * LINE_WIDTH;
+                for pixel in &mut self.output[row..row + LINE_WIDTH] {
+                    *pixel = 0;
+                }
+            }
+            return;
+        }
+
+        let mode = self.bg_mode & 0x07;
+        let hires = mode == 5 || mode == 6;
+        let pseudo_hires = self.screen_init & 0x08 != 0;
+        self.output_hires |= hires || pseudo_hires;
+
+        let mosaic_size = u16::from(self.mosaic >> 4) + 1;
+
+        let mut layers = [[None; LINE_WIDTH]; 5];
+        for (index, background) in self.backgrounds.iter().enumerate() {
+            let depth = match bits_per_pixel(mode, index) {
+                Some(depth) => depth,
+                None => continue,
+            };
+
+            let mosaic = if self.mosaic & (1 << index) != 0 {
+                mosaic_size
+            } else {
+                1
+            };
+
+            // Mosaic blocks repeat the first line of each block, counted from the top
+            let line = line - (line - 1) % mosaic;
+
+            // Modes 5 and 6 draw different lines in each field when interlaced
+            let y = if hires && interlace {
+                2 * line + self.field as u16
+            } else {
+                line
+            };
+
+            let settings = LineSettings {
+                index,
+                mode,
+                bits_per_pixel: depth,
+                large_tiles: self.bg_mode & (0x10 << index) != 0,
+                hires,
+                mosaic,
+            };
+            layers[index] = background.render_line(&self.memory, &settings, y);
+        }
+
+        let object_field = if self.screen_init & 0x02 != 0 {
+            Some(self.field)
+        } else {
+            None
+        };
+        layers[Layer::Obj as usize] = self.objects.render_line(&self.memory, line, object_field);
+
+        let order = layer_order(mode, self.bg_mode & 0x08 != 0);
+        let direct_color = self.compositor.direct_color() && (mode == 3 || mode == 4);
+
+        let cgram = &self.cgram;
+        let color_of = |(layer, pixel): (Layer, LayerPixel)| {
+            if direct_color && layer == Layer::Bg1 {
+                compositor::direct_color(pixel.color, pixel.palette)
+            } else {
+                cgram.data[usize::from(pixel.color)]
+            }
+        };
+
+        for x in 0..SCREEN_WIDTH {
+            // In hi-res the sub screen is shown in the even columns and the main screen in the
+            // odd. Otherwise both columns are the same.
+            let windows = self.compositor.windows.masks(x as u8);
+            let main = self
+                .compositor
+                .front_pixel(Screen::Main, order, &layers, 2 * x + 1, windows);
+            let sub = self
+                .compositor
+                .front_pixel(Screen::Sub, order, &layers, 2 * x, windows);
+
+            let main_color = main.map(color_of).unwrap_or(cgram.data[0]);
+            let sub_color = sub.map(color_of);
+
+            let main_layer = main.map(|(layer, pixel)| (layer, pixel.palette));
+            let color = self
+                .compositor
+                .blend(main_layer, main_color, sub_color, windows);
+
+            let even = if hires || pseudo_hires {
+                sub_color.unwrap_or(cgram.data[0])
+            } else {
+                color
+            };
+
+            let even = self.apply_brightness(even);
+            let odd = self.apply_brightness(color);
+            for row in rows.clone() {
+                let pixel = row * LINE_WIDTH + 2 * x;
+                self.output[pixel] = even;
+                self.output[pixel + 1] = odd;
+            }
+        }
+    }
+
+    /// Scale a BGR555 color by the master brightness in INIDISP.
+    fn apply_brightness(&self, color: u16) -> u16 {
+        let brightness = u16::from(self.screen_display & 0x0f);
+        let scale = |channel: u16| (channel & 0x1f) * brightness / 15;
+
+        scale(color) | scale(color >> 5) << 5 | scale(color >> 10) << 10
+    }
+}
+
+/// The layers of each background mode, from front to back, together with the priority the layer's
+/// pixels must have to be drawn at that depth.
+fn layer_order(mode: u8, bg3_priority: bool) -> &'static [(Layer, u8)] {
+    use Layer::*;
+    match mode {
+        0 => &[
+            (Obj, 3),
+            (Bg1, 1),
+            (Bg2, 1),
+            (Obj, 2),
+            (Bg1, 0),
+            (Bg2, 0),
+            (Obj, 1),
+            (Bg3, 1),
+            (Bg4, 1),
+            (Obj, 0),
+            (Bg3, 0),
+            (Bg4, 0),
+        ],
+        1 if bg3_priority => &[
+            (Bg3, 1),
+            (Obj, 3),
+            (Bg1, 1),
+            (Bg2, 1),
+            (Obj, 2),
+            (Bg1, 0),
+            (Bg2, 0),
+            (Obj, 1),
+            (Obj, 0),
+            (Bg3, 0),
+        ],
+        1 => &[
+            (Obj, 3),
+            (Bg1, 1),
+            (Bg2, 1),
+            (Obj, 2),
+            (Bg1, 0),
+            (Bg2, 0),
+            (Obj, 1),
+            (Bg3, 1),
+            (Obj, 0),
+            (Bg3, 0),
+        ],
+        2..=6 => &[
+            (Obj, 3),
+            (Bg1, 1),
+            (Obj, 2),
+            (Bg2, 1),
+            (Obj, 1),
+            (Bg1, 0),
+            (Obj, 0),
+            (Bg2, 0),
+        ],
+        _ => &[(Obj, 3), (Obj, 2), (Obj, 1), (Obj, 0)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A PPU showing BG1 in the given mode, with tile 0 filling the screen. Every column of tile 0
+    /// uses a different color.
+    fn video_with_background(mode: u8) -> Video {
+        let mut video = Video::new();
+        video.write_port(0x2100, 0x0f);
+        video.write_port(0x2105, mode);
+        video.write_port(0x2107, 0x10);
+        video.write_port(0x212C, 0x01);
+
+        // Tile 0, stored at word address 0, bit planes 0 and 1 count up from the left
+        for row in 0..8 {
+            *video.memory.word_mut(row) = 0x3355;
+        }
+        for color in 0..16u16 {
+            video.cgram.data[usize::from(color)] = color + 1;
+        }
+
+        video
+    }
+
+    fn render_frame(video: &mut Video) {
+        video.start_frame(!video.field);
+        for line in 1..=video.visible_lines() {
+            video.render_scanline(line);
+        }
+        video.start_vblank();
+    }
+
+    #[test]
+    fn resolution() {
+        let mut video = video_with_background(1);
+        render_frame(&mut video);
+        assert_eq!((video.frame().width, video.frame().height), (256, 224));
+
+        video.write_port(0x2105, 5);
+        video.write_port(0x2133, 0x05);
+        render_frame(&mut video);
+        assert_eq!((video.frame().width, video.frame().height), (512, 478));
+
+        video.write_port(0x2105, 1);
+        video.write_port(0x2133, 0x08);
+        render_frame(&mut video);
+        assert_eq!((video.frame().width, video.frame().height), (512, 224));
+    }
+
+    #[test]
+    fn mosaic() {
+        let mut video = video_with_background(1);
+        render_frame(&mut video);
+        let colors = video.frame().pixels[..4].to_vec();
+        assert_ne!(colors[0], colors[1]);
+
+        video.write_port(0x2106, 0x11);
+        render_frame(&mut video);
+        let pixels = video.frame().pixels;
+        assert_eq!(pixels[..4], [colors[0], colors[0], colors[2], colors[2]]);
+    }
+
+    #[test]
+    fn framebuffer() {
+        let mut video = video_with_background(1);
+        // The backdrop white, then pure red and pure blue
+        video.cgram.data[0] = 0x7fff;
+        video.cgram.data[1] = 0x001f;
+        video.cgram.data[2] = 0x7c00;
+        render_frame(&mut video);
+
+        let frame = video.frame();
+        let rgb555 = frame.to_framebuffer(PixelFormat::Rgb555);
+        assert_eq!((rgb555.width, rgb555.height), (256, 224));
+        assert_eq!(rgb555.data.len(), 2 * 256 * 224);
+        assert_eq!(&rgb555.data[..6], &[0xff, 0x7f, 0x00, 0x7c, 0x1f, 0x00]);
+
+        let rgba = frame.to_framebuffer(PixelFormat::Rgba8888);
+        assert_eq!(rgba.data.len(), 4 * 256 * 224);
+        assert_eq!(
+            &rgba.data[..12],
+            &[255, 255, 255, 255, 255, 0, 0, 255, 0, 0, 255, 255]
+        );
+    }
+}
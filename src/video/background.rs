@@ -0,0 +1,135 @@
+use super::*;
+
+/// One of the four background layers, BG1-BG4.
+#[derive(Default)]
+pub(super) struct Background {
+    /// BGnSC: tilemap base address and size
+    pub screen: u8,
+
+    /// Base word address of the tiles' character data
+    pub char_base: u16,
+
+    pub h_offset: u16,
+    pub v_offset: u16,
+}
+
+save_state! { Background { screen, char_base, h_offset, v_offset } }
+
+/// How a background is drawn on a scanline.
+pub(super) struct LineSettings {
+    /// 0 for BG1 through 3 for BG4
+    pub index: usize,
+    pub mode: u8,
+    pub bits_per_pixel: u8,
+    pub large_tiles: bool,
+    pub hires: bool,
+
+    /// The width of mosaic blocks, 1 if mosaic is disabled
+    pub mosaic: u16,
+}
+
+/// The color depth of a background in a background mode, `None` if the background isn't drawn.
+pub(super) fn bits_per_pixel(mode: u8, background: usize) -> Option<u8> {
+    match (mode, background) {
+        (0, _) => Some(2),
+        (1, 0) | (1, 1) => Some(4),
+        (1, 2) => Some(2),
+        (2, 0) | (2, 1) => Some(4),
+        (3, 0) => Some(8),
+        (3, 1) => Some(4),
+        (4, 0) => Some(8),
+        (4, 1) => Some(2),
+        (5, 0) => Some(4),
+        (5, 1) => Some(2),
+        (6, 0) => Some(4),
+        _ => None,
+    }
+}
+
+impl Background {
+    /// Render the background's pixels at the vertical position `y`, before scrolling.
+    pub fn render_line(&self, vram: &VideoRam, settings: &LineSettings, y: u16) -> LayerLine {
+        let mut pixels = [None; LINE_WIDTH];
+
+        // Tiles are always 16 pixels wide in hi-res
+        let tile_height = if settings.large_tiles { 16 } else { 8 };
+        let tile_width = if settings.large_tiles || settings.hires {
+            16
+        } else {
+            8
+        };
+        let y = y.wrapping_add(self.v_offset);
+
+        for column in 0..LINE_WIDTH {
+            // Without hi-res each pixel covers two columns
+            if !settings.hires && column % 2 == 1 {
+                pixels[column] = pixels[column - 1];
+                continue;
+            }
+
+            let x = if settings.hires {
+                let x = column as u16;
+                (x - x % (2 * settings.mosaic)).wrapping_add(2 * self.h_offset)
+            } else {
+                let x = column as u16 / 2;
+                (x - x % settings.mosaic).wrapping_add(self.h_offset)
+            };
+
+            let entry = self.tilemap_entry(vram, x / tile_width, y / tile_height);
+            let palette = ((entry >> 10) & 7) as u8;
+            let priority = ((entry >> 13) & 1) as u8;
+
+            let mut tile_x = x % tile_width;
+            let mut tile_y = y % tile_height;
+            if entry & 0x4000 != 0 {
+                tile_x = tile_width - 1 - tile_x;
+            }
+            if entry & 0x8000 != 0 {
+                tile_y = tile_height - 1 - tile_y;
+            }
+
+            // Large tiles are made up of 8x8 tiles: N, N+1, N+16 and N+17
+            let tile = ((entry & 0x3ff) + tile_x / 8 + 16 * (tile_y / 8)) & 0x3ff;
+            let bits_per_pixel = settings.bits_per_pixel;
+            let words_per_tile = 4 * u16::from(bits_per_pixel);
+            let address = self.char_base.wrapping_add(tile * words_per_tile);
+
+            let color = vram.tile_pixel(address, bits_per_pixel, tile_x % 8, tile_y % 8);
+            if color == 0 {
+                continue;
+            }
+
+            let color = match bits_per_pixel {
+                2 if settings.mode == 0 => 32 * settings.index as u8 + 4 * palette + color,
+                2 => 4 * palette + color,
+                4 => 16 * palette + color,
+                _ => color,
+            };
+
+            pixels[column] = Some(LayerPixel {
+                color,
+                priority,
+                palette,
+            });
+        }
+
+        pixels
+    }
+
+    /// Get the tilemap entry of a tile. Tilemaps are made of up to four 32x32 screens.
+    fn tilemap_entry(&self, vram: &VideoRam, tile_x: u16, tile_y: u16) -> u16 {
+        let base = u16::from(self.screen & 0xfc) << 8;
+        let wide = self.screen & 0x01 != 0;
+        let tall = self.screen & 0x02 != 0;
+
+        let mut offset = ((tile_y & 31) << 5) | (tile_x & 31);
+        if wide && tile_x & 32 != 0 {
+            offset += 0x400;
+        }
+        if tall && tile_y & 32 != 0 {
+            offset += if wide { 0x800 } else { 0x400 };
+        }
+
+        vram.word(base.wrapping_add(offset))
+    }
+}
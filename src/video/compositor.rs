@@ -0,0 +1,216 @@
+use super::*;
+
+/// Combines the layers into the main and sub screens, and blends them with color math
+/// ($212C-$2132).
+#[derive(Default)]
+pub(super) struct Compositor {
+    pub windows: Windows,
+
+    /// TM and TS: the layers enabled on the main and sub screens
+    pub main_layers: u8,
+    pub sub_layers: u8,
+
+    /// TMW and TSW: the layers masked by their windows on the main and sub screens
+    pub main_window: u8,
+    pub sub_window: u8,
+
+    /// CGWSEL: clipping, color math prevention, sub screen addition and direct color
+    pub color_window_select: u8,
+
+    /// CGADSUB: add or subtract, halving and the layers color math applies to
+    pub color_math: u8,
+
+    /// COLDATA: the fixed color, also the backdrop of the sub screen
+    pub fixed_color: u16,
+}
+
+save_state! {
+    Compositor {
+        windows, main_layers, sub_layers, main_window, sub_window, color_window_select,
+        color_math, fixed_color
+    }
+}
+
+#[derive(Copy, Clone)]
+pub(super) enum Screen {
+    Main,
+    Sub,
+}
+
+/// The backdrop's bit in CGADSUB.
+const BACKDROP: u8 = 0x20;
+
+impl Compositor {
+    pub fn write_fixed_color(&mut self, value: u8) {
+        let intensity = u16::from(value & 0x1f);
+        for channel in 0..3 {
+            if value & (0x20 << channel) != 0 {
+                let shift = 5 * channel;
+                self.fixed_color = (self.fixed_color & !(0x1f << shift)) | intensity << shift;
+            }
+        }
+    }
+
+    pub fn direct_color(&self) -> bool {
+        self.color_window_select & 0x01 != 0
+    }
+
+    /// Find the frontmost visible pixel of a screen, `None` if only the backdrop is visible.
+    /// `windows` is the mask of windows containing the pixel, as returned by `Windows::masks`.
+    pub fn front_pixel(
+        &self,
+        screen: Screen,
+        order: &[(Layer, u8)],
+        layers: &[LayerLine; 5],
+        x: usize,
+        windows: u8,
+    ) -> Option<(Layer, LayerPixel)> {
+        let (enabled, masked) = match screen {
+            Screen::Main => (self.main_layers, self.main_window),
+            Screen::Sub => (self.sub_layers, self.sub_window),
+        };
+        let visible = enabled & !(masked & windows);
+
+        order.iter().find_map(|&(layer, priority)| {
+            if visible & layer.bit() == 0 {
+                return None;
+            }
+
+            layers[layer as usize][x]
+                .filter(|pixel| pixel.priority == priority)
+                .map(|pixel| (layer, pixel))
+        })
+    }
+
+    /// Apply clipping and color math to a main screen pixel. `main` is the layer and palette the
+    /// pixel came from, `None` for the backdrop. `sub` is `None` if the sub screen is transparent.
+    pub fn blend(
+        &self,
+        main: Option<(Layer, u8)>,
+        main_color: u16,
+        sub_color: Option<u16>,
+        windows: u8,
+    ) -> u16 {
+        let in_color_window = windows & (1 << COLOR_WINDOW) != 0;
+        let region = |setting: u8| match setting & 0x03 {
+            0 => false,
+            1 => !in_color_window,
+            2 => in_color_window,
+            _ => true,
+        };
+
+        let clipped = region(self.color_window_select >> 6);
+        let main_color = if clipped { 0 } else { main_color };
+
+        let layer_enabled = match main {
+            None => self.color_math & BACKDROP != 0,
+            // Only sprites with palettes 4-7 participate in color math
+            Some((Layer::Obj, palette)) => palette >= 4 && self.color_math & Layer::Obj.bit() != 0,
+            Some((layer, _)) => self.color_math & layer.bit() != 0,
+        };
+        if !layer_enabled || region(self.color_window_select >> 4) {
+            return main_color;
+        }
+
+        let add_sub_screen = self.color_window_select & 0x02 != 0;
+        let (operand, transparent) = match sub_color {
+            Some(color) if add_sub_screen => (color, false),
+            _ => (self.fixed_color, add_sub_screen),
+        };
+
+        let halve = self.color_math & 0x40 != 0 && !clipped && !transparent;
+        let subtract = self.color_math & 0x80 != 0;
+
+        let mut color = 0;
+        for shift in &[0, 5, 10] {
+            let a = (main_color >> shift) & 0x1f;
+            let b = (operand >> shift) & 0x1f;
+
+            let mut channel = if subtract {
+                a.saturating_sub(b)
+            } else {
+                a + b
+            };
+            if halve {
+                channel >>= 1;
+            }
+
+            color |= channel.min(0x1f) << shift;
+        }
+
+        color
+    }
+}
+
+/// Get the color of an 8-bit pixel in direct color mode, where the palette bits of the tilemap
+/// entry extend the color index.
+pub(super) fn direct_color(index: u8, palette: u8) -> u16 {
+    let (index, palette) = (u16::from(index), u16::from(palette));
+
+    let red = (index & 0x07) << 2 | (palette & 0x01) << 1;
+    let green = ((index >> 3) & 0x07) << 2 | (palette & 0x02);
+    let blue = ((index >> 6) & 0x03) << 3 | (palette & 0x04);
+
+    red | green << 5 | blue << 10
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: u16 = 0x7fff;
+    const RED: u16 = 0x001f;
+
+    #[test]
+    fn window_logic() {
+        // BG1 uses both windows, BG2 uses window 1 inverted
+        let mut windows = Windows {
+            select: [0x0a | 0x30, 0, 0],
+            left: [10, 20],
+            right: [30, 40],
+            logic: [0, 0],
+        };
+
+        windows.logic[0] = 0x01; // AND
+        assert!(!windows.contains(0, 15));
+        assert!(windows.contains(0, 25));
+
+        windows.logic[0] = 0x02; // XOR
+        assert!(windows.contains(0, 15));
+        assert!(!windows.contains(0, 25));
+
+        assert!(windows.contains(1, 5));
+        assert!(!windows.contains(1, 10));
+        assert!(!windows.contains(2, 15), "windows are disabled by default");
+    }
+
+    #[test]
+    fn color_math() {
+        let mut compositor = Compositor::default();
+        compositor.write_fixed_color(0xe0 | 0x10);
+        assert_eq!(compositor.fixed_color, 0x4210);
+
+        // Add the fixed color to BG1 with halving
+        compositor.color_math = 0x40 | 0x01;
+        let main = Some((Layer::Bg1, 0));
+        assert_eq!(compositor.blend(main, RED, None, 0), 0x2117);
+        assert_eq!(compositor.blend(Some((Layer::Bg2, 0)), RED, None, 0), RED);
+
+        // Subtract the sub screen, which is transparent here so the fixed color is used unhalved
+        compositor.color_math = 0xc0 | 0x01;
+        compositor.color_window_select = 0x02;
+        assert_eq!(compositor.blend(main, WHITE, None, 0), 0x3def);
+        assert_eq!(compositor.blend(main, WHITE, Some(RED), 0), 0x3de0);
+
+        // Sprites with palettes 0-3 never participate
+        compositor.color_math = 0x80 | 0x10;
+        assert_eq!(compositor.blend(Some((Layer::Obj, 3)), WHITE, Some(RED), 0), WHITE);
+        assert_eq!(compositor.blend(Some((Layer::Obj, 4)), WHITE, Some(RED), 0), 0x7fe0);
+
+        // Clip to black inside the color window
+        compositor.color_math = 0;
+        compositor.color_window_select = 0x80;
+        assert_eq!(compositor.blend(main, WHITE, None, 1 << COLOR_WINDOW), 0);
+        assert_eq!(compositor.blend(main, WHITE, None, 0), WHITE);
+    }
+}
@@ -0,0 +1,350 @@
+use super::*;
+
+/// 512 bytes of sprite attributes followed by a 32 byte high table.
+const OAM_SIZE: usize = 544;
+
+/// At most this many sprites are drawn on a single scanline.
+const SPRITES_PER_LINE: usize = 32;
+
+/// At most this many 8 pixel slivers of sprites are drawn on a single scanline.
+const TILES_PER_LINE: usize = 34;
+
+/// Object Attribute Memory (OAM) and the state of the sprite unit.
+pub(super) struct Objects {
+    oam: [u8; OAM_SIZE],
+
+    /// OBSEL: sprite sizes and character data addresses
+    pub select: u8,
+
+    /// OAMADD as last written. Bit 15 enables priority rotation.
+    address_reload: u16,
+
+    /// Internal byte address into OAM
+    address: u16,
+
+    /// Low byte latched by even writes to the low table
+    write_latch: u8,
+
+    /// More than 32 sprites were found on a scanline
+    range_over: bool,
+
+    /// More than 34 sprite tiles were found on a scanline
+    time_over: bool,
+}
+
+save_state! {
+    Objects { oam, select, address_reload, address, write_latch, range_over, time_over }
+}
+
+/// A decoded entry in OAM.
+#[derive(Debug)]
+struct Sprite {
+    x: i16,
+    y: u8,
+
+    /// 9-bit tile number
+    tile: u16,
+
+    palette: u8,
+    priority: u8,
+    flip_x: bool,
+    flip_y: bool,
+    large: bool,
+}
+
+impl Objects {
+    pub fn new() -> Objects {
+        Objects {
+            oam: [0; OAM_SIZE],
+            select: 0,
+            address_reload: 0,
+            address: 0,
+            write_latch: 0,
+            range_over: false,
+            time_over: false,
+        }
+    }
+
+    // ========= //
+    // OAM ports //
+    // ========= //
+
+    pub fn write_address_low(&mut self, value: u8) {
+        self.address_reload = (self.address_reload & 0xff00) | u16::from(value);
+        self.reload_address();
+    }
+
+    pub fn write_address_high(&mut self, value: u8) {
+        self.address_reload = (self.address_reload & 0x00ff) | u16::from(value) << 8;
+        self.reload_address();
+    }
+
+    /// Reset the internal address to the last written OAMADD.
+    pub fn reload_address(&mut self) {
+        self.address = (self.address_reload & 0x1ff) << 1;
+    }
+
+    /// Writes to the low table are buffered so that a whole word is written at once, the high
+    /// table is written directly.
+    pub fn write_data(&mut self, value: u8) {
+        let address = usize::from(self.address);
+
+        if address >= 0x200 {
+            self.oam[0x200 + (address & 0x1f)] = value;
+        } else if address % 2 == 0 {
+            self.write_latch = value;
+        } else {
+            self.oam[address - 1] = self.write_latch;
+            self.oam[address] = value;
+        }
+
+        self.address = (self.address + 1) & 0x3ff;
+    }
+
+    pub fn read_data(&mut self) -> u8 {
+        let address = usize::from(self.address);
+
+        let value = if address >= 0x200 {
+            self.oam[0x200 + (address & 0x1f)]
+        } else {
+            self.oam[address]
+        };
+
+        self.address = (self.address + 1) & 0x3ff;
+        value
+    }
+
+    /// The sprite bits of STAT77 ($213E), together with the PPU1 version number.
+    pub fn status(&self) -> u8 {
+        (self.time_over as u8) << 7 | (self.range_over as u8) << 6 | 0x01
+    }
+
+    pub fn clear_status(&mut self) {
+        self.range_over = false;
+        self.time_over = false;
+    }
+
+    // ========= //
+    // Rendering //
+    // ========= //
+
+    /// The small and large sprite sizes selected in OBSEL as (width, height).
+    fn sizes(&self) -> ((u16, u16), (u16, u16)) {
+        match self.select >> 5 {
+            0 => ((8, 8), (16, 16)),
+            1 => ((8, 8), (32, 32)),
+            2 => ((8, 8), (64, 64)),
+            3 => ((16, 16), (32, 32)),
+            4 => ((16, 16), (64, 64)),
+            5 => ((32, 32), (64, 64)),
+            6 => ((16, 32), (32, 64)),
+            _ => ((16, 32), (32, 32)),
+        }
+    }
+
+    /// Word address in VRAM of a tile in the sprite character data.
+    fn tile_address(&self, tile: u16) -> u16 {
+        let base = u16::from(self.select & 0x07) << 13;
+        let gap = (u16::from((self.select >> 3) & 0x03) + 1) << 12;
+
+        let address = if tile & 0x100 == 0 {
+            base + 16 * tile
+        } else {
+            base + gap + 16 * (tile & 0xff)
+        };
+
+        address & 0x7fff
+    }
+
+    fn sprite(&self, index: usize) -> Sprite {
+        let entry = &self.oam[4 * index..4 * index + 4];
+        let high = self.oam[0x200 + index / 4] >> (2 * (index % 4));
+
+        let x = u16::from(entry[0]) | u16::from(high & 0x01) << 8;
+        let attributes = entry[3];
+
+        Sprite {
+            // Sign extend from 9 bits
+            x: ((x << 7) as i16) >> 7,
+            y: entry[1],
+            tile: u16::from(entry[2]) | u16::from(attributes & 0x01) << 8,
+            palette: (attributes >> 1) & 0x07,
+            priority: (attributes >> 4) & 0x03,
+            flip_x: attributes & 0x40 != 0,
+            flip_y: attributes & 0x80 != 0,
+            large: high & 0x02 != 0,
+        }
+    }
+
+    /// Find the sprites on a scanline and draw them. Sets the range and time over flags if the
+    /// per-line limits are exceeded. With OBJ interlace, `field` selects which rows of the sprites
+    /// are drawn.
+    pub fn render_line(&mut self, vram: &VideoRam, line: u16, field: Option<bool>) -> LayerLine {
+        let mut pixels = [None; LINE_WIDTH];
+        let (small, large) = self.sizes();
+
+        // With priority rotation, evaluation begins at the sprite OAMADD points to
+        let first = if self.address_reload & 0x8000 != 0 {
+            usize::from(self.address_reload >> 1) & 0x7f
+        } else {
+            0
+        };
+
+        let mut in_range = Vec::with_capacity(SPRITES_PER_LINE);
+        for n in 0..128 {
+            let sprite = self.sprite((first + n) % 128);
+            let (width, height) = if sprite.large { large } else { small };
+
+            // Interlaced sprites cover half as many lines, with every other row in each field
+            let row = line.wrapping_sub(u16::from(sprite.y)) & 0xff;
+            let row = match field {
+                Some(field) => 2 * row + field as u16,
+                None => row,
+            };
+
+            let visible = (sprite.x > -(width as i16) && sprite.x < 256) || sprite.x == -256;
+            if row >= height || !visible {
+                continue;
+            }
+
+            if in_range.len() == SPRITES_PER_LINE {
+                self.range_over = true;
+                break;
+            }
+
+            in_range.push((sprite, width, height, row));
+        }
+
+        // Tiles are fetched starting with the last sprite found, so with too many tiles the
+        // first sprites are the ones that are dropped. Sprites found earlier are drawn on top.
+        let mut tiles = 0;
+        'fetch: for (sprite, width, height, row) in in_range.iter().rev() {
+            let row = if sprite.flip_y { height - 1 - row } else { *row };
+            let columns = width / 8;
+
+            for column in 0..columns {
+                let x = sprite.x + 8 * column as i16;
+                if x <= -8 || x >= SCREEN_WIDTH as i16 {
+                    continue;
+                }
+
+                if tiles == TILES_PER_LINE {
+                    self.time_over = true;
+                    break 'fetch;
+                }
+                tiles += 1;
+
+                let tile_column = if sprite.flip_x {
+                    columns - 1 - column
+                } else {
+                    column
+                };
+
+                // Large sprites are made of tiles in a 16x16 grid, wrapping within the grid
+                let tile = (sprite.tile & 0x100)
+                    | ((sprite.tile >> 4).wrapping_add(row / 8) & 0x0f) << 4
+                    | (sprite.tile.wrapping_add(tile_column) & 0x0f);
+                let address = self.tile_address(tile);
+
+                for pixel in 0..8 {
+                    let screen_x = x + pixel;
+                    if screen_x < 0 || screen_x >= SCREEN_WIDTH as i16 {
+                        continue;
+                    }
+
+                    let tile_x = if sprite.flip_x { 7 - pixel } else { pixel } as u16;
+                    let color = vram.tile_pixel(address, 4, tile_x, row % 8);
+                    if color == 0 {
+                        continue;
+                    }
+
+                    let pixel = Some(LayerPixel {
+                        color: 128 + 16 * sprite.palette + color,
+                        priority: sprite.priority,
+                        palette: sprite.palette,
+                    });
+                    pixels[2 * screen_x as usize] = pixel;
+                    pixels[2 * screen_x as usize + 1] = pixel;
+                }
+            }
+        }
+
+        pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Place an 8x8 sprite using tile 0 at the given position.
+    fn place_sprite(objects: &mut Objects, index: usize, x: u8, y: u8, attributes: u8) {
+        objects.oam[4 * index..4 * index + 4].copy_from_slice(&[x, y, 0, attributes]);
+    }
+
+    #[test]
+    fn range_over() {
+        let vram = VideoRam::new();
+        let mut objects = Objects::new();
+        for index in 0..128 {
+            place_sprite(&mut objects, index, 0, 0xf0, 0);
+        }
+        for index in 0..33 {
+            place_sprite(&mut objects, index, (8 * index) as u8, 10, 0);
+        }
+
+        objects.render_line(&vram, 10, None);
+        assert_eq!(objects.status() & 0xc0, 0x40);
+    }
+
+    #[test]
+    fn time_over() {
+        let vram = VideoRam::new();
+        let mut objects = Objects::new();
+        for index in 0..128 {
+            place_sprite(&mut objects, index, 0, 0xf0, 0);
+        }
+
+        // 16x16 sprites: 18 sprites of two tiles each
+        objects.select = 0x00;
+        for index in 0..18 {
+            place_sprite(&mut objects, index, 0, 10, 0);
+            objects.oam[0x200 + index / 4] |= 0x02 << (2 * (index % 4));
+        }
+
+        objects.render_line(&vram, 10, None);
+        assert_eq!(objects.status() & 0xc0, 0x80);
+
+        objects.clear_status();
+        place_sprite(&mut objects, 17, 0, 0xf0, 0);
+        objects.render_line(&vram, 10, None);
+        assert_eq!(objects.status() & 0xc0, 0x00);
+    }
+
+    #[test]
+    fn flip_and_priority() {
+        let mut vram = VideoRam::new();
+
+        // Tile 0: only the leftmost pixel of each row is set, color 1
+        for row in 0..8 {
+            *vram.word_mut(row) = 0x0080;
+        }
+
+        let mut objects = Objects::new();
+        for index in 0..128 {
+            place_sprite(&mut objects, index, 0, 0xf0, 0);
+        }
+        place_sprite(&mut objects, 0, 16, 0, 0x30 | 0x02);
+        place_sprite(&mut objects, 1, 9, 0, 0x40 | 0x04);
+        place_sprite(&mut objects, 2, 40, 0, 0x40 | 0x04);
+
+        let pixels = objects.render_line(&vram, 0, None);
+
+        let top = pixels[2 * 16].unwrap();
+        assert_eq!(top.color, 128 + 16 + 1, "lower OAM index is drawn on top");
+        assert_eq!(top.priority, 3);
+
+        assert_eq!(pixels[2 * 40], None);
+        assert_eq!(pixels[2 * 47 + 1].unwrap().color, 128 + 32 + 1);
+    }
+}
@@ -0,0 +1,63 @@
+/// The color window's index among the window settings, after BG1-4 and OBJ.
+pub(super) const COLOR_WINDOW: usize = 5;
+
+/// The two windows and how they mask each layer ($2123-$212B).
+#[derive(Default)]
+pub(super) struct Windows {
+    /// W12SEL, W34SEL and WOBJSEL: enable and invert bits, four per layer
+    pub select: [u8; 3],
+
+    /// WH0 and WH2
+    pub left: [u8; 2],
+
+    /// WH1 and WH3
+    pub right: [u8; 2],
+
+    /// WBGLOG and WOBJLOG: how the two windows are combined, two bits per layer
+    pub logic: [u8; 2],
+}
+
+save_state! { Windows { select, left, right, logic } }
+
+impl Windows {
+    /// Is the pixel at `x` inside the combined window of a layer? Layers are numbered as in
+    /// `Layer`, with the color window last.
+    pub fn contains(&self, layer: usize, x: u8) -> bool {
+        let settings = (self.select[layer / 2] >> (4 * (layer % 2))) & 0x0f;
+
+        let window = |n: usize| {
+            let enabled = settings & (0x02 << (2 * n)) != 0;
+            let invert = settings & (0x01 << (2 * n)) != 0;
+            let inside = self.left[n] <= x && x <= self.right[n];
+            if enabled {
+                Some(inside ^ invert)
+            } else {
+                None
+            }
+        };
+
+        let logic = match layer {
+            0..=3 => self.logic[0] >> (2 * layer),
+            4 => self.logic[1],
+            _ => self.logic[1] >> 2,
+        };
+
+        match (window(0), window(1)) {
+            (None, None) => false,
+            (Some(inside), None) | (None, Some(inside)) => inside,
+            (Some(first), Some(second)) => match logic & 0x03 {
+                0 => first | second,
+                1 => first & second,
+                2 => first ^ second,
+                _ => !(first ^ second),
+            },
+        }
+    }
+
+    /// A bit mask of the layers, and the color window, whose window contain the pixel at `x`.
+    pub fn masks(&self, x: u8) -> u8 {
+        (0..=COLOR_WINDOW).fold(0, |mask, layer| {
+            mask | (self.contains(layer, x) as u8) << layer
+        })
+    }
+}